@@ -1,14 +1,30 @@
-use anyhow::Result;
-use futures_util::StreamExt;
-use metrics_exporter_prometheus::PrometheusBuilder;
-use metrics_process::collector::collect;  // Import the collect function correctly
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::{FutureExt, StreamExt};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hmac::{Hmac, Mac};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use zbus::{Connection, zvariant};
-use chrono::Utc;
+use chrono::{DateTime, Local, Timelike, Utc};
+use std::collections::VecDeque;
+use std::convert::Infallible;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
 use tokio::signal::ctrl_c;
-use clap::{Parser, ValueEnum};
+use tokio::sync::{broadcast, mpsc};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 // Get the package name from Cargo.toml at compile time
 const PKG_NAME: &str = env!("CARGO_PKG_NAME");
@@ -26,33 +42,298 @@ const GIT_HASH: &str = match option_env!("GIT_HASH") {
 #[derive(Parser, Debug)]
 #[command(author, version, about = "GeoClue2 Prometheus Exporter")]
 struct Args {
+    /// Optional TOML configuration file; flags given on the command line take precedence over it
+    #[arg(short = 'c', long)]
+    config: Option<PathBuf>,
+
     /// Display version information
     #[arg(short, long)]
     version_info: bool,
 
+    /// Display version information as JSON
+    #[arg(long)]
+    version_json: bool,
+
+    /// Print Prometheus alerting rules for the exporter's metrics and exit
+    #[arg(long)]
+    print_alert_rules: bool,
+
+    /// Print every exported metric with its type and description and exit
+    #[arg(long)]
+    list_metrics: bool,
+
+    /// Validate the configuration, print the effective settings and exit without connecting to DBus
+    #[arg(long)]
+    check: bool,
+
+    /// Wait for a single location update, print it as JSON and exit without serving metrics
+    #[arg(long)]
+    once: bool,
+
+    /// With --once, seconds to wait for the location update before giving up
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
     /// Log level filter
     #[arg(short, long, default_value = "info")]
     log_level: LogLevel,
 
+    /// Log every GeoClue2 DBus call with its arguments, result and duration, regardless of --log-level
+    #[arg(long)]
+    trace_dbus: bool,
+
     /// Distance threshold in meters
     #[arg(short = 'd', long, default_value_t = 10)]
     distance_threshold: u32,
+
+    /// Ignore moves shorter than --distance-threshold in geoclue_distance_traveled_meters_total
+    #[arg(long)]
+    filter_distance_jitter: bool,
+
+    /// Leave moves longer than this many meters between successive fixes out of geoclue_distance_traveled_meters_total
+    #[arg(long, value_parser = parse_max_jump_meters)]
+    max_jump_meters: Option<f64>,
     
     /// Time threshold in seconds
     #[arg(short = 't', long, default_value_t = 30)]
     time_threshold: u32,
     
-    /// Accuracy level 
-    #[arg(short = 'a', long, default_value = "street")]
+    /// Accuracy level (none, country, city, neighborhood, street, exact or GeoClue2's numeric 0-8)
+    #[arg(short = 'a', long, default_value = "street", value_parser = parse_accuracy_level)]
     accuracy_level: AccuracyLevelArg,
+
+    /// Accuracy levels to step down through when no update arrives after Start (e.g. exact,street,city)
+    #[arg(long, value_delimiter = ',')]
+    accuracy_fallback: Vec<AccuracyLevelArg>,
+
+    /// Seconds without an update after which geoclue_data_available drops to 0 (0 = disabled)
+    #[arg(long, default_value_t = 0)]
+    data_timeout: u64,
+
+    /// Seconds to wait for the first update before moving to the next --accuracy-fallback level
+    #[arg(long, default_value_t = 60)]
+    accuracy_fallback_timeout: u64,
+
+    /// Run an additional client at this accuracy level; the most accurate reading becomes primary (repeatable)
+    #[arg(long = "extra-accuracy-level")]
+    extra_accuracy_levels: Vec<AccuracyLevelArg>,
     
     /// Prometheus metrics endpoint port
     #[arg(short = 'p', long, default_value_t = 9090)]
     metrics_port: u16,
     
-    /// Bind address for the metrics server (IPv4 or IPv6)
+    /// Bind address for the metrics server (IPv4, IPv6, a resolvable hostname, or
+    /// unix:PATH to serve on a Unix domain socket, in which case --metrics-port is ignored)
     #[arg(short = 'b', long, default_value = "127.0.0.1")]
     bind_address: String,
+
+    /// Fall back to the IPv4 equivalent of an IPv6 bind address when the host lacks IPv6
+    #[arg(long)]
+    ipv6_fallback: bool,
+
+    /// Only serve HTTP clients whose address is in this network, e.g. 10.0.0.0/8 (repeatable; default: all)
+    #[arg(long = "allow-cidr", value_parser = parse_cidr)]
+    allow_cidrs: Vec<ipnet::IpNet>,
+
+    /// Sign metrics responses with HMAC-SHA256 under this key, sent in the X-Metrics-Signature header
+    #[arg(long, value_parser = parse_hmac_key)]
+    hmac_key: Option<String>,
+
+    /// Accept --metrics-port 0 and bind an OS-assigned port, which is logged at startup
+    #[arg(long)]
+    allow_ephemeral_port: bool,
+
+    /// Prefix for the exporter's own operational metrics (reconnects, errors, HTTP, process) in place of geoclue_
+    #[arg(long)]
+    self_metrics_prefix: Option<String>,
+
+    /// HTTP path serving the Prometheus metrics
+    #[arg(long, default_value = "/metrics", value_parser = parse_metrics_path)]
+    metrics_path: String,
+
+    /// Log output format
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Bucket layout of the geoclue_accuracy_meters histogram: fixed bounds, or powers of two from 1m to 50km
+    #[arg(long, default_value = "linear")]
+    accuracy_bucket_scale: BucketScale,
+
+    /// Unit of the exported speed gauges: m/s as geoclue_speed, or km/h and mph as geoclue_speed_kmh and geoclue_speed_mph
+    #[arg(long, default_value = "mps")]
+    speed_unit: SpeedUnit,
+
+    /// Timezone used for log timestamps and hour-of-day bucketing
+    #[arg(long, default_value = "utc")]
+    log_timezone: LogTimezone,
+
+    /// Count location updates per hour of day (geoclue_updates_by_hour)
+    #[arg(long)]
+    track_hourly: bool,
+
+    /// Export geoclue_moved_since_last_scrape, comparing the position between successive scrapes
+    #[arg(long)]
+    track_scrape_movement: bool,
+
+    /// Graphite plaintext relay to push location values to (HOST:PORT)
+    #[arg(long)]
+    graphite_address: Option<String>,
+
+    /// Metric path prefix used for Graphite output
+    #[arg(long, default_value = "geoclue")]
+    graphite_prefix: String,
+
+    /// DBus service name of GeoClue2 (or a compatible implementation)
+    #[arg(long, default_value = "org.freedesktop.GeoClue2")]
+    service_name: String,
+
+    /// DBus object path of the GeoClue2 Manager
+    #[arg(long, default_value = "/org/freedesktop/GeoClue2/Manager")]
+    manager_path: String,
+
+    /// Prefix of the Manager, Client and Location interface names
+    #[arg(long, default_value = "org.freedesktop.GeoClue2")]
+    interface_prefix: String,
+
+    /// DesktopId reported to GeoClue2, matched by its agent and [app] access rules
+    #[arg(long, default_value = PKG_NAME, value_parser = parse_desktop_id)]
+    desktop_id: String,
+
+    /// Also export coordinates projected to this EPSG code (supported: 3857)
+    #[arg(long, value_parser = parse_epsg)]
+    project_to: Option<u32>,
+
+    /// Extra attempts for a location property read that fails within one update
+    #[arg(long, default_value_t = 1)]
+    property_read_retries: u32,
+
+    /// Seconds to keep retrying while the DBus system bus is not reachable yet (0 = try once)
+    #[arg(long, default_value_t = 30)]
+    connect_timeout: u64,
+
+    /// Longest delay in seconds between GeoClue2 reconnection attempts
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    max_backoff: u64,
+
+    /// Shut down cleanly and exit after this many updates with at least one accepted coordinate
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_updates: Option<u64>,
+
+    /// Reconnect to GeoClue2 after this many consecutive updates had every value rejected
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_consecutive_invalid: Option<u32>,
+
+    /// Leave geoclue_heading unchanged while moving slower than --heading-speed-threshold
+    #[arg(long)]
+    suppress_heading_when_slow: bool,
+
+    /// Speed in meters per second below which the heading is treated as noise
+    #[arg(long, default_value_t = 0.5)]
+    heading_speed_threshold: f64,
+
+    /// Also read the previous Location object to export the move GeoClue2 reports per update
+    #[arg(long)]
+    track_previous_location: bool,
+
+    /// Round latitude and longitude to this many decimal places (2 = ~1km), for privacy
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=15))]
+    coordinate_precision: Option<u8>,
+
+    /// Only export a location value within METRIC=MIN:MAX, e.g. heading=0:359.999 (repeatable)
+    #[arg(long = "valid-range", value_parser = parse_value_range)]
+    valid_ranges: Vec<ValueRange>,
+
+    /// Export this "LAT,LON" position until the first fix arrives, flagged by geoclue_using_default_position
+    #[arg(long, value_parser = parse_position, allow_hyphen_values = true)]
+    default_position: Option<(f64, f64)>,
+
+    /// Snap exported latitude/longitude to the centre of a grid cell of this size in meters
+    #[arg(long, value_parser = parse_grid_meters)]
+    snap_grid_meters: Option<f64>,
+
+    /// Also export an accuracy-weighted average of recent positions as geoclue_latitude/longitude_smoothed
+    #[arg(long)]
+    smooth_position: bool,
+
+    /// Number of recent fixes averaged by --smooth-position
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    smooth_window: u32,
+
+    /// Location metrics to neither describe nor export, by suffix (e.g. altitude,heading)
+    #[arg(long, value_delimiter = ',', value_parser = parse_location_metric)]
+    disable_metrics: Vec<String>,
+
+    /// Export geoclue_location_info with the current Location object path of each client
+    #[arg(long)]
+    export_location_info: bool,
+
+    /// Also export earth-centered, earth-fixed (ECEF) coordinates in meters
+    #[arg(long)]
+    export_ecef: bool,
+
+    /// Append one NDJSON object per processed location update to this file
+    #[arg(long)]
+    events_file: Option<PathBuf>,
+
+    /// Size in bytes at which the events file is rotated to <PATH>.1 (0 = never rotate)
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    events_file_max_bytes: u64,
+
+    /// Run this shell command on each processed update, with the location in GEOCLUE_* environment variables
+    #[arg(long)]
+    on_update_command: Option<String>,
+
+    /// Maximum number of --on-update-command invocations running at once; further updates are dropped
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    on_update_command_concurrency: u32,
+
+    /// Periodically write a JSON snapshot of internal state here, for diagnosing crashes
+    #[arg(long)]
+    state_dump_file: Option<PathBuf>,
+
+    /// Stream location updates as Server-Sent Events on /events
+    #[arg(long)]
+    enable_sse: bool,
+
+    /// Parse every rendered metrics page and count format errors (debugging aid)
+    #[arg(long)]
+    validate_output: bool,
+
+    /// Close HTTP connections that have not sent complete request headers within this many seconds (0 = no limit)
+    #[arg(long, default_value_t = 10)]
+    http_header_timeout: u64,
+
+    /// Close HTTP connections whose response is not written within this many seconds of the request (0 = no limit; /events is exempt)
+    #[arg(long, default_value_t = 30)]
+    http_request_timeout: u64,
+
+    /// Skip fixes reporting fewer satellites (only when the backend exposes a satellite count)
+    #[arg(long)]
+    min_satellites: Option<u32>,
+
+    /// Prometheus Pushgateway base URL to push the metrics page to, for hosts that cannot be scraped
+    #[arg(long)]
+    push_gateway: Option<String>,
+
+    /// Seconds between pushes to --push-gateway
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    push_interval: u64,
+
+    /// OTLP/HTTP metrics endpoint to export the location gauges to, e.g. http://localhost:4318/v1/metrics (needs the otlp feature)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Seconds between exports to --otlp-endpoint
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    otlp_interval: u64,
+
+    /// URL receiving a JSON notification (POST) when the exporter shuts down cleanly
+    #[arg(long)]
+    shutdown_webhook: Option<String>,
+
+    /// Seconds added to wall-clock time in *_timestamp_seconds gauges (for devices without NTP)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    clock_offset_seconds: f64,
 }
 
 // Generate a detailed version string including build information
@@ -61,19 +342,244 @@ fn get_version_string() -> String {
             PKG_NAME, PKG_VERSION, GIT_HASH)
 }
 
+// Generate version information as a JSON object for tooling
+fn get_version_json() -> String {
+    serde_json::json!({
+        "name": PKG_NAME,
+        "version": PKG_VERSION,
+        "git_hash": GIT_HASH,
+    }).to_string()
+}
+
+// Validate the --metrics-path argument
+fn parse_metrics_path(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err(format!("metrics path must start with '/': {}", value));
+    }
+
+    Ok(value.to_string())
+}
+
+// Validate the --desktop-id argument
+fn parse_desktop_id(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() {
+        return Err("desktop id must not be empty".to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+// Suffixes of the per-update location metrics (geoclue_<suffix>, geoclue_client_<suffix>)
+const LOCATION_METRICS: [&str; 6] = ["latitude", "longitude", "accuracy", "altitude", "speed", "heading"];
+
+// Validate a --disable-metrics entry against the location metric suffixes
+fn parse_location_metric(value: &str) -> Result<String, String> {
+    if LOCATION_METRICS.contains(&value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("unknown metric {} (expected one of {})", value, LOCATION_METRICS.join(", ")))
+    }
+}
+
+// Validate the --hmac-key argument
+fn parse_hmac_key(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err("HMAC key must not be empty".to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+// Parse an --allow-cidr network; a bare address allows just that host
+fn parse_cidr(value: &str) -> Result<ipnet::IpNet, String> {
+    value.parse::<ipnet::IpNet>()
+        .or_else(|_| value.parse::<std::net::IpAddr>().map(ipnet::IpNet::from))
+        .map_err(|_| format!("invalid CIDR: {}", value))
+}
+
+// Parse a --valid-range "METRIC=MIN:MAX" bound
+fn parse_value_range(value: &str) -> Result<ValueRange, String> {
+    let (metric, bounds) = value.split_once('=')
+        .ok_or_else(|| format!("invalid range: {} (expected METRIC=MIN:MAX)", value))?;
+    let metric = parse_location_metric(metric)?;
+    let (min, max) = bounds.split_once(':')
+        .ok_or_else(|| format!("invalid range: {} (expected METRIC=MIN:MAX)", value))?;
+    let min: f64 = min.parse().map_err(|_| format!("invalid minimum: {}", min))?;
+    let max: f64 = max.parse().map_err(|_| format!("invalid maximum: {}", max))?;
+
+    if !min.is_finite() || !max.is_finite() || min > max {
+        return Err(format!("range must have finite bounds with MIN <= MAX: {}", value));
+    }
+
+    Ok(ValueRange { metric, min, max })
+}
+
+// Parse a --default-position "LAT,LON" pair in degrees
+fn parse_position(value: &str) -> Result<(f64, f64), String> {
+    let (lat, lon) = value.split_once(',')
+        .ok_or_else(|| format!("invalid position: {} (expected LAT,LON)", value))?;
+    let lat: f64 = lat.trim().parse().map_err(|_| format!("invalid latitude: {}", lat.trim()))?;
+    let lon: f64 = lon.trim().parse().map_err(|_| format!("invalid longitude: {}", lon.trim()))?;
+
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(format!("latitude must be between -90 and 90: {}", lat));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("longitude must be between -180 and 180: {}", lon));
+    }
+
+    Ok((lat, lon))
+}
+
+// Validate the --max-jump-meters distance
+fn parse_max_jump_meters(value: &str) -> Result<f64, String> {
+    let meters: f64 = value.parse()
+        .map_err(|_| format!("invalid distance: {}", value))?;
+
+    if !meters.is_finite() || meters <= 0.0 {
+        return Err(format!("maximum jump must be a positive number of meters: {}", value));
+    }
+
+    Ok(meters)
+}
+
+// Validate the --snap-grid-meters cell size
+fn parse_grid_meters(value: &str) -> Result<f64, String> {
+    let meters: f64 = value.parse()
+        .map_err(|_| format!("invalid grid size: {}", value))?;
+
+    if !meters.is_finite() || meters <= 0.0 {
+        return Err(format!("grid size must be a positive number of meters: {}", value));
+    }
+
+    Ok(meters)
+}
+
+// EPSG codes supported by --project-to
+const SUPPORTED_EPSG_CODES: &[u32] = &[3857];
+
+// Parse an accuracy level by name or by its GeoClue2 numeric value. Values GeoClue2 does
+// not define (2, 3 and 7) are rounded down to the next defined level.
+fn parse_accuracy_level(value: &str) -> Result<AccuracyLevelArg, String> {
+    if let Ok(level) = AccuracyLevelArg::from_str(value, true) {
+        return Ok(level);
+    }
+
+    let number: u32 = value.parse()
+        .map_err(|_| format!("invalid accuracy level: {} (expected a level name or 0-8)", value))?;
+    let level = match number {
+        0 => AccuracyLevelArg::None,
+        1..=3 => AccuracyLevelArg::Country,
+        4 => AccuracyLevelArg::City,
+        5 => AccuracyLevelArg::Neighborhood,
+        6 | 7 => AccuracyLevelArg::Street,
+        8 => AccuracyLevelArg::Exact,
+        _ => return Err(format!("accuracy level {} is out of range (0-8)", number)),
+    };
+
+    Ok(level)
+}
+
+// The number given for an accuracy level when parse_accuracy_level had to round it down
+fn rounded_accuracy_level(value: &str) -> Option<u32> {
+    let number: u32 = value.parse().ok()?;
+    let level = parse_accuracy_level(value).ok()?;
+    (AccuracyLevel::from(level) as u32 != number).then_some(number)
+}
+
+// Parse and validate an EPSG code given on the command line
+fn parse_epsg(value: &str) -> Result<u32, String> {
+    let code: u32 = value.trim_start_matches("EPSG:").parse()
+        .map_err(|_| format!("invalid EPSG code: {}", value))?;
+
+    if !SUPPORTED_EPSG_CODES.contains(&code) {
+        return Err(format!("unsupported EPSG code {} (supported: {:?})", code, SUPPORTED_EPSG_CODES));
+    }
+
+    Ok(code)
+}
+
 // Log level enum for command line arguments
-#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[clap(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 enum LogLevel {
+    Trace,
     Debug,
     Info,
     Warn,
     Error,
 }
 
-// Accuracy level enum for command line arguments
+// Log format enum for command line arguments
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "lowercase")]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+// Timezone enum for command line arguments
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "lowercase")]
+enum LogTimezone {
+    Utc,
+    Local,
+}
+
+// Bucket layout enum for the accuracy histogram
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "lowercase")]
+enum BucketScale {
+    Linear,
+    Log,
+}
+
+// Unit enum for the exported speed
 #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
 #[clap(rename_all = "lowercase")]
+enum SpeedUnit {
+    Mps,
+    Kmh,
+    Mph,
+}
+
+impl SpeedUnit {
+    // Convert a GeoClue2 speed in meters per second to this unit
+    fn convert(self, meters_per_second: f64) -> f64 {
+        match self {
+            SpeedUnit::Mps => meters_per_second,
+            SpeedUnit::Kmh => meters_per_second * 3.6,
+            SpeedUnit::Mph => meters_per_second * 3600.0 / METERS_PER_MILE,
+        }
+    }
+
+    // Name of the speed gauge, carrying the unit unless it is the default m/s
+    fn metric_name(self) -> &'static str {
+        match self {
+            SpeedUnit::Mps => "geoclue_speed",
+            SpeedUnit::Kmh => "geoclue_speed_kmh",
+            SpeedUnit::Mph => "geoclue_speed_mph",
+        }
+    }
+
+    // Name of the per-client speed gauge
+    fn client_metric_name(self) -> &'static str {
+        match self {
+            SpeedUnit::Mps => "geoclue_client_speed",
+            SpeedUnit::Kmh => "geoclue_client_speed_kmh",
+            SpeedUnit::Mph => "geoclue_client_speed_mph",
+        }
+    }
+}
+
+// Length of an international mile in meters
+const METERS_PER_MILE: f64 = 1609.344;
+
+// Accuracy level enum for command line arguments
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 enum AccuracyLevelArg {
     None,
     Country,
@@ -83,6 +589,115 @@ enum AccuracyLevelArg {
     Exact,
 }
 
+// Settings that can be provided through the --config TOML file
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    distance_threshold: Option<u32>,
+    time_threshold: Option<u32>,
+    accuracy_level: Option<AccuracyLevelArg>,
+    metrics_port: Option<u16>,
+    bind_address: Option<String>,
+    log_level: Option<LogLevel>,
+}
+
+// Read the configuration file; a missing file is not an error
+fn load_file_config(path: &Path) -> Result<Option<FileConfig>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+    let config = toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Failed to parse config file {}: {}", path.display(), e))?;
+
+    Ok(Some(config))
+}
+
+// Log level to apply after re-reading the config file; None keeps the current level.
+// Only the log level is reloadable, and --log-level on the command line still wins.
+fn reloaded_log_level(path: &Path, log_level_from_command_line: bool) -> Result<Option<LogLevel>> {
+    let Some(config) = load_file_config(path)? else {
+        log("WARN", "Config file not found, keeping current settings", &[("config", path.display().to_string())]);
+        return Ok(None);
+    };
+
+    let restart_required = config.distance_threshold.is_some()
+        || config.time_threshold.is_some()
+        || config.accuracy_level.is_some()
+        || config.metrics_port.is_some()
+        || config.bind_address.is_some();
+    if restart_required {
+        log("INFO", "Only log_level is reloaded at runtime, other settings apply after a restart", &[]);
+    }
+
+    if log_level_from_command_line && config.log_level.is_some() {
+        log("INFO", "Ignoring config file log_level, --log-level on the command line takes precedence", &[]);
+        return Ok(None);
+    }
+
+    Ok(config.log_level)
+}
+
+// Apply config file values to any argument not explicitly given on the command line.
+// Precedence: command line flags > config file > built-in defaults.
+fn apply_file_config(args: &mut Args, config: FileConfig, matches: &ArgMatches) {
+    let from_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if let Some(value) = config.distance_threshold.filter(|_| !from_command_line("distance_threshold")) {
+        args.distance_threshold = value;
+    }
+    if let Some(value) = config.time_threshold.filter(|_| !from_command_line("time_threshold")) {
+        args.time_threshold = value;
+    }
+    if let Some(value) = config.accuracy_level.filter(|_| !from_command_line("accuracy_level")) {
+        args.accuracy_level = value;
+    }
+    if let Some(value) = config.metrics_port.filter(|_| !from_command_line("metrics_port")) {
+        args.metrics_port = value;
+    }
+    if let Some(value) = config.bind_address.filter(|_| !from_command_line("bind_address")) {
+        args.bind_address = value;
+    }
+    if let Some(value) = config.log_level.filter(|_| !from_command_line("log_level")) {
+        args.log_level = value;
+    }
+}
+
+// Build the effective arguments from parsed command line matches and the optional config file
+fn args_from_matches(matches: &ArgMatches) -> Result<Args> {
+    let mut args = Args::from_arg_matches(matches)?;
+
+    if let Some(path) = args.config.clone() {
+        if let Some(config) = load_file_config(&path)? {
+            apply_file_config(&mut args, config, matches);
+        }
+    }
+
+    validate_args(&args)?;
+    Ok(args)
+}
+
+// Reject settings that parse but are almost certainly a mistake
+fn validate_args(args: &Args) -> Result<()> {
+    if args.metrics_port == 0 && !args.allow_ephemeral_port && !args.bind_address.starts_with("unix:") {
+        anyhow::bail!("--metrics-port 0 would bind a random ephemeral port that cannot be configured as a scrape target; choose a fixed port or pass --allow-ephemeral-port");
+    }
+    if cfg!(not(feature = "otlp")) && args.otlp_endpoint.is_some() {
+        anyhow::bail!("--otlp-endpoint needs a build with the otlp feature");
+    }
+    // Clients are labelled by their level, so two clients at one level would share gauges
+    let mut levels = vec![args.accuracy_level];
+    for level in &args.extra_accuracy_levels {
+        if levels.contains(level) {
+            anyhow::bail!("--extra-accuracy-level {} is already in use; each client needs its own accuracy level", client_label(*level));
+        }
+        levels.push(*level);
+    }
+    Ok(())
+}
+
 // Enum for GeoClue2 accuracy levels (internal representation)
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -108,675 +723,6767 @@ impl From<AccuracyLevelArg> for AccuracyLevel {
     }
 }
 
-// Structure to track location update status
-struct UpdateTracker {
-    received_updates: u64,
+impl AccuracyLevel {
+    // Worst accuracy in meters still plausible for this level, if the level implies a bound
+    fn expected_max_accuracy_meters(self) -> Option<f64> {
+        match self {
+            AccuracyLevel::None | AccuracyLevel::Country => None,
+            AccuracyLevel::City => Some(50_000.0),
+            AccuracyLevel::Neighborhood => Some(10_000.0),
+            AccuracyLevel::Street => Some(5_000.0),
+            AccuracyLevel::Exact => Some(1_000.0),
+        }
+    }
 }
 
-// Structure to hold GeoClue2 connection components
-struct GeoClueConnection {
-    connection: Arc<Connection>,
-    client_path: zvariant::OwnedObjectPath,
+// Number of recent accuracy samples used to judge whether the requested level is met
+const ACCURACY_EXPECTATION_SAMPLES: usize = 5;
+
+// Running accuracy aggregates over the current connection session
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct AccuracyStats {
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
 }
 
-// Global log level
-static mut LOG_LEVEL: LogLevel = LogLevel::Info;
+impl AccuracyStats {
+    fn record(&mut self, accuracy: f64) {
+        if self.count == 0 {
+            self.min = accuracy;
+            self.max = accuracy;
+        } else {
+            self.min = self.min.min(accuracy);
+            self.max = self.max.max(accuracy);
+        }
+        self.sum += accuracy;
+        self.count += 1;
+    }
 
-fn setup_metrics(bind_address: &str, port: u16) -> Result<()> {
-    // Parse the bind address - try both IPv4 and IPv6
-    let socket_addr: SocketAddr = format!("{}:{}", bind_address, port).parse()
-        .map_err(|e| anyhow::anyhow!("Failed to parse bind address: {}", e))?;
+    fn average(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
 
-    // Build and install the Prometheus recorder
-    PrometheusBuilder::new()
-        .with_http_listener(socket_addr)
-        .install()
-        .map_err(|e| anyhow::anyhow!("Failed to start Prometheus metrics server: {}", e))?;
+    fn reset(&mut self) {
+        *self = AccuracyStats::default();
+    }
+}
 
-    // Define metrics
-    metrics::describe_gauge!("up", "Indicates if the exporter is operational (1 = up)");
-    metrics::describe_gauge!("geoclue_latitude", "Latitude in degrees");
-    metrics::describe_gauge!("geoclue_longitude", "Longitude in degrees");
-    metrics::describe_gauge!("geoclue_accuracy", "Location accuracy in meters");
-    metrics::describe_gauge!("geoclue_altitude", "Altitude in meters above sea level (not available = -1)");
-    metrics::describe_gauge!("geoclue_speed", "Speed in meters per second");
-    metrics::describe_gauge!("geoclue_heading", "Heading in degrees from North");
-    metrics::describe_gauge!("geoclue_location_updates_received", "Number of location updates received");
-    
-    // Set the "up" metric to indicate the exporter is running
-    metrics::gauge!("up").set(1.0);
-    
-    // Initialize geoclue metrics with default values so they appear in metrics output
-    metrics::gauge!("geoclue_location_updates_received").set(0.0);
-    
-    // Initialize process metrics collection
-    // For metrics-process v2.4.0 we need to collect metrics manually
-    collect();
-    
-    Ok(())
+// Structure to track location update status
+struct UpdateTracker {
+    // Every LocationUpdated signal, usable or not
+    received_updates: u64,
+    // Updates with at least one value accepted and exported
+    applied_updates: u64,
+    accuracy_samples: VecDeque<f64>,
+    accuracy_warning_logged: bool,
+    session_accuracy: AccuracyStats,
+    last_update: Option<std::time::Instant>,
+    last_reading: Option<LocationReading>,
+    // Last position counted towards geoclue_distance_traveled_meters_total
+    last_position: Option<(f64, f64)>,
+    // Exact total behind the integer counter, so sub-meter moves are not lost to rounding
+    distance_traveled: f64,
+    // Recent (latitude, longitude, accuracy) fixes averaged by --smooth-position
+    recent_positions: VecDeque<(f64, f64, f64)>,
+    // When the current GeoClue2 connection was established, None while disconnected
+    connected_since: Option<std::time::Instant>,
+    // Updates in a row whose values were all rejected, for --max-consecutive-invalid
+    consecutive_invalid: u32,
 }
 
-// Helper function to check if a message should be logged based on log level
-fn should_log(message_level: LogLevel) -> bool {
-    // Safety: This is safe because we set LOG_LEVEL once at startup and never modify it again
-    unsafe {
-        match LOG_LEVEL {
-            LogLevel::Debug => true, // Debug logs everything
-            LogLevel::Info => message_level != LogLevel::Debug, // Info logs Info, Warn, Error
-            LogLevel::Warn => message_level == LogLevel::Warn || message_level == LogLevel::Error, // Warn logs Warn, Error
-            LogLevel::Error => message_level == LogLevel::Error, // Error logs only Error
+impl UpdateTracker {
+    fn new() -> Self {
+        UpdateTracker {
+            received_updates: 0,
+            applied_updates: 0,
+            accuracy_samples: VecDeque::with_capacity(ACCURACY_EXPECTATION_SAMPLES),
+            accuracy_warning_logged: false,
+            session_accuracy: AccuracyStats::default(),
+            last_update: None,
+            last_reading: None,
+            last_position: None,
+            distance_traveled: 0.0,
+            recent_positions: VecDeque::new(),
+            connected_since: None,
+            consecutive_invalid: 0,
         }
     }
-}
 
-// Helper function to log in structured format
-fn log(level: &str, message: &str, fields: &[(&str, String)]) {
-    let message_level = match level {
-        "DEBUG" => LogLevel::Debug,
-        "INFO" => LogLevel::Info,
-        "WARN" => LogLevel::Warn,
-        "ERROR" => LogLevel::Error,
-        _ => LogLevel::Info, // Default to Info for unknown levels
-    };
-    
-    if !should_log(message_level) {
-        return;
+    // Count a LocationUpdated signal before anything about it is known to be usable
+    fn record_signal_received(&mut self) {
+        self.received_updates += 1;
+        metrics::counter!("geoclue_signals_received_total").increment(1);
+        // Deprecated alias, kept for one release
+        metrics::gauge!("geoclue_location_updates_received").set(self.received_updates as f64);
     }
-    
-    let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S%.3f");
-    
-    let mut log_str = String::new();
-    write!(&mut log_str, "timestamp=\"{}\" level={} message=\"{}\"", timestamp, level, message).unwrap();
-    
-    for (key, value) in fields {
-        write!(&mut log_str, " {}={}", key, value).unwrap();
+
+    // Count an update that exported at least one value
+    fn record_update_applied(&mut self) {
+        self.applied_updates += 1;
+        metrics::counter!("geoclue_updates_applied_total").increment(1);
     }
-    
-    println!("{}", log_str);
-}
 
-// Helper function to set gauge only if the value is valid
-fn set_gauge_if_valid(metric_name: &str, value: f64) -> bool {
-    // Skip setting the metric if it's a sentinel value (-1 or extreme negative value)
-    if value == -1.0 || value <= -1.7e308 {
-        log("DEBUG", &format!("Skipping invalid metric {}", metric_name), &[
-            ("metric", metric_name.to_string()), 
-            ("value", value.to_string())
-        ]);
-        return false;
+    // Whether --max-updates applied updates have been processed
+    fn capture_complete(&self, max_updates: Option<u64>) -> bool {
+        max_updates.is_some_and(|max| self.applied_updates >= max)
     }
-    
-    // Set the gauge with the appropriate name - use static string literals for metrics
-    match metric_name {
-        "latitude" => metrics::gauge!("geoclue_latitude").set(value),
-        "longitude" => metrics::gauge!("geoclue_longitude").set(value),
-        "accuracy" => metrics::gauge!("geoclue_accuracy").set(value),
-        "altitude" => metrics::gauge!("geoclue_altitude").set(value),
-        "speed" => metrics::gauge!("geoclue_speed").set(value),
-        "heading" => metrics::gauge!("geoclue_heading").set(value),
-        _ => {
-            log("WARN", &format!("Unknown metric name: {}", metric_name), &[]);
-            // Don't try to use a dynamic name with the gauge macro - it needs static strings
+
+    // Count an update that had every value rejected, or reset the run on a valid one.
+    // Returns true once the run reaches the limit, at which point it starts over.
+    fn record_update_validity(&mut self, valid: bool, max_consecutive_invalid: Option<u32>) -> bool {
+        if valid {
+            self.consecutive_invalid = 0;
             return false;
         }
+
+        self.consecutive_invalid += 1;
+        match max_consecutive_invalid {
+            Some(max) if self.consecutive_invalid >= max => {
+                self.consecutive_invalid = 0;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Add a fix to the smoothing window, dropping the oldest beyond window_size, and
+    // return the smoothed position
+    fn record_smoothing_sample(&mut self, latitude: f64, longitude: f64, accuracy: f64, window_size: usize) -> Option<(f64, f64)> {
+        self.recent_positions.push_back((latitude, longitude, accuracy));
+        while self.recent_positions.len() > window_size {
+            self.recent_positions.pop_front();
+        }
+        weighted_position(self.recent_positions.make_contiguous())
+    }
+
+    // Record a fix and return the distance travelled since the last counted position.
+    // Moves shorter than min_move_meters are treated as jitter and not counted; the
+    // previous position is kept so slow movement still adds up once it exceeds the minimum.
+    // Moves longer than max_jump_meters are taken as spurious and not counted either, but
+    // the new fix becomes the reference so a jump back (or a real relocation) is not counted.
+    fn record_position(&mut self, latitude: f64, longitude: f64, min_move_meters: f64, max_jump_meters: Option<f64>) -> Option<f64> {
+        let Some((last_latitude, last_longitude)) = self.last_position else {
+            self.last_position = Some((latitude, longitude));
+            return None;
+        };
+
+        let distance = haversine(last_latitude, last_longitude, latitude, longitude);
+        if distance < min_move_meters {
+            return None;
+        }
+        self.last_position = Some((latitude, longitude));
+        if max_jump_meters.is_some_and(|max| distance > max) {
+            log("DEBUG", "Ignoring jump in distance traveled", &[
+                ("distance_meters", format!("{:.1}", distance)),
+                ("max_jump_meters", max_jump_meters.unwrap_or_default().to_string()),
+            ]);
+            metrics::counter!("geoclue_distance_jumps_filtered_total").increment(1);
+            return None;
+        }
+        self.distance_traveled += distance;
+        Some(distance)
+    }
+
+    // Whether the exported location is current: an update was processed and, with a
+    // non-zero timeout, it is no older than the timeout
+    fn data_available(&self, timeout_seconds: u64, now: std::time::Instant) -> bool {
+        match self.last_update {
+            Some(last_update) => {
+                timeout_seconds == 0
+                    || now.saturating_duration_since(last_update) <= std::time::Duration::from_secs(timeout_seconds)
+            },
+            None => false,
+        }
+    }
+
+    // Seconds since the last processed update, or -1 before the first one
+    fn update_age_seconds(&self) -> f64 {
+        self.last_update
+            .map(|last_update| last_update.elapsed().as_secs_f64())
+            .unwrap_or(-1.0)
+    }
+
+    // Seconds the current GeoClue2 connection has been up, 0 while disconnected
+    fn connection_uptime_seconds(&self, now: std::time::Instant) -> f64 {
+        self.connected_since
+            .map(|connected_since| now.saturating_duration_since(connected_since).as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    // Mark the connection as lost; the uptime stays 0 until the next session starts
+    fn end_session(&mut self) {
+        self.connected_since = None;
+        metrics::gauge!(self_metric("geoclue_connection_uptime_seconds")).set(0.0);
+    }
+
+    // Start a new connection session, discarding the previous session's accuracy statistics
+    fn start_session(&mut self) {
+        self.connected_since = Some(std::time::Instant::now());
+        metrics::gauge!(self_metric("geoclue_connection_uptime_seconds")).set(0.0);
+        self.session_accuracy.reset();
+        metrics::gauge!("geoclue_session_accuracy_min").set(f64::NAN);
+        metrics::gauge!("geoclue_session_accuracy_max").set(f64::NAN);
+        metrics::gauge!("geoclue_session_accuracy_avg").set(f64::NAN);
     }
-    
-    // Fixed clippy::needless_return warning
-    true
 }
 
-// Function to establish GeoClue2 connection and setup client
-async fn setup_geoclue_connection(args: &Args) -> Result<GeoClueConnection> {
-    // Create a shared connection
-    let connection = Arc::new(Connection::system().await?);
-    log("INFO", "Connected to DBus system bus", &[]);
+// Snapshot of internal state written to --state-dump-file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StateSnapshot {
+    version: String,
+    pid: u32,
+    written_at: i64,
+    uptime_seconds: u64,
+    geoclue_connected: bool,
+    received_updates: u64,
+    // Missing from state files written before it was added
+    #[serde(default)]
+    applied_updates: u64,
+    last_update_age_seconds: Option<f64>,
+    last_latitude: Option<f64>,
+    last_longitude: Option<f64>,
+    last_accuracy: Option<f64>,
+}
 
-    // Get the manager proxy
-    let manager = zbus::Proxy::new(
-        &connection, 
-        "org.freedesktop.GeoClue2", 
-        "/org/freedesktop/GeoClue2/Manager", 
-        "org.freedesktop.GeoClue2.Manager"
-    ).await?;
-    log("INFO", "Created GeoClue2 Manager proxy", &[]);
-    
-    // Call GetClient to get a client object path
-    let client_path: zvariant::OwnedObjectPath = manager.call::<_, _, zvariant::OwnedObjectPath>(
-        "GetClient", 
-        &()
-    ).await?;
-    
-    log("INFO", "Got client path", &[("path", format!("{}", client_path))]);
+impl StateSnapshot {
+    fn capture(tracker: &UpdateTracker, uptime: std::time::Duration) -> Self {
+        StateSnapshot {
+            version: PKG_VERSION.to_string(),
+            pid: std::process::id(),
+            written_at: Utc::now().timestamp(),
+            uptime_seconds: uptime.as_secs(),
+            geoclue_connected: GEOCLUE_CONNECTED.load(Ordering::Relaxed),
+            received_updates: tracker.received_updates,
+            applied_updates: tracker.applied_updates,
+            last_update_age_seconds: tracker.last_update.map(|last_update| last_update.elapsed().as_secs_f64()),
+            last_latitude: tracker.last_reading.as_ref().map(|reading| reading.latitude),
+            last_longitude: tracker.last_reading.as_ref().map(|reading| reading.longitude),
+            last_accuracy: tracker.last_reading.as_ref().map(|reading| reading.accuracy),
+        }
+    }
 
-    // Create client proxy
-    let client = zbus::Proxy::new(
-        &connection, 
-        "org.freedesktop.GeoClue2", 
-        &client_path, 
-        "org.freedesktop.GeoClue2.Client"
-    ).await?;
-    
-    // Set client properties
-    client.set_property("DesktopId", &PKG_NAME.to_string()).await?;
-    log("INFO", "Set client desktop ID", &[("desktop_id", PKG_NAME.to_string())]);
-    
-    // Get accuracy level from command-line args
-    let accuracy_level: AccuracyLevel = args.accuracy_level.into();
-    
-    // Set distance threshold (in meters)
-    client.set_property("DistanceThreshold", &args.distance_threshold).await?;
-    log("INFO", "Set distance threshold", &[("threshold_meters", args.distance_threshold.to_string())]);
-    
-    // Set time threshold (in seconds)
-    client.set_property("TimeThreshold", &args.time_threshold).await?;
-    log("INFO", "Set time threshold", &[("threshold_seconds", args.time_threshold.to_string())]);
-    
-    // Set requested accuracy level
-    client.set_property("RequestedAccuracyLevel", &(accuracy_level as u32)).await?;
-    log("INFO", "Set accuracy level", &[
-        ("accuracy_level", format!("{:?}", accuracy_level)),
-        ("level_value", (accuracy_level as u32).to_string()),
-    ]);
-    
-    // Start the client
-    client.call::<_, _, ()>("Start", &()).await?;
-    log("INFO", "Started GeoClue2 client", &[]);
+    // Replace the state file atomically by writing a sibling temporary file and renaming it
+    fn write(&self, path: &Path) -> Result<()> {
+        let mut temporary = path.to_path_buf().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
 
-    Ok(GeoClueConnection {
-        connection,
-        client_path,
-    })
+        std::fs::write(&temporary, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
 }
 
-// Check if an error indicates a permanent failure that should not be retried
-fn is_permanent_error(error: &anyhow::Error, has_connected_before: bool) -> bool {
-    let error_str = error.to_string().to_lowercase();
-    
-    // Debug logging for error classification
-    log("DEBUG", "Classifying error", &[
-        ("error_str", error_str.clone()),
-        ("has_connected_before", has_connected_before.to_string()),
-    ]);
-    
-    // Always permanent errors
-    if error_str.contains("permission denied") ||
-       error_str.contains("access denied") ||
-       error_str.contains("invalid argument") ||
-       error_str.contains("not permitted") {
-        log("DEBUG", "Error classified as always permanent", &[("reason", "permission/access".to_string())]);
-        return true;
+// DBus names used to reach GeoClue2 or a compatible implementation
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GeoClueNames {
+    service: String,
+    manager_path: String,
+    interface_prefix: String,
+}
+
+impl GeoClueNames {
+    fn from_args(args: &Args) -> Self {
+        GeoClueNames {
+            service: args.service_name.clone(),
+            manager_path: args.manager_path.clone(),
+            interface_prefix: args.interface_prefix.clone(),
+        }
     }
-    
-    // If we've never connected before, be more conservative - treat more errors as permanent
-    if !has_connected_before {
-        let is_permanent = error_str.contains("no such file or directory") ||
-               error_str.contains("service not found") ||
-               error_str.contains("serviceunknown") ||
-               error_str.contains("service unknown") ||
-               error_str.contains("name not found") ||
-               (error_str.contains("failed to connect") && error_str.contains("dbus"));
-        log("DEBUG", "First connection error classification", &[
-            ("is_permanent", is_permanent.to_string()),
-            ("reason", "first_connection_conservative".to_string()),
-        ]);
-        return is_permanent;
+
+    fn manager_interface(&self) -> String {
+        format!("{}.Manager", self.interface_prefix)
+    }
+
+    fn client_interface(&self) -> String {
+        format!("{}.Client", self.interface_prefix)
+    }
+
+    fn location_interface(&self) -> String {
+        format!("{}.Location", self.interface_prefix)
     }
-    
-    // If we've connected before, most errors are retryable (service might restart)
-    // Only treat clearly permanent configuration errors as non-retryable
-    let is_permanent = error_str.contains("permission denied") ||
-        error_str.contains("access denied") ||
-        error_str.contains("invalid argument") ||
-        error_str.contains("not permitted");
-    
-    log("DEBUG", "Reconnection error classification", &[
-        ("is_permanent", is_permanent.to_string()),
-        ("reason", "reconnection_liberal".to_string()),
-    ]);
-    
-    is_permanent
 }
 
-// Check if an error indicates a DBus disconnection that warrants reconnection
-fn is_disconnection_error(error: &anyhow::Error, has_connected_before: bool) -> bool {
-    let is_disconnection = !is_permanent_error(error, has_connected_before);
-    
-    log("DEBUG", "Disconnection error check", &[
-        ("is_disconnection", is_disconnection.to_string()),
-        ("error", error.to_string()),
-    ]);
-    
-    is_disconnection
+// A GeoClue2 client owned by this exporter
+#[derive(Debug, Clone)]
+struct GeoClueClient {
+    label: String,
+    path: zvariant::OwnedObjectPath,
 }
 
-// Function to monitor location updates with proper error handling
-async fn monitor_location_updates(
-    geoclue_conn: &GeoClueConnection,
-    tracker: Arc<Mutex<UpdateTracker>>
-) -> Result<()> {
-    log("INFO", "Waiting for location updates", &[]);
+// Structure to hold GeoClue2 connection components
+struct GeoClueConnection {
+    connection: Arc<Connection>,
+    clients: Vec<GeoClueClient>,
+    names: GeoClueNames,
+    accuracy_levels: Arc<Mutex<AccuracyLevels>>,
+    // Task following AvailableAccuracyLevel changes, stopped with the connection
+    accuracy_watch: tokio::task::JoinHandle<()>,
+    // Set once Stop has been issued, so late LocationUpdated signals are ignored
+    client_stopped: Arc<AtomicBool>,
+}
 
-    // Create client proxy from the connection
-    let client = zbus::Proxy::new(
-        &geoclue_conn.connection, 
-        "org.freedesktop.GeoClue2", 
-        &geoclue_conn.client_path, 
-        "org.freedesktop.GeoClue2.Client"
-    ).await?;
+impl Drop for GeoClueConnection {
+    fn drop(&mut self) {
+        self.accuracy_watch.abort();
+    }
+}
 
-    // Monitor for location updates
-    let mut location_updated_stream = client.receive_signal("LocationUpdated").await?;
-    
-    while let Some(signal) = location_updated_stream.next().await {
-        // Update counter whenever we get a new location
-        {
-            let mut tracker = tracker.lock().unwrap();
-            tracker.received_updates += 1;
-            
-            // Update the received updates counter
-            metrics::gauge!("geoclue_location_updates_received").set(tracker.received_updates as f64);
-            
-            // Log the current update count
-            log("DEBUG", "Location update received", &[
-                ("received_updates", tracker.received_updates.to_string()),
-            ]);
-        }
-        
-        // Deserialize the entire body as a tuple
-        let body_owned = signal.body().clone();
-        let (old_path, new_path): (zvariant::ObjectPath, zvariant::ObjectPath) = 
-            body_owned.deserialize()?;
-        
-        log("INFO", "Received location update", &[
-            ("old_path", format!("{}", old_path)),
-            ("new_path", format!("{}", new_path)),
-        ]);
+// Accuracy level the primary client requests and the AvailableAccuracyLevel reported
+// by the Manager (None when not provided)
+#[derive(Debug, Clone, Copy)]
+struct AccuracyLevels {
+    requested: AccuracyLevel,
+    available: Option<u32>,
+}
 
-        // Create a location proxy for this location
-        let location = zbus::Proxy::new(
-            &geoclue_conn.connection, 
-            "org.freedesktop.GeoClue2", 
-            &new_path, 
-            "org.freedesktop.GeoClue2.Location"
-        ).await?;
+// Maximum number of Graphite lines buffered while the relay is unreachable
+const GRAPHITE_BUFFER_LINES: usize = 1000;
 
-        // Get location properties
-        let lat: f64 = location.get_property("Latitude").await?;
-        let lon: f64 = location.get_property("Longitude").await?;
-        let acc: f64 = location.get_property("Accuracy").await?;
-        let alt: f64 = location.get_property("Altitude").await?;
-        let spd: f64 = location.get_property("Speed").await?;
-        let head: f64 = location.get_property("Heading").await?;
-        
-        // Prepare field arrays for logging
-        let mut update_fields = vec![
-            ("latitude", format!("{}", lat)),
-            ("longitude", format!("{}", lon)),
-            ("accuracy", format!("{}", acc))
-        ];
-        
-        // Add optional fields only if they're valid
-        // Fixed the redundant comparison - if alt > -1.0 then alt > -1.7e308 is always true
-        if alt > -1.0 {
-            update_fields.push(("altitude", format!("{}", alt)));
-        } else {
-            update_fields.push(("altitude", "not_available".to_string()));
-        }
-        
-        if spd > -1.0 {
-            update_fields.push(("speed", format!("{}", spd)));
-        } else {
-            update_fields.push(("speed", "not_available".to_string()));
+// Handle to the background task pushing location values to a Graphite relay
+struct GraphiteSink {
+    prefix: String,
+    sender: mpsc::Sender<Vec<String>>,
+}
+
+// Append-only NDJSON log of processed updates, rotated once it reaches max_bytes
+struct EventsFile {
+    path: PathBuf,
+    max_bytes: u64,
+    file: std::fs::File,
+    written: u64,
+}
+
+// External command run on each processed update, bounded by a concurrency cap
+struct UpdateCommand {
+    command: String,
+    slots: Arc<tokio::sync::Semaphore>,
+}
+
+// Outputs that receive every processed location update besides the gauges
+struct UpdateSinks {
+    graphite: Option<GraphiteSink>,
+    events: Option<broadcast::Sender<String>>,
+    events_file: Option<Mutex<EventsFile>>,
+    update_command: Option<UpdateCommand>,
+    // Versions the metrics page for conditional scrapes
+    scrapes: Arc<ScrapeTracker>,
+}
+
+// Bucket upper bounds in meters for the geoclue_accuracy_meters histogram, spanning
+// GPS-grade fixes (1-10m) through WiFi (25-100m) to cell/IP geolocation (500m+)
+const ACCURACY_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 500.0, 1000.0];
+
+// Range covered by the log-scaled accuracy buckets, from a GPS fix to IP geolocation
+const ACCURACY_LOG_BUCKET_RANGE: (f64, f64) = (1.0, 50_000.0);
+
+// Powers-of-two bucket bounds starting at min, with the last bound the first one at or above max
+fn log_buckets(min: f64, max: f64) -> Vec<f64> {
+    let mut buckets = vec![min];
+    while buckets[buckets.len() - 1] < max {
+        buckets.push(buckets[buckets.len() - 1] * 2.0);
+    }
+    buckets
+}
+
+// Bucket bounds for the geoclue_accuracy_meters histogram under the selected scale
+fn accuracy_buckets(scale: BucketScale) -> Vec<f64> {
+    match scale {
+        BucketScale::Linear => ACCURACY_BUCKETS.to_vec(),
+        BucketScale::Log => log_buckets(ACCURACY_LOG_BUCKET_RANGE.0, ACCURACY_LOG_BUCKET_RANGE.1),
+    }
+}
+
+// Number of SSE events buffered for slow subscribers before they start skipping
+const SSE_CHANNEL_CAPACITY: usize = 16;
+
+// Interval between SSE keep-alive comments
+const SSE_KEEP_ALIVE: tokio::time::Duration = tokio::time::Duration::from_secs(15);
+
+// Body type shared by all HTTP responses
+type HttpBody = UnsyncBoxBody<Bytes, Infallible>;
+
+// Shared state used by the HTTP server to answer requests
+struct HttpState {
+    handle: PrometheusHandle,
+    metrics_path: String,
+    events: Option<broadcast::Sender<String>>,
+    scrapes: Arc<ScrapeTracker>,
+    validate_output: bool,
+    header_timeout: Option<std::time::Duration>,
+    request_timeout: Option<std::time::Duration>,
+    // Networks clients may connect from; empty allows everyone
+    allowed_networks: Vec<ipnet::IpNet>,
+    // Key signing the metrics body into X-Metrics-Signature
+    hmac_key: Option<Vec<u8>>,
+}
+
+// Time of the last metrics scrape, to notice when Prometheus stops scraping
+struct ScrapeTracker {
+    last_scrape: Mutex<std::time::Instant>,
+    // Version of the metrics page as of the latest applied location update
+    version: Mutex<RenderVersion>,
+    // Exported position at the previous scrape, when --track-scrape-movement is set
+    scraped_position: Option<Mutex<Option<(f64, f64)>>>,
+}
+
+// Version of the rendered metrics page, behind the ETag and Last-Modified headers. It
+// follows the applied location updates rather than the page content, which the process
+// and age metrics change on every upkeep tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RenderVersion {
+    // Random per process, so ETags from before a restart never match
+    epoch: u64,
+    // Number of applied location updates, the content behind the page version
+    location_updates: u64,
+    // Unix seconds; strictly increasing between versions so If-Modified-Since can tell
+    // apart pages that changed within the same second
+    modified_at: i64,
+}
+
+impl RenderVersion {
+    fn etag(&self) -> String {
+        format!("\"{:016x}-{:x}\"", self.epoch, self.location_updates)
+    }
+}
+
+impl ScrapeTracker {
+    // Until the first scrape, the age counts from startup, and until the first location
+    // update the page counts as modified at startup
+    fn new() -> Self {
+        ScrapeTracker {
+            last_scrape: Mutex::new(std::time::Instant::now()),
+            version: Mutex::new(RenderVersion {
+                epoch: rand::random(),
+                location_updates: 0,
+                modified_at: Utc::now().timestamp(),
+            }),
+            scraped_position: None,
         }
-        
-        if head > -1.0 {
-            update_fields.push(("heading", format!("{}", head)));
-        } else {
-            update_fields.push(("heading", "not_available".to_string()));
+    }
+
+    // Tracker that also compares the exported position between scrapes
+    fn tracking_movement() -> Self {
+        ScrapeTracker {
+            scraped_position: Some(Mutex::new(None)),
+            ..ScrapeTracker::new()
         }
-        
-        log("INFO", "Updated location metrics", &update_fields);
+    }
 
-        // Log the complete raw data at debug level
-        log("DEBUG", "Raw location data", &[
-            ("latitude", format!("{}", lat)),
-            ("longitude", format!("{}", lon)),
-            ("accuracy", format!("{}", acc)),
-            ("altitude", format!("{}", alt)),
-            ("speed", format!("{}", spd)),
-            ("heading", format!("{}", head)),
-        ]);
+    // Publish whether the position changed since the previous scrape and remember it for
+    // the next one. The first position to appear counts as a move.
+    fn record_movement(&self, current: Option<(f64, f64)>) {
+        let Some(scraped_position) = &self.scraped_position else {
+            return;
+        };
+
+        let previous = std::mem::replace(&mut *scraped_position.lock().unwrap(), current);
+        let moved = current.is_some() && previous != current;
+        metrics::gauge!("geoclue_moved_since_last_scrape").set(if moved { 1.0 } else { 0.0 });
+    }
+
+    // Start a new version of the metrics page for a location update applied at `now`
+    fn record_location_update(&self, now: DateTime<Utc>) {
+        let mut version = self.version.lock().unwrap();
+        version.location_updates += 1;
+        version.modified_at = now.timestamp().max(version.modified_at + 1);
+    }
+
+    // Version of the metrics page served now
+    fn current_version(&self) -> RenderVersion {
+        *self.version.lock().unwrap()
+    }
+
+    fn record_scrape(&self) {
+        *self.last_scrape.lock().unwrap() = std::time::Instant::now();
+    }
+
+    fn seconds_since_last_scrape(&self, now: std::time::Instant) -> f64 {
+        now.saturating_duration_since(*self.last_scrape.lock().unwrap()).as_secs_f64()
+    }
+}
+
+// Unit of the exported speed gauges, set once from --speed-unit
+static SPEED_UNIT: AtomicU8 = AtomicU8::new(SpeedUnit::Mps as u8);
+
+// Global log level
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+// Global log format
+static LOG_FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Text as u8);
+
+// Global log timezone
+static LOG_TIMEZONE: AtomicU8 = AtomicU8::new(LogTimezone::Utc as u8);
+
+// Whether GeoClue2 is connected and delivering data, mirrored in geoclue_connection_up
+static GEOCLUE_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+// Set when the metrics listener was passed in by systemd, which then owns any socket file
+static SOCKET_ACTIVATED: AtomicBool = AtomicBool::new(false);
+
+// Set in --once mode so stdout only carries the JSON result
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+// Current Location object path per client path for geoclue_location_info. GeoClue2 creates a
+// new object path for every update and the recorder cannot remove series, so this is kept
+// out of it and rendered alongside
+static LOCATION_INFO: Mutex<std::collections::BTreeMap<String, String>> = Mutex::new(std::collections::BTreeMap::new());
+
+// Latest exported latitude/longitude, compared between scrapes by --track-scrape-movement
+static EXPORTED_POSITION: Mutex<Option<(f64, f64)>> = Mutex::new(None);
+
+// Set once the first location update is applied; /ready reports 503 until then
+static LOCATION_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+// Prefix replacing geoclue_ on operational metrics; set once from --self-metrics-prefix
+static SELF_METRICS_PREFIX: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+// Source of correlation IDs for update cycles
+static NEXT_CYCLE_ID: AtomicU64 = AtomicU64::new(1);
+
+// Set once at startup from --trace-dbus
+static TRACE_DBUS: AtomicBool = AtomicBool::new(false);
+
+// Bit per LOCATION_METRICS entry turned off with --disable-metrics, set once at startup
+static DISABLED_METRICS: AtomicU8 = AtomicU8::new(0);
+
+// Whether NaN coordinates were already reported, so the backend bug is logged only once
+static NAN_COORDINATES_WARNED: AtomicBool = AtomicBool::new(false);
+
+// One location update cycle; its ID tags every TRACE line logged while handling it
+struct UpdateCycle {
+    id: String,
+}
+
+// Effective configuration after merging the config file, as loggable fields
+fn effective_config(args: &Args) -> Vec<(&'static str, String)> {
+    vec![
+        ("config", args.config.as_ref().map(|path| path.display().to_string()).unwrap_or_default()),
+        ("log_level", format!("{:?}", args.log_level)),
+        ("trace_dbus", args.trace_dbus.to_string()),
+        ("bind_address", args.bind_address.to_string()),
+        ("allow_ephemeral_port", args.allow_ephemeral_port.to_string()),
+        ("allow_cidrs", args.allow_cidrs.iter().map(|network| network.to_string()).collect::<Vec<_>>().join(",")),
+        // Never log the key itself
+        ("hmac_key", if args.hmac_key.is_some() { "<redacted>" } else { "" }.to_string()),
+        ("distance_threshold", args.distance_threshold.to_string()),
+        ("filter_distance_jitter", args.filter_distance_jitter.to_string()),
+        ("max_jump_meters", args.max_jump_meters.map(|meters| meters.to_string()).unwrap_or_default()),
+        ("time_threshold", args.time_threshold.to_string()),
+        ("accuracy_level", format!("{:?}", args.accuracy_level)),
+        ("extra_accuracy_levels", format!("{:?}", args.extra_accuracy_levels)),
+        ("data_timeout", args.data_timeout.to_string()),
+        ("accuracy_fallback", format!("{:?}", args.accuracy_fallback)),
+        ("accuracy_fallback_timeout", args.accuracy_fallback_timeout.to_string()),
+        ("once", args.once.to_string()),
+        ("timeout", args.timeout.to_string()),
+        ("metrics_port", args.metrics_port.to_string()),
+        ("metrics_path", args.metrics_path.clone()),
+        ("self_metrics_prefix", args.self_metrics_prefix.clone().unwrap_or_default()),
+        ("ipv6_fallback", args.ipv6_fallback.to_string()),
+        ("log_format", format!("{:?}", args.log_format)),
+        ("accuracy_bucket_scale", format!("{:?}", args.accuracy_bucket_scale)),
+        ("speed_unit", format!("{:?}", args.speed_unit)),
+        ("log_timezone", format!("{:?}", args.log_timezone)),
+        ("track_hourly", args.track_hourly.to_string()),
+        ("track_scrape_movement", args.track_scrape_movement.to_string()),
+        ("graphite_address", args.graphite_address.clone().unwrap_or_default()),
+        ("graphite_prefix", args.graphite_prefix.clone()),
+        ("project_to", args.project_to.map(|epsg| epsg.to_string()).unwrap_or_default()),
+        ("property_read_retries", args.property_read_retries.to_string()),
+        ("connect_timeout", args.connect_timeout.to_string()),
+        ("max_backoff", args.max_backoff.to_string()),
+        ("max_updates", args.max_updates.map(|max| max.to_string()).unwrap_or_default()),
+        ("max_consecutive_invalid", args.max_consecutive_invalid.map(|count| count.to_string()).unwrap_or_default()),
+        ("suppress_heading_when_slow", args.suppress_heading_when_slow.to_string()),
+        ("heading_speed_threshold", args.heading_speed_threshold.to_string()),
+        ("track_previous_location", args.track_previous_location.to_string()),
+        ("coordinate_precision", args.coordinate_precision.map(|decimals| decimals.to_string()).unwrap_or_default()),
+        ("default_position", args.default_position.map(|(lat, lon)| format!("{},{}", lat, lon)).unwrap_or_default()),
+        ("snap_grid_meters", args.snap_grid_meters.map(|meters| meters.to_string()).unwrap_or_default()),
+        ("smooth_position", args.smooth_position.to_string()),
+        ("smooth_window", args.smooth_window.to_string()),
+        ("disable_metrics", args.disable_metrics.join(",")),
+        ("valid_ranges", args.valid_ranges.iter().map(|range| format!("{}={}:{}", range.metric, range.min, range.max)).collect::<Vec<_>>().join(",")),
+        ("export_location_info", args.export_location_info.to_string()),
+        ("export_ecef", args.export_ecef.to_string()),
+        ("enable_sse", args.enable_sse.to_string()),
+        ("validate_output", args.validate_output.to_string()),
+        ("http_header_timeout", args.http_header_timeout.to_string()),
+        ("http_request_timeout", args.http_request_timeout.to_string()),
+        ("events_file", args.events_file.as_ref().map(|path| path.display().to_string()).unwrap_or_default()),
+        ("events_file_max_bytes", args.events_file_max_bytes.to_string()),
+        ("on_update_command", args.on_update_command.clone().unwrap_or_default()),
+        ("on_update_command_concurrency", args.on_update_command_concurrency.to_string()),
+        ("state_dump_file", args.state_dump_file.as_ref().map(|path| path.display().to_string()).unwrap_or_default()),
+        ("min_satellites", args.min_satellites.map(|min| min.to_string()).unwrap_or_default()),
+        ("clock_offset_seconds", args.clock_offset_seconds.to_string()),
+        ("push_gateway", args.push_gateway.clone().unwrap_or_default()),
+        ("push_interval", args.push_interval.to_string()),
+        ("otlp_endpoint", args.otlp_endpoint.clone().unwrap_or_default()),
+        ("otlp_interval", args.otlp_interval.to_string()),
+        ("shutdown_webhook", args.shutdown_webhook.clone().unwrap_or_default()),
+        ("service_name", args.service_name.clone()),
+        ("manager_path", args.manager_path.clone()),
+        ("interface_prefix", args.interface_prefix.clone()),
+        ("desktop_id", args.desktop_id.clone()),
+    ]
+}
+
+// Kind of a registered metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricKind {
+    Gauge,
+    Counter,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+// A metric exported by this exporter, with its HELP text
+struct MetricDescription {
+    name: &'static str,
+    kind: MetricKind,
+    help: &'static str,
+}
+
+// Registry of every exported metric; setup_metrics describes each entry, --list-metrics
+// prints it and the --print-alert-rules output only references metrics listed here
+const METRIC_DESCRIPTIONS: &[MetricDescription] = &[
+    MetricDescription { name: "up", kind: MetricKind::Gauge, help: "Indicates if the exporter process is running (1 = up)" },
+    MetricDescription { name: "geoclue_connection_uptime_seconds", kind: MetricKind::Gauge, help: "Seconds since the current GeoClue2 connection was established (0 while disconnected), refreshed every 15s" },
+    MetricDescription { name: "geoclue_connection_up", kind: MetricKind::Gauge, help: "Indicates if the GeoClue2 client is connected and delivering data (1 = connected)" },
+    MetricDescription { name: "geoclue_daemon_present", kind: MetricKind::Gauge, help: "Whether the GeoClue2 service was found on the system bus at the last connection attempt (1 = present)" },
+    MetricDescription { name: "geoclue_latitude", kind: MetricKind::Gauge, help: "Latitude in degrees" },
+    MetricDescription { name: "geoclue_using_default_position", kind: MetricKind::Gauge, help: "1 while geoclue_latitude/longitude hold the --default-position rather than a real fix" },
+    MetricDescription { name: "geoclue_longitude", kind: MetricKind::Gauge, help: "Longitude in degrees" },
+    MetricDescription { name: "geoclue_accuracy", kind: MetricKind::Gauge, help: "Location accuracy in meters" },
+    MetricDescription { name: "geoclue_altitude", kind: MetricKind::Gauge, help: "Altitude in meters above sea level" },
+    MetricDescription { name: "geoclue_altitude_available", kind: MetricKind::Gauge, help: "1 if the latest fix included an altitude; geoclue_altitude keeps the last known value otherwise" },
+    MetricDescription { name: "geoclue_speed_available", kind: MetricKind::Gauge, help: "1 if the latest fix included a speed; geoclue_speed keeps the last known value otherwise" },
+    MetricDescription { name: "geoclue_heading_available", kind: MetricKind::Gauge, help: "1 if the latest fix included a usable heading; geoclue_heading keeps the last known value otherwise" },
+    MetricDescription { name: "geoclue_speed", kind: MetricKind::Gauge, help: "Speed in meters per second" },
+    MetricDescription { name: "geoclue_speed_kmh", kind: MetricKind::Gauge, help: "Speed in kilometers per hour, replacing geoclue_speed with --speed-unit kmh" },
+    MetricDescription { name: "geoclue_speed_mph", kind: MetricKind::Gauge, help: "Speed in miles per hour, replacing geoclue_speed with --speed-unit mph" },
+    MetricDescription { name: "geoclue_heading", kind: MetricKind::Gauge, help: "Heading in degrees from North" },
+    MetricDescription { name: "geoclue_client_latitude", kind: MetricKind::Gauge, help: "Latitude in degrees reported by each client" },
+    MetricDescription { name: "geoclue_client_longitude", kind: MetricKind::Gauge, help: "Longitude in degrees reported by each client" },
+    MetricDescription { name: "geoclue_client_accuracy", kind: MetricKind::Gauge, help: "Location accuracy in meters reported by each client" },
+    MetricDescription { name: "geoclue_client_altitude", kind: MetricKind::Gauge, help: "Altitude in meters reported by each client" },
+    MetricDescription { name: "geoclue_client_speed", kind: MetricKind::Gauge, help: "Speed in meters per second reported by each client" },
+    MetricDescription { name: "geoclue_client_speed_kmh", kind: MetricKind::Gauge, help: "Speed in kilometers per hour reported by each client, with --speed-unit kmh" },
+    MetricDescription { name: "geoclue_client_speed_mph", kind: MetricKind::Gauge, help: "Speed in miles per hour reported by each client, with --speed-unit mph" },
+    MetricDescription { name: "geoclue_client_heading", kind: MetricKind::Gauge, help: "Heading in degrees from North reported by each client" },
+    MetricDescription { name: "geoclue_client_info", kind: MetricKind::Gauge, help: "GeoClue2 client owned by this exporter, always 1 (labels: client_path, desktop_id)" },
+    MetricDescription { name: "geoclue_requested_accuracy_level", kind: MetricKind::Gauge, help: "Accuracy level asked for on the command line (0 = none ... 8 = exact)" },
+    MetricDescription { name: "geoclue_available_accuracy_level", kind: MetricKind::Gauge, help: "Highest accuracy level GeoClue2 reports it can deliver (0 = none ... 8 = exact, -1 = unknown)" },
+    MetricDescription { name: "geoclue_available_accuracy_changes_total", kind: MetricKind::Counter, help: "Number of AvailableAccuracyLevel changes signalled by GeoClue2 while running" },
+    MetricDescription { name: "geoclue_distance_threshold_meters", kind: MetricKind::Gauge, help: "DistanceThreshold reported by GeoClue2 after configuring the client, in meters" },
+    MetricDescription { name: "geoclue_time_threshold_seconds", kind: MetricKind::Gauge, help: "TimeThreshold reported by GeoClue2 after configuring the client, in seconds" },
+    MetricDescription { name: "geoclue_effective_accuracy_level", kind: MetricKind::Gauge, help: "Accuracy level the primary client is actually operating at: the requested level after fallback, capped by what GeoClue2 can deliver (0 = none ... 8 = exact)" },
+    MetricDescription { name: "geoclue_active_accuracy_level", kind: MetricKind::Gauge, help: "GeoClue2 accuracy level currently requested by the primary client (0 = none ... 8 = exact)" },
+    MetricDescription { name: "geoclue_location_info", kind: MetricKind::Gauge, help: "Location object currently reported by each client, always 1 (--export-location-info)" },
+    MetricDescription { name: "geoclue_signals_received_total", kind: MetricKind::Counter, help: "Number of LocationUpdated signals received, whether or not they were usable" },
+    MetricDescription { name: "geoclue_updates_applied_total", kind: MetricKind::Counter, help: "Number of location updates with at least one accepted coordinate" },
+    MetricDescription { name: "geoclue_location_updates_received", kind: MetricKind::Gauge, help: "Deprecated: same value as geoclue_signals_received_total, to be removed in the next release" },
+    MetricDescription { name: "geoclue_latitude_smoothed", kind: MetricKind::Gauge, help: "Accuracy-weighted average latitude of the last --smooth-window fixes (--smooth-position)" },
+    MetricDescription { name: "geoclue_longitude_smoothed", kind: MetricKind::Gauge, help: "Accuracy-weighted average longitude of the last --smooth-window fixes (--smooth-position)" },
+    MetricDescription { name: "geoclue_x_projected", kind: MetricKind::Gauge, help: "Projected X coordinate in the --project-to reference system" },
+    MetricDescription { name: "geoclue_y_projected", kind: MetricKind::Gauge, help: "Projected Y coordinate in the --project-to reference system" },
+    MetricDescription { name: "geoclue_data_available", kind: MetricKind::Gauge, help: "Whether the exported location is current (1 = fresh update received, 0 = none yet or older than --data-timeout)" },
+    MetricDescription { name: "geoclue_update_age_seconds", kind: MetricKind::Gauge, help: "Seconds since the last successfully processed location update, refreshed every 15s (-1 before the first update)" },
+    MetricDescription { name: "geoclue_seconds_since_last_scrape", kind: MetricKind::Gauge, help: "Seconds since the metrics endpoint was last scraped (since startup before the first scrape), refreshed every 15s" },
+    MetricDescription { name: "geoclue_properties_read_last_update", kind: MetricKind::Gauge, help: "Number of the six location properties successfully read for the most recent update" },
+    MetricDescription { name: "geoclue_position_x_meters", kind: MetricKind::Gauge, help: "Earth-centered, earth-fixed X coordinate in meters (--export-ecef)" },
+    MetricDescription { name: "geoclue_position_y_meters", kind: MetricKind::Gauge, help: "Earth-centered, earth-fixed Y coordinate in meters (--export-ecef)" },
+    MetricDescription { name: "geoclue_position_z_meters", kind: MetricKind::Gauge, help: "Earth-centered, earth-fixed Z coordinate in meters (--export-ecef)" },
+    MetricDescription { name: "geoclue_reported_move_meters", kind: MetricKind::Gauge, help: "Distance in meters between the previous and the current GeoClue2 Location object (--track-previous-location)" },
+    MetricDescription { name: "geoclue_last_update_timestamp_seconds", kind: MetricKind::Gauge, help: "Unix timestamp of the last successfully processed location update" },
+    MetricDescription { name: "geoclue_accuracy_meters", kind: MetricKind::Histogram, help: "Distribution of reported location accuracy in meters" },
+    MetricDescription { name: "geoclue_session_accuracy_min", kind: MetricKind::Gauge, help: "Best accuracy in meters during the current connection session (NaN before the first sample)" },
+    MetricDescription { name: "geoclue_session_accuracy_max", kind: MetricKind::Gauge, help: "Worst accuracy in meters during the current connection session (NaN before the first sample)" },
+    MetricDescription { name: "geoclue_session_accuracy_avg", kind: MetricKind::Gauge, help: "Average accuracy in meters during the current connection session (NaN before the first sample)" },
+    MetricDescription { name: "geoclue_accuracy_expectation_met", kind: MetricKind::Gauge, help: "Whether observed accuracy matches the requested accuracy level (1 = met, 0 = consistently worse)" },
+    MetricDescription { name: "geoclue_updates_by_hour", kind: MetricKind::Counter, help: "Number of location updates received per hour of day" },
+    MetricDescription { name: "geoclue_dbus_calls_total", kind: MetricKind::Counter, help: "Number of GeoClue2 DBus calls and property accesses, by method and result" },
+    MetricDescription { name: "geoclue_reconnects_total", kind: MetricKind::Counter, help: "Number of times the GeoClue2 connection was re-established" },
+    MetricDescription { name: "geoclue_location_update_errors_total", kind: MetricKind::Counter, help: "Number of location updates that failed to process, by error kind" },
+    MetricDescription { name: "geoclue_rejected_values_total", kind: MetricKind::Counter, help: "Number of location values not exported because they failed validation, by metric and reason" },
+    MetricDescription { name: "geoclue_property_read_retries_total", kind: MetricKind::Counter, help: "Number of location property reads retried after a failure, by property" },
+    MetricDescription { name: "geoclue_late_signals_ignored_total", kind: MetricKind::Counter, help: "Number of LocationUpdated signals ignored because they arrived after the client was stopped" },
+    MetricDescription { name: "geoclue_invalid_location_path_total", kind: MetricKind::Counter, help: "Number of location updates ignored because the Location object path was empty, root or malformed" },
+    MetricDescription { name: "geoclue_moved_since_last_scrape", kind: MetricKind::Gauge, help: "1 if the exported position changed between the previous scrape and this one" },
+    MetricDescription { name: "geoclue_distance_traveled_meters_total", kind: MetricKind::Counter, help: "Cumulative great-circle distance in meters between successive fixes" },
+    MetricDescription { name: "geoclue_distance_jumps_filtered_total", kind: MetricKind::Counter, help: "Moves longer than --max-jump-meters left out of geoclue_distance_traveled_meters_total" },
+    MetricDescription { name: "geoclue_nan_coordinates_total", kind: MetricKind::Counter, help: "Number of NaN latitude or longitude values reported by GeoClue2 and rejected" },
+    MetricDescription { name: "geoclue_updates_by_source_total", kind: MetricKind::Counter, help: "Number of location updates from all clients by detected provider (gps, network, modem, unknown)" },
+    MetricDescription { name: "geoclue_low_quality_fixes_total", kind: MetricKind::Counter, help: "Number of fixes skipped for reporting fewer satellites than --min-satellites" },
+    MetricDescription { name: "geoclue_render_failures_total", kind: MetricKind::Counter, help: "Number of scrapes answered with fallback output because rendering failed" },
+    MetricDescription { name: "geoclue_http_timeouts_total", kind: MetricKind::Counter, help: "Number of HTTP connections closed for exceeding --http-header-timeout or --http-request-timeout, by kind" },
+    MetricDescription { name: "geoclue_invalid_output_total", kind: MetricKind::Counter, help: "Number of rendered metrics pages that failed to parse (--validate-output)" },
+    MetricDescription { name: "geoclue_update_command_dropped_total", kind: MetricKind::Counter, help: "Number of --on-update-command invocations skipped because the concurrency cap was reached" },
+    MetricDescription { name: "geoclue_push_failures_total", kind: MetricKind::Counter, help: "Number of failed pushes to the Prometheus Pushgateway" },
+    MetricDescription { name: "geoclue_otlp_export_failures_total", kind: MetricKind::Counter, help: "Number of failed exports to the --otlp-endpoint collector" },
+    MetricDescription { name: "geoclue_graphite_errors_total", kind: MetricKind::Counter, help: "Number of failed attempts to push values to Graphite" },
+];
+
+// Look up a metric in the registry by name
+fn metric_description(name: &str) -> Option<&'static MetricDescription> {
+    METRIC_DESCRIPTIONS.iter().find(|metric| metric.name == name)
+}
+
+// A Prometheus alerting rule emitted by --print-alert-rules
+struct AlertRule {
+    name: &'static str,
+    // Registered metric the expression is built on
+    metric: &'static str,
+    expr: &'static str,
+    duration: &'static str,
+    severity: &'static str,
+    summary: &'static str,
+}
+
+const ALERT_RULES: &[AlertRule] = &[
+    AlertRule {
+        name: "GeoClueExporterDown",
+        metric: "up",
+        expr: "up{job=\"geoclue\"} == 0",
+        duration: "5m",
+        severity: "critical",
+        summary: "GeoClue2 exporter is down",
+    },
+    AlertRule {
+        name: "GeoClueDisconnected",
+        metric: "geoclue_connection_up",
+        expr: "geoclue_connection_up == 0",
+        duration: "10m",
+        severity: "warning",
+        summary: "GeoClue2 client is not connected",
+    },
+    AlertRule {
+        name: "GeoClueLocationStale",
+        metric: "geoclue_last_update_timestamp_seconds",
+        expr: "time() - geoclue_last_update_timestamp_seconds > 3600",
+        duration: "5m",
+        severity: "warning",
+        summary: "No location update for over an hour",
+    },
+    AlertRule {
+        name: "GeoClueHighReconnectRate",
+        metric: "geoclue_reconnects_total",
+        expr: "increase(geoclue_reconnects_total[1h]) > 5",
+        duration: "0m",
+        severity: "warning",
+        summary: "GeoClue2 connection is flapping",
+    },
+    AlertRule {
+        name: "GeoClueLowAccuracy",
+        metric: "geoclue_accuracy",
+        expr: "geoclue_accuracy > 1000",
+        duration: "30m",
+        severity: "info",
+        summary: "Location accuracy is worse than 1km",
+    },
+];
+
+// Render ALERT_RULES as a Prometheus rules file, describing each alert with the HELP
+// text of its metric. Rules whose metric is missing from the registry are skipped.
+fn alert_rules_yaml() -> String {
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+
+    let mut yaml = String::new();
+    writeln!(&mut yaml, "groups:").unwrap();
+    writeln!(&mut yaml, "  - name: {}", PKG_NAME).unwrap();
+    writeln!(&mut yaml, "    rules:").unwrap();
+    for rule in ALERT_RULES {
+        let Some(metric) = metric_description(rule.metric) else {
+            continue;
+        };
+        let metric_name = exported_metric_name(metric.name);
+        writeln!(&mut yaml, "      - alert: {}", rule.name).unwrap();
+        writeln!(&mut yaml, "        expr: {}", quote(&rule.expr.replace(metric.name, &metric_name))).unwrap();
+        writeln!(&mut yaml, "        for: {}", rule.duration).unwrap();
+        writeln!(&mut yaml, "        labels:").unwrap();
+        writeln!(&mut yaml, "          severity: {}", rule.severity).unwrap();
+        writeln!(&mut yaml, "        annotations:").unwrap();
+        writeln!(&mut yaml, "          summary: {}", quote(rule.summary)).unwrap();
+        writeln!(&mut yaml, "          description: {}", quote(&format!("{}: {}", metric_name, metric.help))).unwrap();
+    }
+    yaml
+}
+
+// Metric catalog printed by --list-metrics: one tab-separated name, type and description per line
+fn metric_listing() -> String {
+    let mut listing = String::new();
+    for metric in METRIC_DESCRIPTIONS {
+        writeln!(&mut listing, "{}\t{}\t{}", exported_metric_name(metric.name), metric.kind.as_str(), metric.help).unwrap();
+    }
+    listing
+}
+
+// Register the HELP text of every metric in the registry with the recorder
+fn describe_metrics() {
+    for metric in METRIC_DESCRIPTIONS.iter().filter(|metric| !is_disabled_metric_name(metric.name)) {
+        let name = exported_metric_name(metric.name);
+        match metric.kind {
+            MetricKind::Gauge => metrics::describe_gauge!(name, metric.help),
+            MetricKind::Counter => metrics::describe_counter!(name, metric.help),
+            MetricKind::Histogram => metrics::describe_histogram!(name, metric.help),
+        }
+    }
+}
+
+// Turn off location metrics by suffix; they are then neither described nor set
+fn disable_location_metrics(suffixes: &[String]) {
+    let mask = LOCATION_METRICS.iter().enumerate()
+        .filter(|(_, suffix)| suffixes.iter().any(|disabled| disabled == *suffix))
+        .fold(0, |mask, (index, _)| mask | 1 << index);
+    DISABLED_METRICS.store(mask, Ordering::Relaxed);
+}
+
+// Whether a location metric suffix was turned off with --disable-metrics
+fn is_metric_disabled(suffix: &str) -> bool {
+    let mask = DISABLED_METRICS.load(Ordering::Relaxed);
+    LOCATION_METRICS.iter()
+        .position(|metric| *metric == suffix)
+        .is_some_and(|index| mask & 1 << index != 0)
+}
+
+// Whether a full metric name is the primary or per-client gauge of a disabled suffix
+fn is_disabled_metric_name(name: &str) -> bool {
+    name.strip_prefix("geoclue_client_")
+        .or_else(|| name.strip_prefix("geoclue_"))
+        .map(|suffix| suffix.strip_suffix("_kmh").or_else(|| suffix.strip_suffix("_mph")).unwrap_or(suffix))
+        .is_some_and(is_metric_disabled)
+}
+
+// Operational metrics about the exporter itself, renamed by --self-metrics-prefix
+const SELF_METRICS: &[&str] = &[
+    "geoclue_connection_up",
+    "geoclue_daemon_present",
+    "geoclue_reconnects_total",
+    "geoclue_location_update_errors_total",
+    "geoclue_render_failures_total",
+    "geoclue_invalid_output_total",
+    "geoclue_http_timeouts_total",
+    "geoclue_graphite_errors_total",
+    "geoclue_push_failures_total",
+    "geoclue_otlp_export_failures_total",
+    "geoclue_update_command_dropped_total",
+    "geoclue_property_read_retries_total",
+    "geoclue_invalid_location_path_total",
+    "geoclue_late_signals_ignored_total",
+    "geoclue_seconds_since_last_scrape",
+    "geoclue_connection_uptime_seconds",
+    "geoclue_rejected_values_total",
+    "geoclue_dbus_calls_total",
+];
+
+// Name a metric is exported under: operational metrics swap their geoclue_ prefix for
+// the self-metrics prefix when one is set, location metrics keep theirs
+fn self_metric_name(name: &'static str, self_prefix: Option<&str>) -> std::borrow::Cow<'static, str> {
+    match (self_prefix, name.strip_prefix("geoclue_")) {
+        (Some(prefix), Some(suffix)) if SELF_METRICS.contains(&name) => format!("{}{}", prefix, suffix).into(),
+        _ => name.into(),
+    }
+}
+
+// Exported name of any registered metric under the configured --self-metrics-prefix
+fn exported_metric_name(name: &'static str) -> std::borrow::Cow<'static, str> {
+    self_metric_name(name, SELF_METRICS_PREFIX.get().map(String::as_str))
+}
+
+// Exported name of an operational metric, for use at its call sites
+fn self_metric(name: &'static str) -> String {
+    debug_assert!(SELF_METRICS.contains(&name), "{} is not an operational metric", name);
+    exported_metric_name(name).into_owned()
+}
+
+// Process metrics collector, moved under --self-metrics-prefix when one is set
+fn process_collector() -> metrics_process::Collector {
+    metrics_process::Collector::new(SELF_METRICS_PREFIX.get().map(String::as_str).unwrap_or(""))
+}
+
+// Whether a bind address is syntactically a DNS hostname (RFC 1123 labels)
+fn is_hostname(address: &str) -> bool {
+    let address = address.strip_suffix('.').unwrap_or(address);
+    !address.is_empty() && address.len() <= 253 && address.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+// Socket path when the bind address selects a Unix domain socket (unix:PATH)
+fn unix_socket_path(bind_address: &str) -> Result<Option<PathBuf>> {
+    match bind_address.strip_prefix("unix:") {
+        Some("") => Err(anyhow::anyhow!("Failed to parse bind address: unix: requires a socket path")),
+        Some(path) => Ok(Some(PathBuf::from(path))),
+        None => Ok(None),
+    }
+}
+
+// Parse an IP literal bind address; IPv6 may be bare or bracketed and carry a %zone
+// (interface name or index) for link-local addresses
+fn parse_socket_addr(address: &str, port: u16) -> Result<SocketAddr> {
+    let bare = address.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).unwrap_or(address);
+    if !bare.contains(':') {
+        return format!("{}:{}", bare, port).parse()
+            .map_err(|e| anyhow::anyhow!("Failed to parse bind address: {}", e));
+    }
+
+    let (ip, zone) = match bare.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (bare, None),
+    };
+    let ip: std::net::Ipv6Addr = ip.parse()
+        .map_err(|e| anyhow::anyhow!("Failed to parse bind address: {}", e))?;
+    let scope_id = match zone {
+        Some(zone) => ipv6_scope_id(zone)?,
+        None => 0,
+    };
+    Ok(SocketAddr::V6(std::net::SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+// Scope id for an IPv6 zone given as an interface index or name
+fn ipv6_scope_id(zone: &str) -> Result<u32> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+
+    let name = std::ffi::CString::new(zone)
+        .map_err(|_| anyhow::anyhow!("Failed to parse bind address: invalid zone {:?}", zone))?;
+    // Safety: name is a valid NUL-terminated string that outlives the call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(anyhow::anyhow!("Failed to parse bind address: unknown network interface {}", zone)),
+        index => Ok(index),
+    }
+}
+
+// Parse the configured bind address and port into a socket address, resolving hostnames
+async fn resolve_bind_address(args: &Args) -> Result<SocketAddr> {
+    let parse_error = match parse_socket_addr(&args.bind_address, args.metrics_port) {
+        Ok(socket_addr) => return Ok(socket_addr),
+        Err(e) => e,
+    };
+
+    if !is_hostname(&args.bind_address) {
+        return Err(parse_error);
+    }
+
+    let mut addresses = tokio::net::lookup_host((args.bind_address.as_str(), args.metrics_port)).await
+        .map_err(|e| anyhow::anyhow!("Failed to resolve bind address {}: {}", args.bind_address, e))?;
+    let socket_addr = addresses.next()
+        .ok_or_else(|| anyhow::anyhow!("Failed to resolve bind address {}: no addresses found", args.bind_address))?;
+
+    log("INFO", "Resolved bind address", &[
+        ("bind_address", args.bind_address.clone()),
+        ("resolved_address", socket_addr.ip().to_string()),
+    ]);
+    Ok(socket_addr)
+}
+
+async fn setup_metrics(
+    args: &Args,
+    events: Option<broadcast::Sender<String>>,
+    scrapes: Arc<ScrapeTracker>
+) -> Result<PrometheusHandle> {
+    // Bind the HTTP listener serving the metrics path (and /events when enabled), unless
+    // systemd passed one in
+    let listener = match socket_activated_listener()? {
+        Some(listener) => listener,
+        None => match unix_socket_path(&args.bind_address)? {
+            Some(path) => HttpListener::Unix(bind_unix_listener(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to start Prometheus metrics server on {}: {}", path.display(), e))?),
+            None => HttpListener::Tcp(bind_tcp_listener(args).await?),
+        },
+    };
+
+    // Build and install the Prometheus recorder
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full("geoclue_accuracy_meters".to_string()), &accuracy_buckets(args.accuracy_bucket_scale))
+        .map_err(|e| anyhow::anyhow!("Failed to configure accuracy histogram buckets: {}", e))?
+        .install_recorder()
+        .map_err(|e| anyhow::anyhow!("Failed to start Prometheus metrics server: {}", e))?;
+
+    tokio::spawn(serve_http(listener, Arc::new(HttpState {
+        handle: handle.clone(),
+        metrics_path: args.metrics_path.clone(),
+        events,
+        scrapes,
+        validate_output: args.validate_output,
+        header_timeout: (args.http_header_timeout > 0).then(|| std::time::Duration::from_secs(args.http_header_timeout)),
+        request_timeout: (args.http_request_timeout > 0).then(|| std::time::Duration::from_secs(args.http_request_timeout)),
+        allowed_networks: args.allow_cidrs.clone(),
+        hmac_key: args.hmac_key.as_ref().map(|key| key.as_bytes().to_vec()),
+    })));
+
+    initialize_metrics(args);
+    
+    // Initialize process metrics collection
+    let process_metrics = process_collector();
+    process_metrics.describe();
+    process_metrics.collect();
+    
+    Ok(handle)
+}
+
+// Describe every metric and set the initial values of those that should appear in the
+// output before the first update
+fn initialize_metrics(args: &Args) {
+    // Define metrics
+    describe_metrics();
+    
+    // Set the "up" metric to indicate the exporter is running
+    metrics::gauge!("up").set(1.0);
+    set_connection_up(false);
+    metrics::gauge!(self_metric("geoclue_connection_uptime_seconds")).set(0.0);
+    
+    // Initialize geoclue metrics with default values so they appear in metrics output
+    metrics::gauge!("geoclue_location_updates_received").set(0.0);
+    metrics::counter!("geoclue_signals_received_total").absolute(0);
+    metrics::counter!("geoclue_updates_applied_total").absolute(0);
+    metrics::gauge!("geoclue_last_update_timestamp_seconds").set(0.0);
+    metrics::gauge!("geoclue_update_age_seconds").set(-1.0);
+    metrics::gauge!("geoclue_data_available").set(0.0);
+    metrics::gauge!("geoclue_available_accuracy_level").set(-1.0);
+    metrics::gauge!("geoclue_effective_accuracy_level").set(0.0);
+    metrics::counter!("geoclue_available_accuracy_changes_total").absolute(0);
+    metrics::gauge!("geoclue_accuracy_expectation_met").set(1.0);
+    seed_default_position(args.default_position);
+    for metric in OPTIONAL_METRICS {
+        set_availability_gauge(metric, false);
+    }
+    metrics::counter!(self_metric("geoclue_reconnects_total")).absolute(0);
+    metrics::counter!(self_metric("geoclue_location_update_errors_total"), "kind" => "deserialize").absolute(0);
+    metrics::counter!(self_metric("geoclue_location_update_errors_total"), "kind" => "property_fetch").absolute(0);
+    metrics::counter!("geoclue_low_quality_fixes_total").absolute(0);
+    for source in UPDATE_SOURCES {
+        metrics::counter!("geoclue_updates_by_source_total", "source" => source).absolute(0);
+    }
+    metrics::counter!("geoclue_nan_coordinates_total").absolute(0);
+    metrics::counter!("geoclue_distance_traveled_meters_total").absolute(0);
+    metrics::counter!("geoclue_distance_jumps_filtered_total").absolute(0);
+    metrics::counter!(self_metric("geoclue_render_failures_total")).absolute(0);
+    if args.validate_output {
+        metrics::counter!(self_metric("geoclue_invalid_output_total")).absolute(0);
+    }
+    metrics::counter!(self_metric("geoclue_http_timeouts_total"), "kind" => "header").absolute(0);
+    metrics::counter!(self_metric("geoclue_http_timeouts_total"), "kind" => "request").absolute(0);
+    metrics::counter!(self_metric("geoclue_graphite_errors_total")).absolute(0);
+    if args.on_update_command.is_some() {
+        metrics::counter!(self_metric("geoclue_update_command_dropped_total")).absolute(0);
+    }
+    if args.push_gateway.is_some() {
+        metrics::counter!(self_metric("geoclue_push_failures_total")).absolute(0);
+    }
+    if args.otlp_endpoint.is_some() {
+        metrics::counter!(self_metric("geoclue_otlp_export_failures_total")).absolute(0);
+    }
+}
+
+// Current Prometheus text exposition of the recorder behind handle, as served on the
+// metrics path and pushed to the Pushgateway
+fn render_metrics(handle: &PrometheusHandle) -> String {
+    render_with_fallback(|| handle.render() + &location_info_lines())
+}
+
+// Resolve the bind address and bind the TCP listener, applying --ipv6-fallback
+async fn bind_tcp_listener(args: &Args) -> Result<tokio::net::TcpListener> {
+    let socket_addr = resolve_bind_address(args).await?;
+
+    let listener = match bind_listener(socket_addr) {
+        Ok(listener) => listener,
+        Err(e) if socket_addr.is_ipv6() && is_ipv6_unavailable(&e) => {
+            let Some(fallback_addr) = ipv6_fallback_address(socket_addr, &e, args.ipv6_fallback) else {
+                log("ERROR", "IPv6 is not available on this host, use an IPv4 bind address or --ipv6-fallback", &[
+                    ("bind_address", socket_addr.to_string()),
+                    ("error", format!("{}", e)),
+                ]);
+                return Err(anyhow::anyhow!("Failed to start Prometheus metrics server: {}", e));
+            };
+
+            log("WARN", "IPv6 is not available on this host, falling back to IPv4", &[
+                ("bind_address", socket_addr.to_string()),
+                ("fallback_address", fallback_addr.to_string()),
+            ]);
+            bind_listener(fallback_addr)
+                .map_err(|e| anyhow::anyhow!("Failed to start Prometheus metrics server: {}", e))?
+        },
+        Err(e) => return Err(anyhow::anyhow!("Failed to start Prometheus metrics server: {}", e)),
+    };
+
+    if socket_addr.port() == 0 {
+        log("INFO", "Metrics server bound to an ephemeral port", &[
+            ("address", listener.local_addr().map(|addr| addr.to_string()).unwrap_or_default()),
+        ]);
+    }
+
+    Ok(listener)
+}
+
+
+// Bind a non-blocking TCP listener for the HTTP server
+fn bind_listener(socket_addr: SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
+    let listener = std::net::TcpListener::bind(socket_addr)?;
+    listener.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(listener)
+}
+
+// Bind a Unix domain socket listener, replacing a stale socket left by an earlier run
+fn bind_unix_listener(path: &Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "path exists and is not a socket"));
+        }
+        std::fs::remove_file(path)?;
+    }
+    tokio::net::UnixListener::bind(path)
+}
+
+// First file descriptor passed by systemd socket activation (SD_LISTEN_FDS_START)
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+// Number of sockets passed by systemd socket activation, following sd_listen_fds(3):
+// LISTEN_PID must name this process and LISTEN_FDS hold a positive count
+fn listen_fds_count(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<u32> {
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    listen_fds?.parse().ok().filter(|&count| count > 0)
+}
+
+// Take over the metrics listener passed by systemd socket activation, so a restart keeps
+// the port open. The variables are removed like sd_listen_fds(1) does, so
+// --on-update-command children do not take the socket for theirs.
+fn socket_activated_listener() -> Result<Option<HttpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    let Some(count) = listen_fds_count(listen_pid.as_deref(), listen_fds.as_deref(), std::process::id()) else {
+        return Ok(None);
+    };
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+
+    if count > 1 {
+        log("WARN", "Socket activation passed more than one socket, serving metrics on the first", &[
+            ("listen_fds", count.to_string()),
+        ]);
+    }
+
+    // Safety: fcntl(F_GETFD) only checks that the descriptor is open. With LISTEN_PID
+    // naming this process, systemd hands over ownership of the descriptors starting at
+    // SD_LISTEN_FDS_START, and nothing else in the process uses them.
+    if unsafe { libc::fcntl(SD_LISTEN_FDS_START, libc::F_GETFD) } == -1 {
+        return Err(anyhow::anyhow!("LISTEN_FDS is set but file descriptor {} is not open", SD_LISTEN_FDS_START));
+    }
+    let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+    let listener = listener_from_fd(fd)
+        .context("taking over the socket-activated metrics listener")?;
+    SOCKET_ACTIVATED.store(true, Ordering::Relaxed);
+    log("INFO", "Using socket-activated metrics listener", &[
+        ("address", match &listener {
+            HttpListener::Tcp(listener) => listener.local_addr().map(|addr| addr.to_string()).unwrap_or_default(),
+            HttpListener::Unix(listener) => listener.local_addr().ok()
+                .and_then(|addr| addr.as_pathname().map(|path| format!("unix:{}", path.display())))
+                .unwrap_or_default(),
+        }),
+    ]);
+    Ok(Some(listener))
+}
+
+// Wrap an inherited listening socket, choosing TCP or Unix by its address family.
+// Inherited descriptors lack close-on-exec, which is set so child processes do not keep
+// the port open.
+fn listener_from_fd(fd: std::os::fd::OwnedFd) -> Result<HttpListener> {
+    use std::os::fd::AsRawFd;
+
+    // Safety: getsockname only writes up to len bytes into the zeroed storage, and fcntl
+    // only changes the flags of a descriptor we own
+    let family = unsafe {
+        let mut address: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd.as_raw_fd(), &mut address as *mut _ as *mut libc::sockaddr, &mut len) != 0 {
+            return Err(anyhow::anyhow!("file descriptor {} is not a socket: {}", fd.as_raw_fd(), std::io::Error::last_os_error()));
+        }
+        libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::c_int::from(address.ss_family)
+    };
+
+    match family {
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = std::net::TcpListener::from(fd);
+            listener.set_nonblocking(true)?;
+            Ok(HttpListener::Tcp(tokio::net::TcpListener::from_std(listener)?))
+        },
+        libc::AF_UNIX => {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            Ok(HttpListener::Unix(tokio::net::UnixListener::from_std(listener)?))
+        },
+        family => Err(anyhow::anyhow!("unsupported address family {} for the metrics listener", family)),
+    }
+}
+
+// Listener the HTTP server accepts connections on
+enum HttpListener {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+// Whether a bind error means the host has no usable IPv6 stack
+fn is_ipv6_unavailable(error: &std::io::Error) -> bool {
+    error.kind() == std::io::ErrorKind::AddrNotAvailable
+        || error.raw_os_error() == Some(libc::EAFNOSUPPORT)
+}
+
+// IPv4 address to retry on after an IPv6 bind failed, if fallback applies
+fn ipv6_fallback_address(socket_addr: SocketAddr, error: &std::io::Error, fallback_enabled: bool) -> Option<SocketAddr> {
+    let SocketAddr::V6(v6_addr) = socket_addr else {
+        return None;
+    };
+
+    if !fallback_enabled || !is_ipv6_unavailable(error) {
+        return None;
+    }
+
+    let ip = v6_addr.ip();
+    let ipv4 = if ip.is_unspecified() {
+        std::net::Ipv4Addr::UNSPECIFIED
+    } else if ip.is_loopback() {
+        std::net::Ipv4Addr::LOCALHOST
+    } else {
+        ip.to_ipv4_mapped()?
+    };
+
+    Some(SocketAddr::new(ipv4.into(), socket_addr.port()))
+}
+
+// Accept HTTP connections and serve each one on its own task
+async fn serve_http(listener: HttpListener, state: Arc<HttpState>) {
+    loop {
+        let accepted = match &listener {
+            HttpListener::Tcp(listener) => listener.accept().await
+                .map(|(stream, peer)| {
+                    let allowed = is_peer_allowed(&state.allowed_networks, peer.ip());
+                    if !allowed {
+                        log("DEBUG", "Rejecting HTTP connection from outside --allow-cidr", &[("peer", peer.to_string())]);
+                    }
+                    tokio::spawn(serve_connection(stream, peer.to_string(), allowed, state.clone()))
+                }),
+            // Access to a Unix socket is governed by its file permissions instead
+            HttpListener::Unix(listener) => listener.accept().await
+                .map(|(stream, _)| tokio::spawn(serve_connection(stream, "unix".to_string(), true, state.clone()))),
+        };
+
+        if let Err(e) = accepted {
+            log("WARN", "Failed to accept HTTP connection", &[("error", format!("{}", e))]);
+        }
+    }
+}
+
+// Response body that clears its connection's request deadline once it has been written
+// (or dropped by the connection). Size hints pass through so Content-Length is kept.
+struct DeadlineBody {
+    inner: HttpBody,
+    deadline: Arc<tokio::sync::watch::Sender<Option<tokio::time::Instant>>>,
+}
+
+impl hyper::body::Body for DeadlineBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>
+    ) -> std::task::Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        std::pin::Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for DeadlineBody {
+    fn drop(&mut self) {
+        self.deadline.send_replace(None);
+    }
+}
+
+// Whether --allow-cidr lets a client connect; IPv4 clients of an IPv6 listener match
+// IPv4 networks
+fn is_peer_allowed(allowed_networks: &[ipnet::IpNet], peer: std::net::IpAddr) -> bool {
+    let peer = peer.to_canonical();
+    allowed_networks.is_empty() || allowed_networks.iter().any(|network| network.contains(&peer))
+}
+
+// Serve HTTP/1 requests on one accepted connection, answering 403 to every request of a
+// client --allow-cidr does not allow. Request headers must arrive within the header
+// timeout, and each response must be written within the request timeout of its headers;
+// /events streams are exempt from the latter.
+async fn serve_connection<S>(stream: S, peer: String, allowed: bool, state: Arc<HttpState>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (deadline_sender, mut deadline) = tokio::sync::watch::channel(None);
+    let deadline_sender = Arc::new(deadline_sender);
+    let requests_served = Arc::new(AtomicU64::new(0));
+
+    let service_state = state.clone();
+    let service_requests = requests_served.clone();
+    let service = service_fn(move |request: Request<Incoming>| {
+        let state = service_state.clone();
+        let deadline_sender = deadline_sender.clone();
+        service_requests.fetch_add(1, Ordering::Relaxed);
+        async move {
+            let request_deadline = state.request_timeout
+                .filter(|_| request.uri().path() != "/events")
+                .map(|timeout| tokio::time::Instant::now() + timeout);
+            deadline_sender.send_replace(request_deadline);
+            let response = if allowed {
+                handle_http_request(state, request).await?
+            } else {
+                text_response(StatusCode::FORBIDDEN, "text/plain", "Forbidden\n".to_string())
+            };
+            Ok::<_, Infallible>(response.map(|inner| DeadlineBody { inner, deadline: deadline_sender }.boxed_unsync()))
+        }
+    });
+
+    let mut builder = hyper::server::conn::http1::Builder::new();
+    builder.timer(TokioTimer::new()).header_read_timeout(state.header_timeout);
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    tokio::pin!(connection);
+
+    let result = loop {
+        let request_deadline = *deadline.borrow_and_update();
+        let expired = async move {
+            match request_deadline {
+                Some(request_deadline) => tokio::time::sleep_until(request_deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = connection.as_mut() => break result,
+            Ok(()) = deadline.changed() => {},
+            () = expired => {
+                record_http_timeout("request", peer);
+                return;
+            },
+        }
+    };
+
+    match result {
+        // hyper's header timer also runs while a keep-alive connection waits for its
+        // next request; only a connection that never completed a request was too slow
+        Err(e) if e.is_timeout() && requests_served.load(Ordering::Relaxed) == 0 => record_http_timeout("header", peer),
+        Err(e) if e.is_timeout() => log("DEBUG", "Closed idle HTTP connection", &[("peer", peer)]),
+        Err(e) => log("DEBUG", "HTTP connection error", &[
+            ("peer", peer),
+            ("error", format!("{}", e)),
+        ]),
+        Ok(()) => {},
+    }
+}
+
+// Count an HTTP connection closed by --http-header-timeout or --http-request-timeout
+fn record_http_timeout(kind: &'static str, peer: String) {
+    metrics::counter!(self_metric("geoclue_http_timeouts_total"), "kind" => kind).increment(1);
+    log("DEBUG", "Closed HTTP connection after timeout", &[
+        ("peer", peer),
+        ("kind", kind.to_string()),
+    ]);
+}
+
+// Whether a conditional scrape already has this version of the metrics page. As in
+// RFC 9110, If-None-Match takes precedence over If-Modified-Since.
+fn is_not_modified(headers: &hyper::HeaderMap, version: RenderVersion) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+        let etag = version.etag();
+        return if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    headers.get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| since.timestamp() >= version.modified_at)
+}
+
+// Header carrying the HMAC of the metrics body
+const METRICS_SIGNATURE: &str = "x-metrics-signature";
+
+// X-Metrics-Signature value for a metrics body: "sha256=" and the lowercase hex HMAC-SHA256
+fn metrics_signature(key: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+
+    let mut signature = String::with_capacity(7 + digest.len() * 2);
+    signature.push_str("sha256=");
+    for byte in digest {
+        write!(&mut signature, "{:02x}", byte).unwrap();
+    }
+    signature
+}
+
+// Add the ETag and Last-Modified headers describing a metrics page version
+fn add_version_headers(response: &mut Response<HttpBody>, version: RenderVersion) {
+    let last_modified = DateTime::from_timestamp(version.modified_at, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    for (name, value) in [(ETAG, version.etag()), (LAST_MODIFIED, last_modified)] {
+        if let Ok(value) = hyper::header::HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+}
+
+// Route a single HTTP request
+async fn handle_http_request(state: Arc<HttpState>, request: Request<Incoming>) -> Result<Response<HttpBody>, Infallible> {
+    let response = match (request.method(), request.uri().path(), &state.events) {
+        (&Method::GET, path, _) if path == state.metrics_path => {
+            state.scrapes.record_scrape();
+            state.scrapes.record_movement(*EXPORTED_POSITION.lock().unwrap());
+            let body = render_metrics(&state.handle);
+            if state.validate_output {
+                check_rendered_output(&body);
+            }
+            let version = state.scrapes.current_version();
+            let mut response = if is_not_modified(request.headers(), version) {
+                text_response(StatusCode::NOT_MODIFIED, "text/plain; version=0.0.4", String::new())
+            } else {
+                let signature = state.hmac_key.as_ref().map(|key| metrics_signature(key, body.as_bytes()));
+                let mut response = text_response(StatusCode::OK, "text/plain; version=0.0.4", body);
+                if let Some(Ok(value)) = signature.map(|signature| hyper::header::HeaderValue::from_str(&signature)) {
+                    response.headers_mut().insert(METRICS_SIGNATURE, value);
+                }
+                response
+            };
+            add_version_headers(&mut response, version);
+            response
+        },
+        (&Method::GET, "/events", Some(events)) => sse_response(events.subscribe()),
+        (&Method::GET, "/health", _) => {
+            text_response(StatusCode::OK, "application/json", health_json(GEOCLUE_CONNECTED.load(Ordering::Relaxed)))
+        },
+        (&Method::GET, "/ready", _) => ready_response(LOCATION_RECEIVED.load(Ordering::Relaxed)),
+        _ => text_response(StatusCode::NOT_FOUND, "text/plain", "Not Found\n".to_string()),
+    };
+
+    Ok(response)
+}
+
+// Record the GeoClue2 connection state for geoclue_connection_up and /health
+fn set_connection_up(connected: bool) {
+    GEOCLUE_CONNECTED.store(connected, Ordering::Relaxed);
+    metrics::gauge!(self_metric("geoclue_connection_up")).set(if connected { 1.0 } else { 0.0 });
+}
+
+// Liveness body for /health; the process answering is what makes it healthy
+fn health_json(geoclue_connected: bool) -> String {
+    serde_json::json!({
+        "status": "ok",
+        "geoclue_connected": geoclue_connected,
+    }).to_string()
+}
+
+// Readiness response for /ready: 503 until the first location update was received
+fn ready_response(location_received: bool) -> Response<HttpBody> {
+    if location_received {
+        text_response(StatusCode::OK, "application/json", serde_json::json!({"status": "ready"}).to_string())
+    } else {
+        text_response(StatusCode::SERVICE_UNAVAILABLE, "application/json", serde_json::json!({"status": "waiting_for_location"}).to_string())
+    }
+}
+
+// Minimal exposition served when the metrics page cannot be rendered
+const FALLBACK_METRICS: &str = "# Rendering metrics failed, serving minimal fallback output\n# TYPE up gauge\nup 1\n";
+
+// Render the metrics page, falling back to a minimal exposition if rendering panics
+// so the scrape target still appears up
+fn render_with_fallback(render: impl FnOnce() -> String) -> String {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(render)) {
+        Ok(body) => body,
+        Err(_) => {
+            metrics::counter!(self_metric("geoclue_render_failures_total")).increment(1);
+            log("ERROR", "Failed to render metrics, serving fallback output", &[]);
+            FALLBACK_METRICS.to_string()
+        }
+    }
+}
+
+// Count and log a rendered metrics page that is not valid Prometheus text format;
+// the page is still served as is
+fn check_rendered_output(body: &str) -> bool {
+    match validate_exposition(body) {
+        Ok(()) => true,
+        Err(e) => {
+            metrics::counter!(self_metric("geoclue_invalid_output_total")).increment(1);
+            log("WARN", "Rendered metrics output is not valid Prometheus text format", &[("error", e)]);
+            false
+        }
+    }
+}
+
+// Check a metrics page against the Prometheus text exposition format, reporting the first bad line
+fn validate_exposition(body: &str) -> std::result::Result<(), String> {
+    for (number, line) in body.lines().enumerate() {
+        validate_exposition_line(line).map_err(|e| format!("line {}: {}: {:?}", number + 1, e, line))?;
+    }
+    Ok(())
+}
+
+// Whether a name is a valid Prometheus metric name
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+// Whether a name is a valid Prometheus label name
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Check a single exposition line: blank, comment, HELP/TYPE or a sample
+fn validate_exposition_line(line: &str) -> std::result::Result<(), &'static str> {
+    if line.trim().is_empty() {
+        return Ok(());
+    }
+
+    if let Some(comment) = line.strip_prefix('#') {
+        let mut parts = comment.trim_start().splitn(3, ' ');
+        return match (parts.next(), parts.next(), parts.next()) {
+            (Some("HELP"), Some(name), _) if !is_metric_name(name) => Err("invalid metric name in HELP"),
+            (Some("TYPE"), Some(name), _) if !is_metric_name(name) => Err("invalid metric name in TYPE"),
+            (Some("TYPE"), Some(_), Some("counter" | "gauge" | "histogram" | "summary" | "untyped")) => Ok(()),
+            (Some("TYPE"), _, _) => Err("invalid TYPE line"),
+            _ => Ok(()),
+        };
+    }
+
+    let name_end = line.find(['{', ' ']).ok_or("missing sample value")?;
+    if !is_metric_name(&line[..name_end]) {
+        return Err("invalid metric name");
+    }
+
+    let mut rest = &line[name_end..];
+    if let Some(labels) = rest.strip_prefix('{') {
+        rest = validate_labels(labels)?;
+    }
+
+    let mut fields = rest.split_whitespace();
+    let value = fields.next().ok_or("missing sample value")?;
+    if !matches!(value, "+Inf" | "-Inf" | "NaN") && value.parse::<f64>().is_err() {
+        return Err("invalid sample value");
+    }
+    if let Some(timestamp) = fields.next() {
+        timestamp.parse::<i64>().map_err(|_| "invalid timestamp")?;
+    }
+    if fields.next().is_some() {
+        return Err("unexpected trailing data");
+    }
+    Ok(())
+}
+
+// Check a label set (after the opening brace), returning the text after the closing brace
+fn validate_labels(mut labels: &str) -> std::result::Result<&str, &'static str> {
+    loop {
+        if let Some(rest) = labels.strip_prefix('}') {
+            return Ok(rest);
+        }
+
+        let (name, rest) = labels.split_once("=\"").ok_or("invalid label")?;
+        if !is_label_name(name) {
+            return Err("invalid label name");
+        }
+
+        // Find the closing quote, skipping escaped characters
+        let mut chars = rest.char_indices();
+        let value_end = loop {
+            match chars.next() {
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, '\\' | '"' | 'n')) => {},
+                    _ => return Err("invalid escape in label value"),
+                },
+                Some((index, '"')) => break index,
+                Some(_) => {},
+                None => return Err("unterminated label value"),
+            }
+        };
+
+        labels = &rest[value_end + 1..];
+        labels = labels.strip_prefix(',').unwrap_or(labels);
+    }
+}
+
+// Build a response with a fixed text body
+fn text_response(status: StatusCode, content_type: &str, body: String) -> Response<HttpBody> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)).boxed_unsync())
+        .expect("static response parts are valid")
+}
+
+// Build a streaming Server-Sent Events response fed by the update broadcast channel
+fn sse_response(receiver: broadcast::Receiver<String>) -> Response<HttpBody> {
+    // The first keep-alive tick fires immediately, which flushes the headers to the client
+    let keep_alive = tokio::time::interval(SSE_KEEP_ALIVE);
+
+    let stream = futures_util::stream::unfold((receiver, keep_alive), |(mut receiver, mut keep_alive)| async move {
+        let chunk = tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(data) => format!("data: {}\n\n", data),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => format!(": skipped {} events\n\n", skipped),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            },
+            _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+        };
+
+        Some((Ok(Frame::data(Bytes::from(chunk))), (receiver, keep_alive)))
+    });
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(StreamBody::new(stream).boxed_unsync())
+        .expect("static response parts are valid")
+}
+
+// Serialize a processed update as the JSON payload of an SSE event
+fn location_event_json(readings: &[(&str, f64)], timestamp: i64) -> String {
+    let mut event = serde_json::Map::new();
+    event.insert("timestamp".to_string(), timestamp.into());
+    for (name, value) in readings {
+        event.insert(name.to_string(), (*value).into());
+    }
+    serde_json::Value::Object(event).to_string()
+}
+
+// Location properties in the order they appear in events file lines
+const EVENT_FIELDS: [&str; 6] = ["latitude", "longitude", "accuracy", "altitude", "speed", "heading"];
+
+// One events file line; properties that were not valid for this update are null
+fn events_file_line(readings: &[(&str, f64)], source: &str, timestamp: i64) -> String {
+    let mut event = serde_json::Map::new();
+    event.insert("timestamp".to_string(), timestamp.into());
+    for field in EVENT_FIELDS {
+        let value = readings.iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| serde_json::Value::from(*value))
+            .unwrap_or(serde_json::Value::Null);
+        event.insert(field.to_string(), value);
+    }
+    event.insert("source".to_string(), source.into());
+    serde_json::Value::Object(event).to_string()
+}
+
+impl EventsFile {
+    fn open(path: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(EventsFile { path, max_bytes, file, written })
+    }
+
+    // Path the current file is moved to on rotation
+    fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    // Append a line, rotating first if it would push the file past max_bytes
+    fn append(&mut self, line: &str) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let size = line.len() as u64 + 1;
+        if self.max_bytes > 0 && self.written > 0 && self.written + size > self.max_bytes {
+            std::fs::rename(&self.path, self.rotated_path())?;
+            *self = EventsFile::open(self.path.clone(), self.max_bytes)?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.written += size;
+        Ok(())
+    }
+}
+
+// Set the unit of the exported speed gauges
+fn set_speed_unit(unit: SpeedUnit) {
+    SPEED_UNIT.store(unit as u8, Ordering::Relaxed);
+}
+
+// Read the unit of the exported speed gauges
+fn speed_unit() -> SpeedUnit {
+    match SPEED_UNIT.load(Ordering::Relaxed) {
+        unit if unit == SpeedUnit::Kmh as u8 => SpeedUnit::Kmh,
+        unit if unit == SpeedUnit::Mph as u8 => SpeedUnit::Mph,
+        _ => SpeedUnit::Mps,
+    }
+}
+
+// Change the global log level; safe to call at any time, e.g. on SIGHUP
+fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Read the global log level
+fn current_log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        level if level == LogLevel::Trace as u8 => LogLevel::Trace,
+        level if level == LogLevel::Debug as u8 => LogLevel::Debug,
+        level if level == LogLevel::Warn as u8 => LogLevel::Warn,
+        level if level == LogLevel::Error as u8 => LogLevel::Error,
+        _ => LogLevel::Info,
+    }
+}
+
+// Set the global log format
+fn set_log_format(format: LogFormat) {
+    LOG_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+// Read the global log format
+#[cfg(not(feature = "tracing"))]
+fn log_format() -> LogFormat {
+    match LOG_FORMAT.load(Ordering::Relaxed) {
+        format if format == LogFormat::Json as u8 => LogFormat::Json,
+        _ => LogFormat::Text,
+    }
+}
+
+// Set the global log timezone
+fn set_log_timezone(timezone: LogTimezone) {
+    LOG_TIMEZONE.store(timezone as u8, Ordering::Relaxed);
+}
+
+// Read the global log timezone
+#[cfg(not(feature = "tracing"))]
+fn log_timezone() -> LogTimezone {
+    match LOG_TIMEZONE.load(Ordering::Relaxed) {
+        timezone if timezone == LogTimezone::Local as u8 => LogTimezone::Local,
+        _ => LogTimezone::Utc,
+    }
+}
+
+// Helper function to check if a message should be logged based on log level
+fn should_log(message_level: LogLevel) -> bool {
+    match current_log_level() {
+        LogLevel::Trace => true, // Trace logs everything
+        LogLevel::Debug => message_level != LogLevel::Trace, // Debug logs everything except Trace
+        LogLevel::Info => message_level != LogLevel::Debug && message_level != LogLevel::Trace, // Info logs Info, Warn, Error
+        LogLevel::Warn => message_level == LogLevel::Warn || message_level == LogLevel::Error, // Warn logs Warn, Error
+        LogLevel::Error => message_level == LogLevel::Error, // Error logs only Error
+    }
+}
+
+// Helper function to log in structured format
+fn log(level: &str, message: &str, fields: &[(&str, String)]) {
+    let message_level = match level {
+        "TRACE" => LogLevel::Trace,
+        "DEBUG" => LogLevel::Debug,
+        "INFO" => LogLevel::Info,
+        "WARN" => LogLevel::Warn,
+        "ERROR" => LogLevel::Error,
+        _ => LogLevel::Info, // Default to Info for unknown levels
+    };
+    
+    if !should_log(message_level) {
+        return;
+    }
+
+    write_log(level, message, fields);
+}
+
+// Write a log line without applying the level filter
+#[cfg(not(feature = "tracing"))]
+fn write_log(level: &str, message: &str, fields: &[(&str, String)]) {
+    let timestamp = match log_timezone() {
+        LogTimezone::Utc => Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        LogTimezone::Local => Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+    };
+    
+    let line = format_log_line(log_format(), &timestamp, level, message, fields);
+    if LOG_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+// Forward a log line to the tracing subscriber; the key=value fields travel as one field
+// until call sites are converted to the tracing macros
+#[cfg(feature = "tracing")]
+fn write_log(level: &str, message: &str, fields: &[(&str, String)]) {
+    let fields = fields.iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match level {
+        "TRACE" => tracing::trace!(fields = %fields, "{}", message),
+        "DEBUG" => tracing::debug!(fields = %fields, "{}", message),
+        "WARN" => tracing::warn!(fields = %fields, "{}", message),
+        "ERROR" => tracing::error!(fields = %fields, "{}", message),
+        _ => tracing::info!(fields = %fields, "{}", message),
+    }
+}
+
+// Install the tracing subscriber. It passes everything up to --log-level (TRACE with
+// --trace-dbus); log() still applies the reloadable level filter on top.
+#[cfg(feature = "tracing")]
+fn init_tracing(log_level: LogLevel, trace_dbus: bool) {
+    let max_level = match if trace_dbus { LogLevel::Trace } else { log_level } {
+        LogLevel::Trace => tracing::Level::TRACE,
+        LogLevel::Debug => tracing::Level::DEBUG,
+        LogLevel::Info => tracing::Level::INFO,
+        LogLevel::Warn => tracing::Level::WARN,
+        LogLevel::Error => tracing::Level::ERROR,
+    };
+    let subscriber = tracing_subscriber::fmt().with_max_level(max_level);
+    if LOG_TO_STDERR.load(Ordering::Relaxed) {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+}
+
+// Render a single log line in the requested format
+#[cfg_attr(feature = "tracing", allow(dead_code))]
+fn format_log_line(format: LogFormat, timestamp: &str, level: &str, message: &str, fields: &[(&str, String)]) -> String {
+    match format {
+        LogFormat::Text => {
+            let mut log_str = String::new();
+            write!(&mut log_str, "timestamp=\"{}\" level={} message=\"{}\"", timestamp, level, message).unwrap();
+            
+            for (key, value) in fields {
+                write!(&mut log_str, " {}={}", key, value).unwrap();
+            }
+
+            log_str
+        },
+        LogFormat::Json => {
+            // Insert the fixed keys last so a field can never shadow them
+            let mut entry = serde_json::Map::new();
+            for (key, value) in fields {
+                entry.insert(key.to_string(), value.clone().into());
+            }
+            entry.insert("timestamp".to_string(), timestamp.into());
+            entry.insert("level".to_string(), level.into());
+            entry.insert("message".to_string(), message.into());
+
+            serde_json::Value::Object(entry).to_string()
+        }
+    }
+}
+
+impl UpdateCycle {
+    fn next() -> Self {
+        let id = NEXT_CYCLE_ID.fetch_add(1, Ordering::Relaxed);
+        Self { id: format!("{:06x}", id) }
+    }
+
+    // Prepend the correlation ID to a log call's fields
+    fn fields<'a>(&self, fields: &[(&'a str, String)]) -> Vec<(&'a str, String)> {
+        let mut tagged = Vec::with_capacity(fields.len() + 1);
+        tagged.push(("cycle", self.id.clone()));
+        tagged.extend_from_slice(fields);
+        tagged
+    }
+
+    fn trace(&self, message: &str, fields: &[(&str, String)]) {
+        log("TRACE", message, &self.fields(fields));
+    }
+}
+
+// Lowest value accepted by set_gauge_if_valid; GeoClue2 reports -DBL_MAX for an unknown altitude
+const MIN_VALID_METRIC_VALUE: f64 = -1.7e308;
+
+// Reason set_gauge_if_valid rejected a value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InvalidMetric {
+    // GeoClue2's -1 "unknown" sentinel
+    Unavailable,
+    NotANumber,
+    OutOfRange { min: f64, max: f64 },
+    UnknownMetric,
+}
+
+impl InvalidMetric {
+    // Value of the reason label on geoclue_rejected_values_total
+    fn reason(&self) -> &'static str {
+        match self {
+            InvalidMetric::Unavailable => "unavailable",
+            InvalidMetric::NotANumber => "nan",
+            InvalidMetric::OutOfRange { .. } => "out_of_range",
+            InvalidMetric::UnknownMetric => "unknown_metric",
+        }
+    }
+}
+
+// Export the --default-position coordinates until a real fix replaces them
+fn seed_default_position(position: Option<(f64, f64)>) {
+    let Some((lat, lon)) = position else {
+        metrics::gauge!("geoclue_using_default_position").set(0.0);
+        return;
+    };
+
+    if !is_metric_disabled("latitude") {
+        metrics::gauge!("geoclue_latitude").set(lat);
+        EXPORTED_VALUES.record("latitude", lat);
+    }
+    if !is_metric_disabled("longitude") {
+        metrics::gauge!("geoclue_longitude").set(lon);
+        EXPORTED_VALUES.record("longitude", lon);
+    }
+    metrics::gauge!("geoclue_using_default_position").set(1.0);
+}
+
+// Mark the coordinate gauges as holding a real fix from here on
+fn clear_default_position() {
+    metrics::gauge!("geoclue_using_default_position").set(0.0);
+}
+
+// Inclusive range of accepted values for one location metric, from --valid-range
+#[derive(Debug, Clone, PartialEq)]
+struct ValueRange {
+    metric: String,
+    min: f64,
+    max: f64,
+}
+
+// Value bounds applied to location metrics on top of the sentinel checks. The default
+// has no ranges, which accepts every value that is not a sentinel.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ValidationConfig {
+    ranges: Vec<ValueRange>,
+}
+
+impl ValidationConfig {
+    fn from_args(args: &Args) -> Self {
+        ValidationConfig { ranges: args.valid_ranges.clone() }
+    }
+
+    // Range configured for a metric; a later --valid-range for the same metric wins
+    fn range(&self, metric_name: &str) -> Option<(f64, f64)> {
+        self.ranges.iter().rev()
+            .find(|range| range.metric == metric_name)
+            .map(|range| (range.min, range.max))
+    }
+}
+
+// Why a location value cannot be exported: a sentinel value (-1, or extreme negative for
+// altitude), NaN, a value outside its --valid-range, or a negative speed or heading, which
+// GeoClue2 uses for unknown
+fn invalid_metric_value(metric_name: &str, value: f64, validation: &ValidationConfig) -> Option<InvalidMetric> {
+    if value == -1.0 {
+        Some(InvalidMetric::Unavailable)
+    } else if value.is_nan() {
+        Some(InvalidMetric::NotANumber)
+    } else if value <= MIN_VALID_METRIC_VALUE {
+        Some(InvalidMetric::OutOfRange { min: MIN_VALID_METRIC_VALUE, max: f64::MAX })
+    } else if let Some((min, max)) = validation.range(metric_name).filter(|(min, max)| value < *min || value > *max) {
+        Some(InvalidMetric::OutOfRange { min, max })
+    } else if matches!(metric_name, "speed" | "heading") && value < 0.0 {
+        Some(InvalidMetric::Unavailable)
+    } else {
+        None
+    }
+}
+
+// Whether an update's accepted values include a coordinate, which is what makes it fresh
+// location data rather than a lone accuracy or altitude
+fn has_accepted_coordinate(valid_readings: &[(&str, f64)]) -> bool {
+    valid_readings.iter().any(|(name, _)| matches!(*name, "latitude" | "longitude"))
+}
+
+// Values GeoClue2 may leave unknown in an otherwise valid fix
+const OPTIONAL_METRICS: [&str; 3] = ["altitude", "speed", "heading"];
+
+// Publish whether an optional value was known in the latest fix. Its value gauge is not
+// touched when it is not, so it keeps the last known value.
+fn set_availability_gauge(metric_name: &str, available: bool) {
+    let value = if available { 1.0 } else { 0.0 };
+    match metric_name {
+        "altitude" => metrics::gauge!("geoclue_altitude_available").set(value),
+        "speed" => metrics::gauge!("geoclue_speed_available").set(value),
+        "heading" => metrics::gauge!("geoclue_heading_available").set(value),
+        _ => {}
+    }
+}
+
+// Set a gauge only if the value is valid. `reported` is what GeoClue2 sent and is what
+// gets validated; `value`, its coarsened form, is exported in its place, so a real
+// coordinate rounded onto the -1 sentinel is not mistaken for an unknown one.
+fn set_gauge_if_valid(metric_name: &str, reported: f64, value: f64, validation: &ValidationConfig) -> Result<(), InvalidMetric> {
+    if let Some(invalid) = invalid_metric_value(metric_name, reported, validation) {
+        log("DEBUG", &format!("Skipping invalid metric {}", metric_name), &[
+            ("metric", metric_name.to_string()), 
+            ("value", reported.to_string()),
+            ("reason", invalid.reason().to_string()),
+        ]);
+        return Err(invalid);
+    }
+
+    // Disabled metrics are validated like the rest but never exported
+    if is_metric_disabled(metric_name) {
+        return Ok(());
+    }
+    
+    EXPORTED_VALUES.record(metric_name, value);
+
+    // Set the gauge with the appropriate name - use static string literals for metrics
+    match metric_name {
+        "latitude" => metrics::gauge!("geoclue_latitude").set(value),
+        "longitude" => metrics::gauge!("geoclue_longitude").set(value),
+        "accuracy" => metrics::gauge!("geoclue_accuracy").set(value),
+        "altitude" => metrics::gauge!("geoclue_altitude").set(value),
+        "speed" => {
+            let unit = speed_unit();
+            metrics::gauge!(unit.metric_name()).set(unit.convert(value));
+        }
+        "heading" => metrics::gauge!("geoclue_heading").set(value),
+        _ => {
+            log("WARN", &format!("Unknown metric name: {}", metric_name), &[]);
+            // Don't try to use a dynamic name with the gauge macro - it needs static strings
+            return Err(InvalidMetric::UnknownMetric);
+        }
+    }
+    
+    Ok(())
+}
+
+// Latest exported value of each location metric, indexed like LOCATION_METRICS, for
+// exporters that do not read the Prometheus recorder
+struct ExportedValues {
+    values: Mutex<[Option<f64>; LOCATION_METRICS.len()]>,
+}
+
+impl ExportedValues {
+    const fn new() -> Self {
+        ExportedValues { values: Mutex::new([None; LOCATION_METRICS.len()]) }
+    }
+
+    fn record(&self, metric_name: &str, value: f64) {
+        if let Some(index) = LOCATION_METRICS.iter().position(|metric| *metric == metric_name) {
+            self.values.lock().unwrap()[index] = Some(value);
+        }
+    }
+
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    fn get(&self, metric_name: &str) -> Option<f64> {
+        let index = LOCATION_METRICS.iter().position(|metric| *metric == metric_name)?;
+        self.values.lock().unwrap()[index]
+    }
+}
+
+// Values behind the geoclue_<metric> gauges, mirrored for the OTLP export
+static EXPORTED_VALUES: ExportedValues = ExportedValues::new();
+
+// Set one location gauge as in set_gauge_if_valid, counting rejected values; returns
+// whether the value was accepted
+fn set_location_gauge(metric_name: &str, reported: f64, value: f64, validation: &ValidationConfig) -> bool {
+    let Err(invalid) = set_gauge_if_valid(metric_name, reported, value, validation) else {
+        return true;
+    };
+
+    metrics::counter!(
+        self_metric("geoclue_rejected_values_total"),
+        "metric" => metric_name.to_string(),
+        "reason" => invalid.reason()
+    ).increment(1);
+
+    if invalid == InvalidMetric::NotANumber && matches!(metric_name, "latitude" | "longitude") {
+        metrics::counter!("geoclue_nan_coordinates_total").increment(1);
+        if !NAN_COORDINATES_WARNED.swap(true, Ordering::Relaxed) {
+            log("WARN", "GeoClue2 reported NaN coordinates, the location backend is misbehaving", &[
+                ("metric", metric_name.to_string()),
+            ]);
+        }
+    }
+    false
+}
+
+// Unix timestamp for *_timestamp_seconds gauges, corrected by the configured clock offset
+fn corrected_timestamp(now: DateTime<Utc>, offset_seconds: f64) -> f64 {
+    now.timestamp() as f64 + offset_seconds
+}
+
+// Determine the hour-of-day bucket for an update received at the given time
+fn update_hour(timestamp: DateTime<Utc>, timezone: LogTimezone) -> u32 {
+    match timezone {
+        LogTimezone::Utc => timestamp.hour(),
+        LogTimezone::Local => timestamp.with_timezone(&Local).hour(),
+    }
+}
+
+// Initialize all 24 hourly buckets so they appear in metrics output
+fn init_hourly_metrics() {
+    for hour in 0..24 {
+        metrics::counter!("geoclue_updates_by_hour", "hour" => hour.to_string()).absolute(0);
+    }
+}
+
+// Record a location update in its hour-of-day bucket
+fn record_hourly_update(timestamp: DateTime<Utc>, timezone: LogTimezone) {
+    let hour = update_hour(timestamp, timezone);
+    metrics::counter!("geoclue_updates_by_hour", "hour" => hour.to_string()).increment(1);
+}
+
+// Format a single value as a Graphite plaintext line
+fn graphite_line(prefix: &str, name: &str, value: f64, timestamp: i64) -> String {
+    if prefix.is_empty() {
+        format!("{} {} {}\n", name, value, timestamp)
+    } else {
+        format!("{}.{} {} {}\n", prefix, name, value, timestamp)
+    }
+}
+
+impl GraphiteSink {
+    fn spawn(address: String, prefix: String) -> Self {
+        let (sender, receiver) = mpsc::channel(64);
+        tokio::spawn(run_graphite_sink(address, receiver));
+        GraphiteSink { prefix, sender }
+    }
+
+    // Queue a batch of values for delivery without blocking the update loop
+    fn send(&self, values: &[(&str, f64)], timestamp: i64) {
+        let lines = values.iter()
+            .map(|(name, value)| graphite_line(&self.prefix, name, *value, timestamp))
+            .collect();
+
+        if self.sender.try_send(lines).is_err() {
+            metrics::counter!(self_metric("geoclue_graphite_errors_total")).increment(1);
+            log("WARN", "Graphite queue full, dropping values", &[]);
+        }
+    }
+}
+
+impl UpdateCommand {
+    fn new(command: String, concurrency: u32) -> Self {
+        UpdateCommand { command, slots: Arc::new(tokio::sync::Semaphore::new(concurrency as usize)) }
+    }
+
+    // Start the command in the background; when every slot is busy the update is dropped
+    // instead of queued, so a slow command never holds up the update loop
+    fn run(&self, readings: &[(&str, f64)], source: &str, timestamp: i64) -> Option<tokio::task::JoinHandle<()>> {
+        let Ok(slot) = self.slots.clone().try_acquire_owned() else {
+            metrics::counter!(self_metric("geoclue_update_command_dropped_total")).increment(1);
+            log("WARN", "Previous update command still running, dropping invocation", &[]);
+            return None;
+        };
+
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg(&self.command)
+            .envs(update_command_env(readings, source, timestamp))
+            .stdin(std::process::Stdio::null());
+
+        Some(tokio::spawn(async move {
+            let _slot = slot;
+            match command.status().await {
+                Ok(status) if status.success() => {},
+                Ok(status) => log("WARN", "Update command failed", &[("status", status.to_string())]),
+                Err(e) => log("WARN", "Failed to run update command", &[("error", format!("{}", e))]),
+            }
+        }))
+    }
+}
+
+// Environment passed to --on-update-command; properties that were not valid for this update are omitted
+fn update_command_env(readings: &[(&str, f64)], source: &str, timestamp: i64) -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = readings.iter()
+        .map(|(name, value)| {
+            let key = match *name {
+                "latitude" => "LAT".to_string(),
+                "longitude" => "LON".to_string(),
+                other => other.to_ascii_uppercase(),
+            };
+            (format!("GEOCLUE_{}", key), value.to_string())
+        })
+        .collect();
+    env.push(("GEOCLUE_SOURCE".to_string(), source.to_string()));
+    env.push(("GEOCLUE_TIMESTAMP".to_string(), timestamp.to_string()));
+    env
+}
+
+// Background task that writes queued lines to the Graphite relay, reconnecting as needed
+async fn run_graphite_sink(address: String, mut receiver: mpsc::Receiver<Vec<String>>) {
+    let mut stream: Option<TcpStream> = None;
+    let mut buffer: VecDeque<String> = VecDeque::new();
+
+    while let Some(lines) = receiver.recv().await {
+        buffer.extend(lines);
+        while buffer.len() > GRAPHITE_BUFFER_LINES {
+            buffer.pop_front();
+        }
+
+        if stream.is_none() {
+            match TcpStream::connect(&address).await {
+                Ok(connected) => {
+                    log("INFO", "Connected to Graphite relay", &[("address", address.clone())]);
+                    stream = Some(connected);
+                },
+                Err(e) => {
+                    metrics::counter!(self_metric("geoclue_graphite_errors_total")).increment(1);
+                    log("WARN", "Failed to connect to Graphite relay", &[
+                        ("address", address.clone()),
+                        ("error", format!("{}", e)),
+                        ("buffered_lines", buffer.len().to_string()),
+                    ]);
+                    continue;
+                }
+            }
+        }
+
+        if let Some(connected) = stream.as_mut() {
+            let payload: String = buffer.iter().map(String::as_str).collect();
+            match connected.write_all(payload.as_bytes()).await {
+                Ok(()) => buffer.clear(),
+                Err(e) => {
+                    metrics::counter!(self_metric("geoclue_graphite_errors_total")).increment(1);
+                    log("WARN", "Failed to write to Graphite relay", &[
+                        ("address", address.clone()),
+                        ("error", format!("{}", e)),
+                        ("buffered_lines", buffer.len().to_string()),
+                    ]);
+                    stream = None;
+                }
+            }
+        }
+    }
+}
+
+// Decide whether recent accuracy samples are plausible for the requested level.
+// Returns None until enough samples have been collected to judge.
+fn accuracy_expectation_met(level: AccuracyLevel, samples: &[f64]) -> Option<bool> {
+    let Some(limit) = level.expected_max_accuracy_meters() else {
+        return Some(true);
+    };
+
+    if samples.len() < ACCURACY_EXPECTATION_SAMPLES {
+        return None;
+    }
+
+    // Only a consistently worse accuracy counts as not meeting the expectation
+    Some(samples.iter().any(|accuracy| *accuracy <= limit))
+}
+
+// Record an accuracy sample and update the expectation and session gauges
+fn record_accuracy_sample(tracker: &mut UpdateTracker, level: AccuracyLevel, accuracy: f64) {
+    tracker.session_accuracy.record(accuracy);
+    metrics::gauge!("geoclue_session_accuracy_min").set(tracker.session_accuracy.min);
+    metrics::gauge!("geoclue_session_accuracy_max").set(tracker.session_accuracy.max);
+    if let Some(average) = tracker.session_accuracy.average() {
+        metrics::gauge!("geoclue_session_accuracy_avg").set(average);
+    }
+
+    if tracker.accuracy_samples.len() == ACCURACY_EXPECTATION_SAMPLES {
+        tracker.accuracy_samples.pop_front();
+    }
+    tracker.accuracy_samples.push_back(accuracy);
+
+    let samples: Vec<f64> = tracker.accuracy_samples.iter().copied().collect();
+    match accuracy_expectation_met(level, &samples) {
+        Some(true) => metrics::gauge!("geoclue_accuracy_expectation_met").set(1.0),
+        Some(false) => {
+            metrics::gauge!("geoclue_accuracy_expectation_met").set(0.0);
+            if !tracker.accuracy_warning_logged {
+                tracker.accuracy_warning_logged = true;
+                log("WARN", "Location accuracy is consistently worse than the requested level, the hardware may not support it", &[
+                    ("accuracy_level", format!("{:?}", level)),
+                    ("expected_max_meters", level.expected_max_accuracy_meters().unwrap_or_default().to_string()),
+                    ("observed_meters", accuracy.to_string()),
+                ]);
+            }
+        },
+        None => {}
+    }
+}
+
+// WGS84 semi-major axis in meters, used as the sphere radius by Web Mercator
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+
+// WGS84 flattening of the reference ellipsoid (1 / 298.257223563)
+const WGS84_FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+// Convert WGS84 latitude/longitude in degrees and ellipsoidal height in meters
+// to earth-centered, earth-fixed (ECEF) X/Y/Z coordinates in meters
+fn wgs84_to_ecef(lat: f64, lon: f64, alt: f64) -> (f64, f64, f64) {
+    let eccentricity_squared = WGS84_FLATTENING * (2.0 - WGS84_FLATTENING);
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+
+    // Radius of curvature in the prime vertical
+    let n = WGS84_SEMI_MAJOR_AXIS / (1.0 - eccentricity_squared * sin_lat * sin_lat).sqrt();
+
+    let x = (n + alt) * cos_lat * cos_lon;
+    let y = (n + alt) * cos_lat * sin_lon;
+    let z = (n * (1.0 - eccentricity_squared) + alt) * sin_lat;
+    (x, y, z)
+}
+
+// Project WGS84 latitude/longitude in degrees to the given EPSG reference system
+fn project_wgs84(epsg: u32, lat: f64, lon: f64) -> Option<(f64, f64)> {
+    match epsg {
+        // Web Mercator (EPSG:3857)
+        3857 => {
+            let x = WGS84_SEMI_MAJOR_AXIS * lon.to_radians();
+            let y = WGS84_SEMI_MAJOR_AXIS * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+            Some((x, y))
+        },
+        _ => None,
+    }
+}
+
+// First delay between system bus connection attempts, doubled after each failure
+const BUS_RETRY_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Longest delay between system bus connection attempts
+const BUS_RETRY_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Run connect until it succeeds or timeout has elapsed, backing off between attempts,
+// and return the last error once time is up
+async fn connect_with_retry<T, E, F, Fut>(
+    timeout: std::time::Duration,
+    initial_delay: std::time::Duration,
+    mut connect: F
+) -> std::result::Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<T, E>>,
+{
+    let deadline = std::time::Instant::now() + timeout;
+    let mut delay = initial_delay;
+    let mut attempt = 1u32;
+
+    loop {
+        let error = match connect().await {
+            Ok(value) => {
+                if attempt > 1 {
+                    log("INFO", "Connected to DBus system bus after retrying", &[("attempts", attempt.to_string())]);
+                }
+                return Ok(value);
+            },
+            Err(error) => error,
+        };
+
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero() {
+            return Err(error);
+        }
+
+        let wait = delay.min(remaining);
+        log("WARN", "DBus system bus not available yet, retrying", &[
+            ("error", error.to_string()),
+            ("attempt", attempt.to_string()),
+            ("retry_in_ms", wait.as_millis().to_string()),
+        ]);
+        tokio::time::sleep(wait).await;
+        delay = (delay * 2).min(BUS_RETRY_MAX_DELAY);
+        attempt += 1;
+    }
+}
+
+// Connect to the system bus, waiting up to --connect-timeout for it to come up
+async fn connect_system_bus(args: &Args) -> zbus::Result<Connection> {
+    connect_with_retry(std::time::Duration::from_secs(args.connect_timeout), BUS_RETRY_INITIAL_DELAY, Connection::system).await
+}
+
+// Function to establish GeoClue2 connection and setup client
+async fn setup_geoclue_connection(args: &Args) -> Result<GeoClueConnection> {
+    // Create a shared connection
+    let connection = Arc::new(connect_system_bus(args).await?);
+    log("INFO", "Connected to DBus system bus", &[]);
+
+    let names = GeoClueNames::from_args(args);
+
+    // Get the manager proxy
+    let manager = zbus::Proxy::new(
+        &connection, 
+        names.service.as_str(), 
+        names.manager_path.as_str(), 
+        names.manager_interface()
+    ).await?;
+    log("INFO", "Created GeoClue2 Manager proxy", &[]);
+
+    // GeoClue2 exposes the accuracy it can deliver on the Manager, not on each client
+    let available_accuracy_level = record_available_accuracy_level(dbus_call("Get.AvailableAccuracyLevel", manager.get_property::<u32>("AvailableAccuracyLevel")).await, args.accuracy_level.into());
+    let accuracy_levels = Arc::new(Mutex::new(AccuracyLevels {
+        requested: args.accuracy_level.into(),
+        available: available_accuracy_level,
+    }));
+
+    // The primary client runs at --accuracy-level, extra clients at their own levels
+    let levels = std::iter::once(args.accuracy_level).chain(args.extra_accuracy_levels.iter().copied());
+    let mut clients = Vec::new();
+
+    let configured: Result<()> = async {
+        for (index, level) in levels.enumerate() {
+            // GetClient always returns the same client for a connection, so extra clients use CreateClient
+            let method = if index == 0 { "GetClient" } else { "CreateClient" };
+            let client_path: zvariant::OwnedObjectPath = dbus_call(method, manager.call::<_, _, zvariant::OwnedObjectPath>(
+                method, 
+                &()
+            )).await.with_context(|| format!("calling {} on {}", method, names.manager_path))?;
+            
+            let label = client_label(level);
+            log("INFO", "Got client path", &[
+                ("path", format!("{}", client_path)),
+                ("client", label.clone()),
+            ]);
+
+            configure_client(&connection, &names, &client_path, args, level.into()).await?;
+            if index == 0 {
+                set_accuracy_level_gauges(level.into(), available_accuracy_level);
+            }
+            clients.push(GeoClueClient { label, path: client_path });
+        }
+        Ok(())
+    }.await;
+
+    // GeoClue2 keeps clients running until told otherwise, so stop those already started
+    if let Err(e) = configured {
+        for geoclue_client in &clients {
+            if let Err(e) = stop_client(&connection, &names, &geoclue_client.path).await {
+                log("WARN", "Failed to stop GeoClue2 client", &[
+                    ("client", geoclue_client.label.clone()),
+                    ("error", format!("{:#}", e)),
+                ]);
+            }
+        }
+        return Err(e);
+    }
+
+    // Spawned last so no early return can detach it along with its connection
+    let accuracy_watch = spawn_available_accuracy_watch(connection.clone(), names.clone(), accuracy_levels.clone());
+    set_connection_up(true);
+
+    Ok(GeoClueConnection {
+        connection,
+        clients,
+        names,
+        accuracy_levels,
+        accuracy_watch,
+        client_stopped: Arc::new(AtomicBool::new(false)),
+    })
+}
+
+// Publish AvailableAccuracyLevel, or -1 when this GeoClue2 version does not provide it
+fn record_available_accuracy_level(available: zbus::Result<u32>, requested: AccuracyLevel) -> Option<u32> {
+    match available {
+        Ok(level) => {
+            metrics::gauge!("geoclue_available_accuracy_level").set(level as f64);
+            if level < requested as u32 {
+                log("WARN", "GeoClue2 cannot deliver the requested accuracy level", &[
+                    ("requested_level", (requested as u32).to_string()),
+                    ("available_level", level.to_string()),
+                ]);
+            }
+            Some(level)
+        },
+        Err(e) => {
+            metrics::gauge!("geoclue_available_accuracy_level").set(-1.0);
+            log("WARN", "Failed to read AvailableAccuracyLevel, GeoClue2 may be too old", &[
+                ("error", format!("{}", e)),
+            ]);
+            None
+        }
+    }
+}
+
+// Follow AvailableAccuracyLevel changes signalled by the Manager, e.g. when the user
+// toggles location services, for as long as the connection lives
+fn spawn_available_accuracy_watch(
+    connection: Arc<Connection>,
+    names: GeoClueNames,
+    levels: Arc<Mutex<AccuracyLevels>>
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let subscription = async {
+            zbus::fdo::PropertiesProxy::builder(&connection)
+                .destination(names.service.as_str())?
+                .path(names.manager_path.as_str())?
+                .build().await?
+                .receive_properties_changed().await
+        };
+        let mut changes = match subscription.await {
+            Ok(changes) => changes,
+            Err(e) => {
+                log("WARN", "Failed to subscribe to AvailableAccuracyLevel changes", &[("error", format!("{}", e))]);
+                return;
+            }
+        };
+
+        let manager_interface = names.manager_interface();
+        while let Some(signal) = changes.next().await {
+            let Ok(signal) = signal.args() else {
+                continue;
+            };
+            if signal.interface_name.as_str() != manager_interface {
+                continue;
+            }
+            if let Some(available) = changed_available_accuracy_level(&signal.changed_properties) {
+                handle_available_accuracy_change(&levels, available);
+            }
+        }
+    })
+}
+
+// New AvailableAccuracyLevel carried by a PropertiesChanged signal, if it changed
+fn changed_available_accuracy_level(changed: &std::collections::HashMap<&str, zvariant::Value<'_>>) -> Option<u32> {
+    changed.get("AvailableAccuracyLevel")?.downcast_ref::<u32>().ok()
+}
+
+// Apply a changed AvailableAccuracyLevel to the gauges, counting actual changes
+fn handle_available_accuracy_change(levels: &Mutex<AccuracyLevels>, available: u32) {
+    let mut levels = levels.lock().unwrap();
+    if levels.available == Some(available) {
+        return;
+    }
+
+    log("INFO", "GeoClue2 available accuracy level changed", &[
+        ("from", levels.available.map(|level| level.to_string()).unwrap_or_else(|| "unknown".to_string())),
+        ("to", available.to_string()),
+    ]);
+    levels.available = Some(available);
+    metrics::counter!("geoclue_available_accuracy_changes_total").increment(1);
+    metrics::gauge!("geoclue_available_accuracy_level").set(available as f64);
+    set_accuracy_level_gauges(levels.requested, levels.available);
+}
+
+// Publish the accuracy level asked for with --accuracy-level, before any fallback or capping
+fn record_requested_accuracy_level(level: AccuracyLevel) {
+    metrics::gauge!("geoclue_requested_accuracy_level").set(level as u32 as f64);
+}
+
+// Publish the level the primary client requests and the level it effectively gets,
+// which GeoClue2 caps at its AvailableAccuracyLevel
+fn set_accuracy_level_gauges(requested: AccuracyLevel, available: Option<u32>) {
+    let requested = requested as u32;
+    let effective = available.map_or(requested, |available| requested.min(available));
+    metrics::gauge!("geoclue_active_accuracy_level").set(requested as f64);
+    metrics::gauge!("geoclue_effective_accuracy_level").set(effective as f64);
+}
+
+// Count a DBus call's outcome in geoclue_dbus_calls_total and return its result unchanged.
+// Methods are labelled by name, property accesses as Get.<Property> / Set.<Property>.
+async fn dbus_call<T: std::fmt::Debug, E: std::fmt::Display>(
+    method: impl Into<metrics::SharedString>,
+    call: impl std::future::Future<Output = std::result::Result<T, E>>
+) -> std::result::Result<T, E> {
+    dbus_call_with_args(method, String::new(), call).await
+}
+
+// dbus_call for calls that take arguments, summarised in the --trace-dbus log
+async fn dbus_call_with_args<T: std::fmt::Debug, E: std::fmt::Display>(
+    method: impl Into<metrics::SharedString>,
+    args: String,
+    call: impl std::future::Future<Output = std::result::Result<T, E>>
+) -> std::result::Result<T, E> {
+    let method = method.into();
+    let started = std::time::Instant::now();
+    let result = call.await;
+
+    if TRACE_DBUS.load(Ordering::Relaxed) {
+        let outcome = result.as_ref().map(|value| format!("{:?}", value)).map_err(|e| e.to_string());
+        write_log("TRACE", "DBus call", &dbus_trace_fields(&method, &args, outcome, started.elapsed()));
+    }
+
+    let outcome = if result.is_ok() { "success" } else { "error" };
+    metrics::counter!(self_metric("geoclue_dbus_calls_total"), "method" => method, "result" => outcome).increment(1);
+    result
+}
+
+// Fields of a --trace-dbus log line
+fn dbus_trace_fields(
+    method: &str,
+    args: &str,
+    outcome: std::result::Result<String, String>,
+    elapsed: std::time::Duration
+) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("dbus", "true".to_string()),
+        ("method", method.to_string()),
+        ("args", args.to_string()),
+    ];
+    match outcome {
+        Ok(value) => {
+            fields.push(("result", "success".to_string()));
+            fields.push(("value", value));
+        },
+        Err(error) => {
+            fields.push(("result", "error".to_string()));
+            fields.push(("error", error));
+        }
+    }
+    fields.push(("elapsed_us", elapsed.as_micros().to_string()));
+    fields
+}
+
+// Label identifying a client by the accuracy level it requests
+fn client_label(level: AccuracyLevelArg) -> String {
+    level.to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_else(|| format!("{:?}", level).to_lowercase())
+}
+
+// Make an arbitrary string safe to use as a label value by replacing control characters
+fn sanitize_label_value(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect()
+}
+
+// Publish the geoclue_client_info info-metric for a configured client
+fn set_client_info(client_path: &str, desktop_id: &str) {
+    metrics::gauge!(
+        "geoclue_client_info",
+        "client_path" => sanitize_label_value(client_path),
+        "desktop_id" => sanitize_label_value(desktop_id)
+    ).set(1.0);
+}
+
+// Point geoclue_location_info at a client's new Location object
+fn set_location_info(client_path: &str, location_path: &str) {
+    LOCATION_INFO.lock().unwrap().insert(client_path.to_string(), location_path.to_string());
+}
+
+// geoclue_location_info in text format, one series per client; empty before the first update.
+// Object paths only hold [A-Za-z0-9_/], so the label values need no escaping.
+fn location_info_lines() -> String {
+    let info = LOCATION_INFO.lock().unwrap();
+    if info.is_empty() {
+        return String::new();
+    }
+
+    let help = METRIC_DESCRIPTIONS.iter()
+        .find(|metric| metric.name == "geoclue_location_info")
+        .map_or("", |metric| metric.help);
+    let mut lines = format!("# HELP geoclue_location_info {}\n# TYPE geoclue_location_info gauge\n", help);
+    for (client_path, location_path) in info.iter() {
+        lines.push_str(&format!(
+            "geoclue_location_info{{location_path=\"{}\",client_path=\"{}\"}} 1\n",
+            location_path, client_path
+        ));
+    }
+    lines
+}
+
+// Set the client properties and start it
+async fn configure_client(
+    connection: &Connection,
+    names: &GeoClueNames,
+    client_path: &zvariant::OwnedObjectPath,
+    args: &Args,
+    accuracy_level: AccuracyLevel
+) -> Result<()> {
+    // Create client proxy
+    let client = zbus::Proxy::new(
+        connection, 
+        names.service.as_str(), 
+        client_path, 
+        names.client_interface()
+    ).await?;
+    
+    // Set client properties
+    dbus_call_with_args("Set.DesktopId", args.desktop_id.clone(), client.set_property("DesktopId", &args.desktop_id)).await?;
+    log("INFO", "Set client desktop ID", &[("desktop_id", args.desktop_id.clone())]);
+    set_client_info(client_path.as_str(), &args.desktop_id);
+    
+    // Set distance threshold (in meters)
+    dbus_call_with_args("Set.DistanceThreshold", args.distance_threshold.to_string(), client.set_property("DistanceThreshold", &args.distance_threshold)).await?;
+    log("INFO", "Set distance threshold", &[("threshold_meters", args.distance_threshold.to_string())]);
+    
+    // Set time threshold (in seconds)
+    dbus_call_with_args("Set.TimeThreshold", args.time_threshold.to_string(), client.set_property("TimeThreshold", &args.time_threshold)).await?;
+    log("INFO", "Set time threshold", &[("threshold_seconds", args.time_threshold.to_string())]);
+
+    // GeoClue2 may clamp or ignore the thresholds, so publish what it actually applied
+    record_applied_threshold("geoclue_distance_threshold_meters", "DistanceThreshold", args.distance_threshold,
+        read_client_u32_property(connection, names, client_path, "DistanceThreshold").await);
+    record_applied_threshold("geoclue_time_threshold_seconds", "TimeThreshold", args.time_threshold,
+        read_client_u32_property(connection, names, client_path, "TimeThreshold").await);
+    
+    // Set requested accuracy level
+    dbus_call_with_args("Set.RequestedAccuracyLevel", (accuracy_level as u32).to_string(), client.set_property("RequestedAccuracyLevel", &(accuracy_level as u32))).await?;
+    record_requested_accuracy_level(args.accuracy_level.into());
+    log("INFO", "Set accuracy level", &[
+        ("accuracy_level", format!("{:?}", accuracy_level)),
+        ("level_value", (accuracy_level as u32).to_string()),
+    ]);
+    
+    // Start the client
+    dbus_call("Start", client.call::<_, _, ()>("Start", &())).await
+        .with_context(|| format!("starting client {}", client_path))?;
+    log("INFO", "Started GeoClue2 client", &[("path", format!("{}", client_path))]);
+
+    Ok(())
+}
+
+// Read a client property from GeoClue2 itself, bypassing the proxy's property cache
+// (which would just echo the value we set)
+async fn read_client_u32_property(
+    connection: &Connection,
+    names: &GeoClueNames,
+    client_path: &zvariant::OwnedObjectPath,
+    property: &str
+) -> Result<u32> {
+    let properties = zbus::fdo::PropertiesProxy::builder(connection)
+        .destination(names.service.as_str())?
+        .path(client_path.as_ref())?
+        .build().await?;
+    let interface = zbus::names::InterfaceName::try_from(names.client_interface())?;
+    let value = dbus_call(format!("Get.{}", property), properties.get(interface, property)).await?;
+    Ok(u32::try_from(value)?)
+}
+
+// Publish a threshold as applied by GeoClue2, warning when it differs from the request
+fn record_applied_threshold(gauge: &'static str, property: &str, requested: u32, applied: Result<u32>) {
+    match applied {
+        Ok(applied) => {
+            metrics::gauge!(gauge).set(applied as f64);
+            if applied != requested {
+                log("WARN", "GeoClue2 applied a different threshold than requested", &[
+                    ("property", property.to_string()),
+                    ("requested", requested.to_string()),
+                    ("applied", applied.to_string()),
+                ]);
+            }
+        },
+        Err(e) => {
+            log("WARN", "Failed to read back client threshold", &[
+                ("property", property.to_string()),
+                ("error", format!("{:#}", e)),
+            ]);
+        }
+    }
+}
+
+// Next level to try after `active` produced no update: the entry following it in the
+// fallback list, or the first entry when the active level is not listed
+fn next_fallback_level(fallback: &[AccuracyLevelArg], active: AccuracyLevelArg) -> Option<AccuracyLevelArg> {
+    match fallback.iter().position(|level| *level == active) {
+        Some(position) => fallback.get(position + 1).copied(),
+        None => fallback.first().copied(),
+    }
+}
+
+// Restart a running client with a different requested accuracy level. Signals already
+// queued on `signals` are dropped as late, since they belong to the old level.
+async fn restart_client_at_level<S: futures_util::Stream + Unpin>(
+    geoclue_conn: &GeoClueConnection,
+    client_path: &zvariant::OwnedObjectPath,
+    accuracy_level: AccuracyLevel,
+    signals: &mut S
+) -> Result<()> {
+    let client = zbus::Proxy::new(
+        &geoclue_conn.connection, 
+        geoclue_conn.names.service.as_str(), 
+        client_path, 
+        geoclue_conn.names.client_interface()
+    ).await?;
+
+    let stop = async {
+        dbus_call("Stop", client.call::<_, _, ()>("Stop", &())).await
+            .with_context(|| format!("stopping client {}", client_path))
+    };
+    let start = async {
+        dbus_call_with_args("Set.RequestedAccuracyLevel", (accuracy_level as u32).to_string(), client.set_property("RequestedAccuracyLevel", &(accuracy_level as u32))).await?;
+        dbus_call("Start", client.call::<_, _, ()>("Start", &())).await
+            .with_context(|| format!("starting client {}", client_path))
+    };
+    restart_dropping_late_signals(&geoclue_conn.client_stopped, signals, stop, start).await?;
+    let mut levels = geoclue_conn.accuracy_levels.lock().unwrap();
+    levels.requested = accuracy_level;
+    set_accuracy_level_gauges(levels.requested, levels.available);
+
+    Ok(())
+}
+
+// Check if an error indicates a permanent failure that should not be retried.
+// Matches against the whole context chain, since the root cause sits below any context.
+fn is_permanent_error(error: &anyhow::Error, has_connected_before: bool) -> bool {
+    let error_str = format!("{:#}", error).to_lowercase();
+    
+    // Debug logging for error classification
+    log("DEBUG", "Classifying error", &[
+        ("error_str", error_str.clone()),
+        ("has_connected_before", has_connected_before.to_string()),
+    ]);
+    
+    // Always permanent errors
+    if error_str.contains("permission denied") ||
+       error_str.contains("access denied") ||
+       error_str.contains("invalid argument") ||
+       error_str.contains("not permitted") {
+        log("DEBUG", "Error classified as always permanent", &[("reason", "permission/access".to_string())]);
+        return true;
+    }
+    
+    // If we've never connected before, be more conservative - treat more errors as permanent
+    if !has_connected_before {
+        let is_permanent = error_str.contains("no such file or directory") ||
+               error_str.contains("service not found") ||
+               error_str.contains("serviceunknown") ||
+               error_str.contains("service unknown") ||
+               error_str.contains("name not found") ||
+               (error_str.contains("failed to connect") && error_str.contains("dbus"));
+        log("DEBUG", "First connection error classification", &[
+            ("is_permanent", is_permanent.to_string()),
+            ("reason", "first_connection_conservative".to_string()),
+        ]);
+        return is_permanent;
+    }
+    
+    // If we've connected before, most errors are retryable (service might restart)
+    // Only treat clearly permanent configuration errors as non-retryable
+    let is_permanent = error_str.contains("permission denied") ||
+        error_str.contains("access denied") ||
+        error_str.contains("invalid argument") ||
+        error_str.contains("not permitted");
+    
+    log("DEBUG", "Reconnection error classification", &[
+        ("is_permanent", is_permanent.to_string()),
+        ("reason", "reconnection_liberal".to_string()),
+    ]);
+    
+    is_permanent
+}
+
+// D-Bus errors meaning nothing owns the GeoClue2 service name, because the daemon is not
+// installed or cannot be activated
+const DAEMON_MISSING_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+];
+
+// Check if an error anywhere in the chain says GeoClue2 is not on the bus at all
+fn is_daemon_missing_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let fdo = match cause.downcast_ref::<zbus::Error>() {
+            Some(zbus::Error::MethodError(name, _, _)) => return DAEMON_MISSING_ERRORS.contains(&name.as_str()),
+            Some(zbus::Error::FDO(fdo)) => Some(&**fdo),
+            _ => cause.downcast_ref::<zbus::fdo::Error>(),
+        };
+        matches!(fdo, Some(zbus::fdo::Error::ServiceUnknown(_) | zbus::fdo::Error::NameHasNoOwner(_)))
+    })
+}
+
+// Check if an error indicates a DBus disconnection that warrants reconnection
+fn is_disconnection_error(error: &anyhow::Error, has_connected_before: bool) -> bool {
+    let is_disconnection = !is_permanent_error(error, has_connected_before);
+    
+    log("DEBUG", "Disconnection error check", &[
+        ("is_disconnection", is_disconnection.to_string()),
+        ("error", format!("{:#}", error)),
+    ]);
+    
+    is_disconnection
+}
+
+// Exponential reconnect backoff before jitter: 2s, 4s, 8s, ... up to max_backoff
+fn backoff_cap(retry_count: u32, max_backoff: u64) -> u64 {
+    2_u64.saturating_pow(retry_count).min(max_backoff)
+}
+
+// Reconnect delay with full jitter, uniform between 0 and the backoff cap, so exporters
+// that lost GeoClue2 at the same moment do not all retry in lockstep
+fn jittered_backoff(retry_count: u32, max_backoff: u64, rng: &mut impl rand::Rng) -> std::time::Duration {
+    let cap_millis = backoff_cap(retry_count, max_backoff).saturating_mul(1000);
+    std::time::Duration::from_millis(rng.random_range(0..=cap_millis))
+}
+
+// Error ending a session after --max-consecutive-invalid rejected updates; it is not a
+// permanent error, so the connection loop reconnects
+fn consecutive_invalid_error(count: u32) -> anyhow::Error {
+    anyhow::anyhow!("{} consecutive location updates had every value rejected", count)
+}
+
+// Count and log a location update that could not be processed
+fn record_update_error(kind: &'static str, error: &anyhow::Error) {
+    metrics::counter!(self_metric("geoclue_location_update_errors_total"), "kind" => kind).increment(1);
+    log("WARN", "Failed to process location update", &[
+        ("kind", kind.to_string()),
+        ("error", format!("{:#}", error)),
+    ]);
+}
+
+// Properties read from a GeoClue2 Location object
+#[derive(Debug, Clone)]
+struct LocationReading {
+    latitude: f64,
+    longitude: f64,
+    accuracy: f64,
+    altitude: f64,
+    speed: f64,
+    heading: f64,
+    satellites: Option<u32>,
+}
+
+// Non-standard Location properties probed for a satellite count. The GeoClue2
+// Location interface does not define one, but some GPS-backed shims add it.
+const SATELLITE_COUNT_PROPERTIES: &[&str] = &["Satellites", "SatelliteCount"];
+
+// Probe the optional satellite count; None when the backend does not expose it
+async fn probe_satellite_count(location: &zbus::Proxy<'_>) -> Option<u32> {
+    for property in SATELLITE_COUNT_PROPERTIES {
+        if let Ok(count) = location.get_property::<u32>(property).await {
+            return Some(count);
+        }
+    }
+    None
+}
+
+// Decide whether a fix passes the --min-satellites gate; unknown quality always passes
+fn passes_fix_quality_gate(min_satellites: Option<u32>, satellites: Option<u32>) -> bool {
+    match (min_satellites, satellites) {
+        (Some(min), Some(count)) => count >= min,
+        _ => true,
+    }
+}
+
+// Provider classes geoclue_updates_by_source_total distinguishes
+const UPDATE_SOURCES: [&str; 4] = ["gps", "network", "modem", "unknown"];
+
+// Guess which kind of provider produced a fix. GeoClue2 does not say, so this goes by
+// the satellite count when the backend reports one and otherwise by the accuracy each
+// provider typically achieves: GPS within tens of meters, cell towers (modem) within
+// a few kilometers, WiFi and GeoIP (network) in between and beyond.
+fn detect_update_source(reading: &LocationReading) -> &'static str {
+    if reading.satellites.is_some_and(|count| count > 0) {
+        return "gps";
+    }
+    match reading.accuracy {
+        accuracy if !accuracy.is_finite() || accuracy < 0.0 => "unknown",
+        accuracy if accuracy <= 30.0 => "gps",
+        accuracy if accuracy <= 500.0 => "network",
+        accuracy if accuracy <= 10_000.0 => "modem",
+        _ => "network",
+    }
+}
+
+// Count an update from any client towards geoclue_updates_by_source_total
+fn record_update_source(reading: &LocationReading) -> &'static str {
+    let source = detect_update_source(reading);
+    metrics::counter!("geoclue_updates_by_source_total", "source" => source).increment(1);
+    source
+}
+
+// Pause between attempts to read a property that failed
+const PROPERTY_READ_RETRY_DELAY: tokio::time::Duration = tokio::time::Duration::from_millis(50);
+
+// Read a property, retrying up to `retries` more times to ride out momentary DBus hiccups
+async fn read_with_retries<F, Fut>(property: &str, retries: u32, delay: tokio::time::Duration, mut read: F) -> zbus::Result<f64>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = zbus::Result<f64>>,
+{
+    let mut attempt = 0;
+    loop {
+        match read().await {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                metrics::counter!(self_metric("geoclue_property_read_retries_total"), "property" => property.to_string()).increment(1);
+                log("DEBUG", "Retrying location property read", &[
+                    ("property", property.to_string()),
+                    ("attempt", attempt.to_string()),
+                    ("error", format!("{}", e)),
+                ]);
+                tokio::time::sleep(delay).await;
+            },
+            result => return result,
+        }
+    }
+}
+
+// Read a single f64 property, logging the raw result and timing at TRACE level
+async fn get_f64_property(cycle: &UpdateCycle, location: &zbus::Proxy<'_>, property: &str, retries: u32) -> zbus::Result<f64> {
+    let started = std::time::Instant::now();
+    let result = read_with_retries(property, retries, PROPERTY_READ_RETRY_DELAY, || dbus_call(format!("Get.{}", property), location.get_property::<f64>(property))).await;
+
+    cycle.trace("Fetched location property", &[
+        ("property", property.to_string()),
+        ("result", match &result {
+            Ok(value) => value.to_string(),
+            Err(e) => format!("error: {}", e),
+        }),
+        ("elapsed_us", started.elapsed().as_micros().to_string()),
+    ]);
+
+    result
+}
+
+// Run `stop`, drop every signal queued on the stream by then and run `start`. GeoClue2
+// sends the Stop reply after any signal it emitted before stopping, so all updates at
+// the old level are queued once `stop` completes, and anything read later is new.
+async fn restart_dropping_late_signals<S: futures_util::Stream + Unpin>(
+    client_stopped: &AtomicBool,
+    signals: &mut S,
+    stop: impl std::future::Future<Output = Result<()>>,
+    start: impl std::future::Future<Output = Result<()>>
+) -> Result<()> {
+    client_stopped.store(true, Ordering::Relaxed);
+    stop.await?;
+    while let Some(Some(_)) = signals.next().now_or_never() {
+        is_late_signal(client_stopped);
+    }
+    start.await?;
+    client_stopped.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+// Whether a LocationUpdated signal arrived after Stop was issued; such signals are
+// counted and dropped instead of processed
+fn is_late_signal(client_stopped: &AtomicBool) -> bool {
+    if !client_stopped.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    metrics::counter!(self_metric("geoclue_late_signals_ignored_total")).increment(1);
+    log("DEBUG", "Ignoring location update received after the client was stopped", &[]);
+    true
+}
+
+// Longest Location object path accepted from a LocationUpdated signal
+const MAX_LOCATION_PATH_LEN: usize = 255;
+
+// --once: start the clients, wait for the first location update and return it as JSON.
+// The clients are stopped again whether or not an update arrived in time.
+async fn run_once(args: &Args) -> Result<String> {
+    let geoclue_conn = setup_geoclue_connection(args).await?;
+    let result = tokio::time::timeout(
+        tokio::time::Duration::from_secs(args.timeout),
+        wait_for_first_location(args, &geoclue_conn)
+    ).await;
+
+    geoclue_conn.client_stopped.store(true, Ordering::Relaxed);
+    for geoclue_client in &geoclue_conn.clients {
+        if let Err(e) = stop_client(&geoclue_conn.connection, &geoclue_conn.names, &geoclue_client.path).await {
+            log("WARN", "Failed to stop GeoClue2 client", &[
+                ("client", geoclue_client.label.clone()),
+                ("error", format!("{:#}", e)),
+            ]);
+        }
+    }
+
+    match result {
+        Ok(reading) => Ok(once_location_json(&reading?, &CoordinateCoarsening::from_args(args), Utc::now().timestamp())),
+        Err(_) => Err(anyhow::anyhow!("No location update received from GeoClue2 within {} seconds", args.timeout)),
+    }
+}
+
+// Read the primary client's location: the current one if GeoClue2 already has a fix
+// (the first LocationUpdated may have been emitted before we subscribed), otherwise
+// the one announced by the next LocationUpdated signal
+async fn wait_for_first_location(args: &Args, geoclue_conn: &GeoClueConnection) -> Result<LocationReading> {
+    let primary = &geoclue_conn.clients[0];
+    let client = zbus::Proxy::new(
+        &geoclue_conn.connection,
+        geoclue_conn.names.service.as_str(),
+        &primary.path,
+        geoclue_conn.names.client_interface()
+    ).await?;
+    let mut location_updated_stream = client.receive_signal("LocationUpdated").await?;
+
+    let current: zvariant::OwnedObjectPath = dbus_call("Get.Location", client.get_property("Location")).await
+        .with_context(|| format!("fetching Location from {}", primary.path))?;
+    let location_path = if is_valid_location_path(current.as_str()) {
+        current
+    } else {
+        loop {
+            let signal = location_updated_stream.next().await
+                .ok_or_else(|| anyhow::anyhow!("LocationUpdated stream of {} ended", primary.path))?;
+            let (_, new_path): (zvariant::ObjectPath, zvariant::OwnedObjectPath) = signal.body().deserialize()?;
+            if is_valid_location_path(new_path.as_str()) {
+                break new_path;
+            }
+        }
+    };
+
+    fetch_location_properties(&UpdateCycle::next(), geoclue_conn, &location_path, false, args.property_read_retries).await
+}
+
+// Stop a client started by setup_geoclue_connection
+async fn stop_client(connection: &Connection, names: &GeoClueNames, client_path: &zvariant::OwnedObjectPath) -> Result<()> {
+    let client = zbus::Proxy::new(
+        connection,
+        names.service.as_str(),
+        client_path,
+        names.client_interface()
+    ).await?;
+    dbus_call("Stop", client.call::<_, _, ()>("Stop", &())).await
+        .with_context(|| format!("stopping client {}", client_path))
+}
+
+// JSON printed by --once; properties GeoClue2 reports as unknown (-1) are null. Known
+// coordinates are coarsened like the exported ones afterwards.
+fn once_location_json(reading: &LocationReading, coarsening: &CoordinateCoarsening, timestamp: i64) -> String {
+    let (lat, lon) = coarsening.apply(reading.latitude, reading.longitude);
+    let readings = [
+        ("latitude", reading.latitude, lat),
+        ("longitude", reading.longitude, lon),
+        ("accuracy", reading.accuracy, reading.accuracy),
+        ("altitude", reading.altitude, reading.altitude),
+        ("speed", reading.speed, reading.speed),
+        ("heading", reading.heading, reading.heading),
+    ];
+    let mut location = serde_json::Map::new();
+    for (name, reported, value) in readings {
+        let value = if reported == -1.0 || !reported.is_finite() {
+            serde_json::Value::Null
+        } else {
+            value.into()
+        };
+        location.insert(name.to_string(), value);
+    }
+    location.insert("timestamp".to_string(), timestamp.into());
+    serde_json::Value::Object(location).to_string()
+}
+
+// Check that a Location path can be read: a well-formed object path below the root
+fn is_valid_location_path(path: &str) -> bool {
+    path.len() <= MAX_LOCATION_PATH_LEN
+        && path != "/"
+        && zvariant::ObjectPath::try_from(path).is_ok()
+}
+
+// Read the coordinate properties of a GeoClue2 Location object
+async fn fetch_location_properties(
+    cycle: &UpdateCycle,
+    geoclue_conn: &GeoClueConnection,
+    location_path: &zvariant::ObjectPath<'_>,
+    probe_quality: bool,
+    read_retries: u32
+) -> Result<LocationReading> {
+    // Create a location proxy for this location
+    let location = zbus::Proxy::new(
+        &geoclue_conn.connection, 
+        geoclue_conn.names.service.as_str(), 
+        location_path, 
+        geoclue_conn.names.location_interface()
+    ).await?;
+
+    let mut results = Vec::with_capacity(LOCATION_PROPERTIES.len());
+    for property in LOCATION_PROPERTIES {
+        results.push(get_f64_property(cycle, &location, property, read_retries).await);
+    }
+
+    let satellites = if probe_quality {
+        probe_satellite_count(&location).await
+    } else {
+        None
+    };
+
+    reading_from_properties(results, satellites, location_path.as_str())
+}
+
+// Location properties read on every update, in LocationReading field order
+const LOCATION_PROPERTIES: [&str; 6] = ["Latitude", "Longitude", "Accuracy", "Altitude", "Speed", "Heading"];
+
+// Build a reading from the property reads, substituting -1 (unknown) for properties that
+// failed. Records how many were read and only fails when none could be read at all.
+fn reading_from_properties(results: Vec<zbus::Result<f64>>, satellites: Option<u32>, location_path: &str) -> Result<LocationReading> {
+    let properties_read = results.iter().filter(|result| result.is_ok()).count();
+    metrics::gauge!("geoclue_properties_read_last_update").set(properties_read as f64);
+
+    let mut values = [-1.0; LOCATION_PROPERTIES.len()];
+    let mut first_error = None;
+    for ((value, result), property) in values.iter_mut().zip(results).zip(LOCATION_PROPERTIES) {
+        match result {
+            Ok(read) => *value = read,
+            Err(e) => {
+                log("WARN", "Failed to read location property", &[
+                    ("property", property.to_string()),
+                    ("path", location_path.to_string()),
+                    ("error", format!("{}", e)),
+                ]);
+                first_error.get_or_insert((property, e));
+            }
+        }
+    }
+
+    if properties_read == 0 {
+        return Err(first_error
+            .map(|(property, e)| anyhow::Error::from(e).context(format!("fetching {} from {}", property, location_path)))
+            .unwrap_or_else(|| anyhow::anyhow!("No location properties read from {}", location_path)));
+    }
+
+    let [latitude, longitude, accuracy, altitude, speed, heading] = values;
+    Ok(LocationReading {
+        latitude,
+        longitude,
+        accuracy,
+        altitude,
+        speed,
+        heading,
+        satellites,
+    })
+}
+
+// A receiver's heading comes from the direction between successive fixes, so while
+// (nearly) stationary it is dominated by position noise and jumps around at random.
+// Decide whether a heading should be exported; an unknown speed keeps the heading.
+fn heading_is_meaningful(suppress_when_slow: bool, speed_threshold: f64, speed: f64) -> bool {
+    !suppress_when_slow || speed < 0.0 || speed >= speed_threshold
+}
+
+// Mean earth radius in meters (IUGG), used for great-circle distances
+const EARTH_MEAN_RADIUS: f64 = 6_371_008.8;
+
+// Size in degrees of latitude and longitude of a distance in meters at the given latitude
+fn meters_to_degrees(meters: f64, latitude: f64) -> (f64, f64) {
+    let meters_per_degree = EARTH_MEAN_RADIUS * std::f64::consts::PI / 180.0;
+    let lat_degrees = meters / meters_per_degree;
+    // Meridians converge towards the poles; a cell never spans more than the whole circle
+    let lon_degrees = (meters / (meters_per_degree * latitude.to_radians().cos())).min(360.0);
+    (lat_degrees, lon_degrees)
+}
+
+// Snap a position to the centre of its grid cell. The longitude step is taken at the
+// cell's centre latitude, so every position in a latitude band shares the same cells.
+fn snap_to_grid(latitude: f64, longitude: f64, cell_meters: f64) -> (f64, f64) {
+    let (lat_step, _) = meters_to_degrees(cell_meters, latitude);
+    let snapped_lat = ((latitude / lat_step).floor() + 0.5) * lat_step;
+
+    let (_, lon_step) = meters_to_degrees(cell_meters, snapped_lat);
+    let snapped_lon = ((longitude / lon_step).floor() + 0.5) * lon_step;
+    (snapped_lat.clamp(-90.0, 90.0), snapped_lon)
+}
+
+// How coordinates are coarsened before anything exports or stores them: rounded to
+// --coordinate-precision, then snapped to the --snap-grid-meters grid
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct CoordinateCoarsening {
+    precision: Option<u8>,
+    grid_meters: Option<f64>,
+}
+
+impl CoordinateCoarsening {
+    fn from_args(args: &Args) -> Self {
+        CoordinateCoarsening { precision: args.coordinate_precision, grid_meters: args.snap_grid_meters }
+    }
+
+    // Coarsen a reported position; one with an unknown (-1) coordinate has no grid cell
+    fn apply(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let lat = round_coordinate(latitude, self.precision);
+        let lon = round_coordinate(longitude, self.precision);
+        match self.grid_meters {
+            Some(cell_meters) if latitude != -1.0 && longitude != -1.0 => snap_to_grid(lat, lon, cell_meters),
+            _ => (lat, lon),
+        }
+    }
+}
+
+// Round a coordinate to the given number of decimal places. The result may be -1 for a
+// real coordinate, so check the reported value for the unknown sentinel instead.
+fn round_coordinate(value: f64, decimals: Option<u8>) -> f64 {
+    match decimals {
+        Some(decimals) => {
+            let scale = 10f64.powi(decimals as i32);
+            (value * scale).round() / scale
+        },
+        None => value,
+    }
+}
+
+// Great-circle distance in meters between two WGS84 coordinates in degrees
+fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_MEAN_RADIUS * a.sqrt().asin()
+}
+
+// Accuracy-weighted average of (latitude, longitude, accuracy) fixes. Each fix is weighted
+// by 1/accuracy², so a 10m fix counts a hundred times as much as a 100m one. Accuracies
+// that are zero, negative (-1 = unknown) or not finite give no usable weight: those fixes
+// are left out, and if no fix has a usable weight all of them are averaged equally.
+// Longitudes are averaged relative to the first fix so the antimeridian does not pull
+// the result towards 0.
+fn weighted_position(samples: &[(f64, f64, f64)]) -> Option<(f64, f64)> {
+    let &(_, reference_longitude, _) = samples.first()?;
+    let usable_weight = |accuracy: f64| accuracy.is_finite() && accuracy > 0.0;
+    let any_usable = samples.iter().any(|&(_, _, accuracy)| usable_weight(accuracy));
+
+    let mut total_weight = 0.0;
+    let mut latitude_sum = 0.0;
+    let mut longitude_offset_sum = 0.0;
+    for &(latitude, longitude, accuracy) in samples {
+        let weight = match (any_usable, usable_weight(accuracy)) {
+            (false, _) => 1.0,
+            (true, true) => 1.0 / (accuracy * accuracy),
+            (true, false) => continue,
+        };
+        // Offset to the first fix, taking the short way round
+        let offset = (longitude - reference_longitude + 540.0).rem_euclid(360.0) - 180.0;
+        total_weight += weight;
+        latitude_sum += weight * latitude;
+        longitude_offset_sum += weight * offset;
+    }
+
+    let longitude = (reference_longitude + longitude_offset_sum / total_weight + 540.0).rem_euclid(360.0) - 180.0;
+    Some((latitude_sum / total_weight, longitude))
+}
+
+// Distance from the previous Location object to the new coordinates. The old path is
+// "/" on a client's first update; None then, or when the old location cannot be read.
+async fn reported_move_meters<F, Fut>(old_path: &str, new: (f64, f64), read_location: F) -> Option<f64>
+where
+    F: FnOnce(zvariant::ObjectPath<'static>) -> Fut,
+    Fut: std::future::Future<Output = Result<(f64, f64)>>,
+{
+    if !is_valid_location_path(old_path) {
+        return None;
+    }
+    let old_path = zvariant::ObjectPath::try_from(old_path.to_string()).ok()?;
+
+    match read_location(old_path).await {
+        Ok((lat, lon)) if lat != -1.0 && lon != -1.0 => Some(haversine(lat, lon, new.0, new.1)),
+        Ok(_) => None,
+        Err(e) => {
+            log("DEBUG", "Failed to read previous location", &[("error", format!("{}", e))]);
+            None
+        }
+    }
+}
+
+// Readings older than this no longer compete for the primary gauges
+const BEST_READING_MAX_AGE: tokio::time::Duration = tokio::time::Duration::from_secs(300);
+
+// Pick the candidate with the best (lowest) accuracy in meters, ignoring unknown accuracy
+fn select_best_reading(candidates: &[Option<LocationReading>]) -> Option<usize> {
+    candidates.iter()
+        .enumerate()
+        .filter_map(|(index, reading)| reading.as_ref().map(|reading| (index, reading.accuracy)))
+        .filter(|(_, accuracy)| accuracy.is_finite() && *accuracy >= 0.0)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}
+
+// Export one client's own reading under its client label
+fn set_client_gauges(client: &str, reading: &LocationReading, coarsening: &CoordinateCoarsening, validation: &ValidationConfig) {
+    let (lat, lon) = coarsening.apply(reading.latitude, reading.longitude);
+    let values = [
+        ("latitude", reading.latitude, lat),
+        ("longitude", reading.longitude, lon),
+        ("accuracy", reading.accuracy, reading.accuracy),
+        ("altitude", reading.altitude, reading.altitude),
+        ("speed", reading.speed, reading.speed),
+        ("heading", reading.heading, reading.heading),
+    ];
+
+    for (metric_name, reported, value) in values {
+        if invalid_metric_value(metric_name, reported, validation).is_some() || is_metric_disabled(metric_name) {
+            continue;
+        }
+
+        let label = client.to_string();
+        match metric_name {
+            "latitude" => metrics::gauge!("geoclue_client_latitude", "client" => label).set(value),
+            "longitude" => metrics::gauge!("geoclue_client_longitude", "client" => label).set(value),
+            "accuracy" => metrics::gauge!("geoclue_client_accuracy", "client" => label).set(value),
+            "altitude" => metrics::gauge!("geoclue_client_altitude", "client" => label).set(value),
+            "speed" => {
+                let unit = speed_unit();
+                metrics::gauge!(unit.client_metric_name(), "client" => label).set(unit.convert(value));
+            }
+            "heading" => metrics::gauge!("geoclue_client_heading", "client" => label).set(value),
+            _ => {}
+        }
+    }
+}
+
+// Function to monitor location updates with proper error handling
+async fn monitor_location_updates(
+    args: &Args,
+    geoclue_conn: &GeoClueConnection,
+    tracker: Arc<Mutex<UpdateTracker>>,
+    sinks: &UpdateSinks
+) -> Result<()> {
+    log("INFO", "Waiting for location updates", &[]);
+
+    // Subscribe to location updates from every client and merge the streams
+    let mut streams = Vec::new();
+    for (index, geoclue_client) in geoclue_conn.clients.iter().enumerate() {
+        // Create client proxy from the connection
+        let client = zbus::Proxy::new(
+            &geoclue_conn.connection, 
+            geoclue_conn.names.service.as_str(), 
+            &geoclue_client.path, 
+            geoclue_conn.names.client_interface()
+        ).await?;
+
+        let stream = client.receive_signal("LocationUpdated").await?;
+        streams.push(stream.map(move |signal| (index, signal)));
+    }
+    let mut location_updated_stream = futures_util::stream::select_all(streams);
+
+    let coarsening = CoordinateCoarsening::from_args(args);
+    let validation = &ValidationConfig::from_args(args);
+
+    // Latest reading per client, used to pick the best one for the primary gauges
+    let multiple_clients = geoclue_conn.clients.len() > 1;
+    let mut latest_readings: Vec<Option<(tokio::time::Instant, LocationReading)>> = vec![None; geoclue_conn.clients.len()];
+
+    // Step the primary client down the --accuracy-fallback list until it delivers an update
+    let fallback_timeout = tokio::time::Duration::from_secs(args.accuracy_fallback_timeout);
+    let mut active_level = args.accuracy_level;
+    let mut awaiting_primary_update = !args.accuracy_fallback.is_empty();
+    let mut fallback_deadline = tokio::time::Instant::now() + fallback_timeout;
+    
+    loop {
+        let next_signal = if awaiting_primary_update {
+            match tokio::time::timeout_at(fallback_deadline, location_updated_stream.next()).await {
+                Ok(next_signal) => next_signal,
+                Err(_) => {
+                    let Some(next_level) = next_fallback_level(&args.accuracy_fallback, active_level) else {
+                        log("WARN", "No location update at the last fallback accuracy level", &[
+                            ("accuracy_level", format!("{:?}", active_level)),
+                        ]);
+                        awaiting_primary_update = false;
+                        continue;
+                    };
+
+                    log("WARN", "No location update received, lowering accuracy level", &[
+                        ("from", format!("{:?}", active_level)),
+                        ("to", format!("{:?}", next_level)),
+                        ("timeout_seconds", args.accuracy_fallback_timeout.to_string()),
+                    ]);
+                    restart_client_at_level(geoclue_conn, &geoclue_conn.clients[0].path, next_level.into(), &mut location_updated_stream).await?;
+                    active_level = next_level;
+                    fallback_deadline = tokio::time::Instant::now() + fallback_timeout;
+                    continue;
+                }
+            }
+        } else {
+            location_updated_stream.next().await
+        };
+        let Some((client_index, signal)) = next_signal else {
+            break;
+        };
+        if is_late_signal(&geoclue_conn.client_stopped) {
+            continue;
+        }
+        if client_index == 0 {
+            awaiting_primary_update = false;
+        }
+
+        let client_label = &geoclue_conn.clients[client_index].label;
+        let cycle = UpdateCycle::next();
+        // Update counter whenever we get a new location
+        {
+            let mut tracker = tracker.lock().unwrap();
+            tracker.record_signal_received();
+            
+            // Log the current update count
+            log("DEBUG", "Location update received", &[
+                ("received_updates", tracker.received_updates.to_string()),
+            ]);
+        }
+
+        if args.track_hourly {
+            record_hourly_update(Utc::now(), args.log_timezone);
+        }
+        
+        // Deserialize the entire body as a tuple
+        let body_owned = signal.body().clone();
+        cycle.trace("Raw LocationUpdated signal", &[
+            ("client", client_label.clone()),
+            ("signature", body_owned.signature().to_string()),
+            ("message", format!("{:?}", signal)),
+        ]);
+        let (old_path, new_path): (zvariant::ObjectPath, zvariant::ObjectPath) = 
+            match body_owned.deserialize() {
+                Ok(paths) => paths,
+                Err(e) => {
+                    record_update_error("deserialize", &e.into());
+                    continue;
+                }
+            };
+
+        if !is_valid_location_path(new_path.as_str()) {
+            metrics::counter!(self_metric("geoclue_invalid_location_path_total")).increment(1);
+            log("WARN", "Ignoring location update with an invalid Location path", &[
+                ("client", client_label.clone()),
+                ("new_path", new_path.chars().take(MAX_LOCATION_PATH_LEN).collect()),
+            ]);
+            continue;
+        }
+        
+        log("INFO", "Received location update", &[
+            ("client", client_label.clone()),
+            ("old_path", format!("{}", old_path)),
+            ("new_path", format!("{}", new_path)),
+        ]);
+
+        if args.export_location_info {
+            let client_path = geoclue_conn.clients[client_index].path.as_str();
+            set_location_info(client_path, new_path.as_str());
+        }
+
+        // Get location properties
+        let reading = match fetch_location_properties(&cycle, geoclue_conn, &new_path, args.min_satellites.is_some(), args.property_read_retries).await {
+            Ok(reading) => reading,
+            Err(e) => {
+                if is_disconnection_error(&e, true) {
+                    set_connection_up(false);
+                }
+                record_update_error("property_fetch", &e);
+                continue;
+            }
+        };
+        set_connection_up(true);
+
+        // Skip low-quality fixes when the backend reports a satellite count
+        if !passes_fix_quality_gate(args.min_satellites, reading.satellites) {
+            metrics::counter!("geoclue_low_quality_fixes_total").increment(1);
+            log("INFO", "Skipping low-quality fix", &[
+                ("satellites", reading.satellites.unwrap_or_default().to_string()),
+                ("min_satellites", args.min_satellites.unwrap_or_default().to_string()),
+            ]);
+            continue;
+        }
+
+        let update_source = record_update_source(&reading);
+        cycle.trace("Detected update source", &[("source", update_source.to_string())]);
+
+        // Distance GeoClue2 itself reports between the previous and the new Location object
+        if args.track_previous_location && reading.latitude != -1.0 && reading.longitude != -1.0 {
+            let cycle = &cycle;
+            let read_previous = |path: zvariant::ObjectPath<'static>| async move {
+                let location = zbus::Proxy::new(
+                    &geoclue_conn.connection, 
+                    geoclue_conn.names.service.as_str(), 
+                    path, 
+                    geoclue_conn.names.location_interface()
+                ).await?;
+                let lat = get_f64_property(cycle, &location, "Latitude", args.property_read_retries).await?;
+                let lon = get_f64_property(cycle, &location, "Longitude", args.property_read_retries).await?;
+                Ok((lat, lon))
+            };
+            // Both ends are raw GeoClue2 readings, so neither is rounded
+            let current = (reading.latitude, reading.longitude);
+            if let Some(meters) = reported_move_meters(old_path.as_str(), current, read_previous).await {
+                metrics::gauge!("geoclue_reported_move_meters").set(meters);
+            }
+        }
+
+        // With several clients, export each one's reading and promote the best to the primary gauges
+        if multiple_clients {
+            set_client_gauges(client_label, &reading, &coarsening, validation);
+            latest_readings[client_index] = Some((tokio::time::Instant::now(), reading.clone()));
+
+            let candidates: Vec<Option<LocationReading>> = latest_readings.iter()
+                .map(|slot| slot.as_ref()
+                    .filter(|(received_at, _)| received_at.elapsed() <= BEST_READING_MAX_AGE)
+                    .map(|(_, reading)| reading.clone()))
+                .collect();
+            let best_index = select_best_reading(&candidates).unwrap_or(client_index);
+
+            log("DEBUG", "Selected best reading", &[
+                ("client", geoclue_conn.clients[best_index].label.clone()),
+            ]);
+            // Another client's reading was already applied when it arrived; only this one is new
+            if best_index != client_index {
+                continue;
+            }
+        }
+        let reading_source = client_label.as_str();
+
+        let LocationReading {
+            latitude: reported_lat,
+            longitude: reported_lon,
+            accuracy: acc,
+            altitude: alt,
+            speed: spd,
+            heading: head,
+            ..
+        } = reading;
+
+        // Coarsen the coordinates before anything exports or stores them. Validation looks
+        // at the reported values, which are the only ones that can hold the -1 sentinel.
+        let (lat, lon) = coarsening.apply(reported_lat, reported_lon);
+        let reading = LocationReading { latitude: lat, longitude: lon, ..reading };
+
+        // Prepare field arrays for logging
+        let mut update_fields = vec![
+            ("latitude", format!("{}", lat)),
+            ("longitude", format!("{}", lon)),
+            ("accuracy", format!("{}", acc))
+        ];
+        
+        // Add optional fields only if they're valid
+        for (name, value) in [("altitude", alt), ("speed", spd), ("heading", head)] {
+            if invalid_metric_value(name, value, validation).is_none() {
+                update_fields.push((name, format!("{}", value)));
+            } else {
+                update_fields.push((name, "not_available".to_string()));
+            }
+        }
+        
+        log("INFO", "Updated location metrics", &update_fields);
+
+        // Log the complete raw data at debug level
+        log("DEBUG", "Raw location data", &[
+            ("latitude", format!("{}", lat)),
+            ("longitude", format!("{}", lon)),
+            ("accuracy", format!("{}", acc)),
+            ("altitude", format!("{}", alt)),
+            ("speed", format!("{}", spd)),
+            ("heading", format!("{}", head)),
+        ]);
+
+        // Update metrics, but only if they are valid values
+        let readings = [
+            ("latitude", reported_lat, lat),
+            ("longitude", reported_lon, lon),
+            ("accuracy", acc, acc),
+            ("altitude", alt, alt),
+            ("speed", spd, spd),
+            ("heading", head, head),
+        ];
+        let heading_meaningful = heading_is_meaningful(args.suppress_heading_when_slow, args.heading_speed_threshold, spd);
+        if !heading_meaningful {
+            log("DEBUG", "Suppressing heading while slow", &[
+                ("speed", spd.to_string()),
+                ("heading_speed_threshold", args.heading_speed_threshold.to_string()),
+            ]);
+        }
+        let valid_readings: Vec<(&str, f64)> = readings.into_iter()
+            .filter(|(name, _, _)| heading_meaningful || *name != "heading")
+            .filter(|(name, reported, value)| set_location_gauge(name, *reported, *value, validation))
+            .map(|(name, _, value)| (name, value))
+            .collect();
+        for (name, value) in [("altitude", alt), ("speed", spd), ("heading", head)] {
+            let known = invalid_metric_value(name, value, validation).is_none() && (heading_meaningful || name != "heading");
+            set_availability_gauge(name, known);
+        }
+        cycle.trace("Set location gauges", &[
+            ("metrics", valid_readings.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(",")),
+        ]);
+
+        let has_reading = |metric: &str| valid_readings.iter().any(|(name, _)| *name == metric);
+
+        // Export projected coordinates alongside the authoritative WGS84 values
+        if let Some(epsg) = args.project_to {
+            if has_reading("latitude") && has_reading("longitude") {
+                if let Some((x, y)) = project_wgs84(epsg, lat, lon) {
+                    metrics::gauge!("geoclue_x_projected").set(x);
+                    metrics::gauge!("geoclue_y_projected").set(y);
+                }
+            }
+        }
+
+        // Export ECEF coordinates, treating an unknown altitude as the ellipsoid surface
+        if args.export_ecef && has_reading("latitude") && has_reading("longitude") {
+            let height = if has_reading("altitude") { alt } else { 0.0 };
+            let (x, y, z) = wgs84_to_ecef(lat, lon, height);
+            metrics::gauge!("geoclue_position_x_meters").set(x);
+            metrics::gauge!("geoclue_position_y_meters").set(y);
+            metrics::gauge!("geoclue_position_z_meters").set(z);
+        }
+
+        // The smoothed position is derived from the same coordinates, snapped like them
+        if args.smooth_position && has_reading("latitude") && has_reading("longitude") {
+            let smoothed = tracker.lock().unwrap().record_smoothing_sample(lat, lon, acc, args.smooth_window as usize);
+            if let Some((smoothed_lat, smoothed_lon)) = smoothed {
+                let (smoothed_lat, smoothed_lon) = match args.snap_grid_meters {
+                    Some(cell_meters) => snap_to_grid(smoothed_lat, smoothed_lon, cell_meters),
+                    None => (smoothed_lat, smoothed_lon),
+                };
+                metrics::gauge!("geoclue_latitude_smoothed").set(smoothed_lat);
+                metrics::gauge!("geoclue_longitude_smoothed").set(smoothed_lon);
+            }
+        }
+
+        if has_reading("latitude") && has_reading("longitude") {
+            clear_default_position();
+            *EXPORTED_POSITION.lock().unwrap() = Some((lat, lon));
+            let min_move = if args.filter_distance_jitter { args.distance_threshold as f64 } else { 0.0 };
+            let mut tracker = tracker.lock().unwrap();
+            if tracker.record_position(lat, lon, min_move, args.max_jump_meters).is_some() {
+                metrics::counter!("geoclue_distance_traveled_meters_total").absolute(tracker.distance_traveled as u64);
+            }
+        }
+
+        // Only mark the data as fresh when at least one coordinate was accepted
+        if has_accepted_coordinate(&valid_readings) {
+            metrics::gauge!("geoclue_last_update_timestamp_seconds")
+                .set(corrected_timestamp(Utc::now(), args.clock_offset_seconds));
+            let mut tracker = tracker.lock().unwrap();
+            tracker.record_update_applied();
+            LOCATION_RECEIVED.store(true, Ordering::Relaxed);
+            sinks.scrapes.record_location_update(Utc::now());
+            tracker.last_update = Some(std::time::Instant::now());
+            tracker.last_reading = Some(reading.clone());
+            metrics::gauge!("geoclue_data_available").set(1.0);
+            metrics::gauge!("geoclue_update_age_seconds").set(0.0);
+        }
+
+        // A backend sending nothing but garbage looks alive; reconnect to reset it
+        if tracker.lock().unwrap().record_update_validity(!valid_readings.is_empty(), args.max_consecutive_invalid) {
+            let max = args.max_consecutive_invalid.unwrap_or_default();
+            log("WARN", "Every value of consecutive location updates was rejected, reconnecting to GeoClue2", &[
+                ("max_consecutive_invalid", max.to_string()),
+            ]);
+            return Err(consecutive_invalid_error(max));
+        }
+
+        // Negative accuracy is never meaningful, even if it slipped past the sentinel checks
+        if has_reading("accuracy") && acc >= 0.0 {
+            metrics::histogram!("geoclue_accuracy_meters").record(acc);
+        }
+
+        if has_reading("accuracy") {
+            let mut tracker = tracker.lock().unwrap();
+            record_accuracy_sample(&mut tracker, active_level.into(), acc);
+        }
+
+        if let Some(graphite) = &sinks.graphite {
+            graphite.send(&valid_readings, Utc::now().timestamp());
+        }
+
+        if let Some(events_file) = &sinks.events_file {
+            let line = events_file_line(&valid_readings, reading_source, Utc::now().timestamp());
+            if let Err(e) = events_file.lock().unwrap().append(&line) {
+                log("WARN", "Failed to write events file", &[("error", format!("{}", e))]);
+            }
+        }
+
+        if let Some(update_command) = &sinks.update_command {
+            update_command.run(&valid_readings, reading_source, Utc::now().timestamp());
+        }
+
+        // Publish to SSE subscribers; sending only fails when nobody is listening
+        if let Some(events) = &sinks.events {
+            let _ = events.send(location_event_json(&valid_readings, Utc::now().timestamp()));
+        }
+
+        // The last update of a bounded capture has reached every output, so stop here
+        if tracker.lock().unwrap().capture_complete(args.max_updates) {
+            log("INFO", "Processed the requested number of updates", &[
+                ("max_updates", args.max_updates.unwrap_or_default().to_string()),
+            ]);
+            return Ok(());
+        }
+    }
+
+    // This indicates the stream has ended (likely due to disconnection)
+    Err(anyhow::anyhow!("Location update stream ended"))
+}
+
+// Reload the log level from the config file whenever SIGHUP is received
+#[cfg(unix)]
+fn spawn_reload_handler(config: Option<PathBuf>, log_level_from_command_line: bool) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log("ERROR", "Failed to listen for SIGHUP", &[("error", format!("{}", e))]);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let Some(path) = &config else {
+                log("INFO", "SIGHUP received without --config, nothing to reload", &[]);
+                continue;
+            };
+
+            match reloaded_log_level(path, log_level_from_command_line) {
+                Ok(Some(level)) => {
+                    set_log_level(level);
+                    log("INFO", "Configuration reloaded", &[("log_level", format!("{:?}", level))]);
+                },
+                Ok(None) => {
+                    log("INFO", "Configuration reloaded, log level unchanged", &[
+                        ("log_level", format!("{:?}", current_log_level())),
+                    ]);
+                },
+                Err(e) => {
+                    log("ERROR", "Failed to reload configuration", &[("error", format!("{:#}", e))]);
+                }
+            }
+        }
+    });
+}
+
+// SIGHUP does not exist here, so there is nothing to reload on
+#[cfg(not(unix))]
+fn spawn_reload_handler(_config: Option<PathBuf>, _log_level_from_command_line: bool) {}
+
+// Upper bound on the shutdown webhook call so an unreachable endpoint cannot hang exit
+const SHUTDOWN_WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// JSON body POSTed to --shutdown-webhook
+fn shutdown_webhook_payload(reason: &str, uptime: std::time::Duration, last_reading: Option<&LocationReading>) -> String {
+    let last_position = last_reading.map(|reading| serde_json::json!({
+        "latitude": reading.latitude,
+        "longitude": reading.longitude,
+        "accuracy": reading.accuracy,
+    }));
+
+    serde_json::json!({
+        "exporter": PKG_NAME,
+        "reason": reason,
+        "uptime_seconds": uptime.as_secs(),
+        "last_position": last_position,
+    }).to_string()
+}
+
+// Deliver the shutdown notification; failures are only logged
+async fn send_shutdown_webhook(url: &str, payload: String, timeout: std::time::Duration) {
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(e) => {
+            log("WARN", "Failed to create shutdown webhook client", &[("error", format!("{}", e))]);
+            return;
+        }
+    };
+
+    let request = client.post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(payload)
+        .send();
+
+    match tokio::time::timeout(timeout, request).await {
+        Ok(Ok(response)) if response.status().is_success() => {
+            log("INFO", "Shutdown webhook delivered", &[("status", response.status().as_u16().to_string())]);
+        },
+        Ok(Ok(response)) => {
+            log("WARN", "Shutdown webhook rejected", &[("status", response.status().as_u16().to_string())]);
+        },
+        Ok(Err(e)) => {
+            log("WARN", "Failed to deliver shutdown webhook", &[("error", format!("{}", e))]);
+        },
+        Err(_) => {
+            log("WARN", "Shutdown webhook timed out", &[("timeout_seconds", timeout.as_secs().to_string())]);
+        }
+    }
+}
+
+// Job label the metrics are pushed under
+const PUSH_GATEWAY_JOB: &str = "geoclue_exporter";
+
+// Pushgateway endpoint for this exporter's job
+fn push_gateway_url(base: &str) -> String {
+    format!("{}/metrics/job/{}", base.trim_end_matches('/'), PUSH_GATEWAY_JOB)
+}
+
+// POST one rendered metrics page to the Pushgateway
+async fn push_metrics(client: &reqwest::Client, url: &str, body: String) -> Result<()> {
+    let response = client.post(url)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body)
+        .send().await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Pushgateway answered {}", response.status()));
+    }
+    Ok(())
+}
+
+// Periodically push the metrics page; failures are counted and retried on the next tick
+fn spawn_push_gateway(base: &str, interval: std::time::Duration, handle: PrometheusHandle) {
+    let url = push_gateway_url(base);
+    tokio::spawn(async move {
+        // A push never outlasts the interval, so pushes cannot pile up
+        let client = match reqwest::Client::builder().timeout(interval).build() {
+            Ok(client) => client,
+            Err(e) => {
+                log("ERROR", "Failed to create Pushgateway client", &[("error", format!("{}", e))]);
+                return;
+            }
+        };
+
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = push_metrics(&client, &url, render_metrics(&handle)).await {
+                metrics::counter!(self_metric("geoclue_push_failures_total")).increment(1);
+                log("WARN", "Failed to push metrics to Pushgateway", &[
+                    ("url", url.clone()),
+                    ("error", format!("{:#}", e)),
+                ]);
+            }
+        }
+    });
+}
+
+// OTLP exporter that logs and counts failed exports; the SDK only reports them through
+// its own internal logging
+#[cfg(feature = "otlp")]
+struct LoggingMetricExporter {
+    inner: opentelemetry_otlp::MetricExporter,
+    endpoint: String,
+}
+
+#[cfg(feature = "otlp")]
+impl opentelemetry_sdk::metrics::exporter::PushMetricExporter for LoggingMetricExporter {
+    async fn export(&self, metrics: &opentelemetry_sdk::metrics::data::ResourceMetrics) -> opentelemetry_sdk::error::OTelSdkResult {
+        let result = self.inner.export(metrics).await;
+        if let Err(e) = &result {
+            metrics::counter!(self_metric("geoclue_otlp_export_failures_total")).increment(1);
+            log("WARN", "Failed to export metrics over OTLP", &[
+                ("endpoint", self.endpoint.clone()),
+                ("error", format!("{}", e)),
+            ]);
+        }
+        result
+    }
+
+    fn force_flush(&self) -> opentelemetry_sdk::error::OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: std::time::Duration) -> opentelemetry_sdk::error::OTelSdkResult {
+        self.inner.shutdown_with_timeout(timeout)
+    }
+
+    fn temporality(&self) -> opentelemetry_sdk::metrics::Temporality {
+        self.inner.temporality()
+    }
+}
+
+// Export the location gauges to an OTLP collector every interval, observing the values
+// last written to the Prometheus gauges. Failed exports are retried on the next interval.
+#[cfg(feature = "otlp")]
+fn start_otlp_export(endpoint: &str, interval: std::time::Duration) -> Result<opentelemetry_sdk::metrics::SdkMeterProvider> {
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let inner = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .with_timeout(interval)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to create OTLP exporter for {}: {}", endpoint, e))?;
+    let exporter = LoggingMetricExporter { inner, endpoint: endpoint.to_string() };
+    let reader = opentelemetry_sdk::metrics::PeriodicReader::builder(exporter)
+        .with_interval(interval)
+        .build();
+    let provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(opentelemetry_sdk::Resource::builder().with_service_name(PKG_NAME).build())
+        .build();
+
+    let meter = provider.meter(PKG_NAME);
+    for metric in LOCATION_METRICS.into_iter().filter(|metric| !is_metric_disabled(metric)) {
+        let name = format!("geoclue_{}", metric);
+        let help = METRIC_DESCRIPTIONS.iter()
+            .find(|description| description.name == name)
+            .map_or("", |description| description.help);
+        // Observable gauges are kept alive by the meter provider once registered
+        let _ = meter.f64_observable_gauge(name)
+            .with_description(help)
+            .with_callback(move |observer| {
+                if let Some(value) = EXPORTED_VALUES.get(metric) {
+                    observer.observe(value, &[]);
+                }
+            })
+            .build();
+    }
+
+    Ok(provider)
+}
+
+// Wait for Ctrl-C or SIGTERM (sent by systemd when stopping the unit)
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = ctrl_c() => result.map(|_| "SIGINT"),
+        _ = terminate.recv() => Ok("SIGTERM"),
+    }
+}
+
+// Wait for Ctrl-C on platforms without Unix signals
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() -> std::io::Result<&'static str> {
+    ctrl_c().await.map(|_| "ctrl_c")
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let started_at = std::time::Instant::now();
+
+    // Parse command line arguments and merge the optional config file
+    let matches = Args::command().get_matches();
+    let args = args_from_matches(&matches)?;
+    
+    // If --version-info flag is provided, display detailed version info and exit
+    if args.version_info {
+        println!("{}", get_version_string());
+        std::process::exit(0);
+    }
+
+    // If --version-json flag is provided, display version info as JSON and exit
+    if args.version_json {
+        println!("{}", get_version_json());
+        std::process::exit(0);
+    }
+
+    // Set before anything that prints metric names so the catalog matches the exported names
+    if let Some(prefix) = &args.self_metrics_prefix {
+        let _ = SELF_METRICS_PREFIX.set(prefix.clone());
+    }
+
+    if args.print_alert_rules {
+        print!("{}", alert_rules_yaml());
+        std::process::exit(0);
+    }
+
+    if args.list_metrics {
+        print!("{}", metric_listing());
+        std::process::exit(0);
+    }
+    
+    // Set global log level
+    set_log_level(args.log_level);
+    TRACE_DBUS.store(args.trace_dbus, Ordering::Relaxed);
+    disable_location_metrics(&args.disable_metrics);
+    set_speed_unit(args.speed_unit);
+    LOG_TO_STDERR.store(args.once, Ordering::Relaxed);
+    #[cfg(feature = "tracing")]
+    init_tracing(args.log_level, args.trace_dbus);
+    set_log_format(args.log_format);
+    set_log_timezone(args.log_timezone);
+
+    // Warned about here rather than in the value parser, which runs before logging is set up
+    let raw_accuracy_level = matches.get_raw("accuracy_level").into_iter().flatten().next();
+    if let Some(requested) = raw_accuracy_level.and_then(|value| rounded_accuracy_level(&value.to_string_lossy())) {
+        log("WARN", "Non-standard accuracy level, using the next lower GeoClue2 level", &[
+            ("requested", requested.to_string()),
+            ("accuracy_level", format!("{:?}", args.accuracy_level)),
+            ("level_value", (AccuracyLevel::from(args.accuracy_level) as u32).to_string()),
+        ]);
+    }
+    
+    if let Some(path) = &args.config {
+        if !path.exists() {
+            log("WARN", "Config file not found, using command line arguments only", &[
+                ("config", path.display().to_string()),
+            ]);
+        }
+    }
+
+    if args.check {
+        if unix_socket_path(&args.bind_address)?.is_none() {
+            resolve_bind_address(&args).await?;
+        }
+        for (key, value) in effective_config(&args) {
+            println!("{}={}", key, value);
+        }
+        println!("Configuration OK");
+        std::process::exit(0);
+    }
+
+    if args.once {
+        let location = run_once(&args).await?;
+        println!("{}", location);
+        std::process::exit(0);
+    }
+
+    spawn_reload_handler(args.config.clone(), matches.value_source("log_level") == Some(ValueSource::CommandLine));
+
+    // Broadcast channel feeding the /events endpoint
+    let events = args.enable_sse.then(|| broadcast::channel(SSE_CHANNEL_CAPACITY).0);
+
+    // Set up metrics with the provided bind address and port
+    let scrapes = Arc::new(if args.track_scrape_movement { ScrapeTracker::tracking_movement() } else { ScrapeTracker::new() });
+    let metrics_handle = match setup_metrics(&args, events.clone(), scrapes.clone()).await {
+        Ok(handle) => {
+            log("INFO", &format!("{} metrics endpoint started", PKG_NAME), &[
+                ("endpoint", match args.bind_address.strip_prefix("unix:") {
+                    _ if SOCKET_ACTIVATED.load(Ordering::Relaxed) => format!("socket-activated {}", args.metrics_path),
+                    Some(path) => format!("unix:{} {}", path, args.metrics_path),
+                    None => format!("http://{}:{}{}", args.bind_address, args.metrics_port, args.metrics_path),
+                }),
+                ("version", PKG_VERSION.to_string()),
+                ("build_hash", GIT_HASH.to_string()),
+                ("log_level", format!("{:?}", args.log_level)),
+            ]);
+            handle
+        },
+        Err(e) => {
+            log("ERROR", &format!("Failed to start {} metrics endpoint", PKG_NAME), &[
+                ("error", format!("{:#}", e)),
+                ("bind_address", args.bind_address.clone()),
+                ("port", args.metrics_port.to_string()),
+            ]);
+            return Err(e);
+        }
+    };
+
+    if args.track_hourly {
+        init_hourly_metrics();
+    }
+
+    if let Some(url) = &args.push_gateway {
+        spawn_push_gateway(url, std::time::Duration::from_secs(args.push_interval), metrics_handle.clone());
+    }
+
+    #[cfg(feature = "otlp")]
+    let otlp_provider = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let provider = start_otlp_export(endpoint, std::time::Duration::from_secs(args.otlp_interval))?;
+            log("INFO", "Exporting location metrics over OTLP", &[
+                ("endpoint", endpoint.clone()),
+                ("interval_seconds", args.otlp_interval.to_string()),
+            ]);
+            Some(provider)
+        },
+        None => None,
+    };
+
+    log("DEBUG", "Command line arguments", &effective_config(&args));
+
+    // Initialize update tracker
+    let tracker = Arc::new(Mutex::new(UpdateTracker::new()));
+
+    // Set up the outputs fed by each processed update
+    let events_file = match &args.events_file {
+        Some(path) => Some(Mutex::new(EventsFile::open(path.clone(), args.events_file_max_bytes)
+            .map_err(|e| anyhow::anyhow!("Failed to open events file {}: {}", path.display(), e))?)),
+        None => None,
+    };
+    let sinks = UpdateSinks {
+        graphite: args.graphite_address.clone()
+            .map(|address| GraphiteSink::spawn(address, args.graphite_prefix.clone())),
+        events,
+        events_file,
+        update_command: args.on_update_command.clone()
+            .map(|command| UpdateCommand::new(command, args.on_update_command_concurrency)),
+        scrapes: scrapes.clone(),
+    };
+
+    // The state file is removed on clean shutdown, so one left behind means the last run crashed
+    if let Some(path) = &args.state_dump_file {
+        if path.exists() {
+            match StateSnapshot::read(path) {
+                Ok(previous) => log("WARN", "Found state from a previous run that did not shut down cleanly", &[
+                    ("previous_pid", previous.pid.to_string()),
+                    ("previous_version", previous.version),
+                    ("written_at", previous.written_at.to_string()),
+                    ("uptime_seconds", previous.uptime_seconds.to_string()),
+                    ("geoclue_connected", previous.geoclue_connected.to_string()),
+                    ("received_updates", previous.received_updates.to_string()),
+                    ("last_latitude", previous.last_latitude.map(|lat| lat.to_string()).unwrap_or_default()),
+                    ("last_longitude", previous.last_longitude.map(|lon| lon.to_string()).unwrap_or_default()),
+                ]),
+                Err(e) => log("WARN", "Failed to read previous state file", &[("error", format!("{:#}", e))]),
+            }
+        }
+    }
+
+    // Periodically collect process metrics, refresh the update age and run recorder upkeep
+    let tracker_clone = tracker.clone();
+    let process_metrics = process_collector();
+    let state_dump_file = args.state_dump_file.clone();
+    let _metrics_handle = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
+        loop {
+            interval.tick().await;
+            process_metrics.collect();
+            let (update_age, connection_uptime) = {
+                let tracker = tracker_clone.lock().unwrap();
+                (tracker.update_age_seconds(), tracker.connection_uptime_seconds(std::time::Instant::now()))
+            };
+            metrics::gauge!("geoclue_update_age_seconds").set(update_age);
+            metrics::gauge!(self_metric("geoclue_connection_uptime_seconds")).set(connection_uptime);
+            metrics::gauge!(self_metric("geoclue_seconds_since_last_scrape")).set(scrapes.seconds_since_last_scrape(std::time::Instant::now()));
+            if let Some(path) = &state_dump_file {
+                let snapshot = StateSnapshot::capture(&tracker_clone.lock().unwrap(), started_at.elapsed());
+                if let Err(e) = snapshot.write(path) {
+                    log("WARN", "Failed to write state file", &[("error", format!("{:#}", e))]);
+                }
+            }
+            metrics_handle.run_upkeep();
+        }
+    });
+
+    // Watchdog marking the data unavailable once no update arrived within --data-timeout
+    if args.data_timeout > 0 {
+        let tracker_clone = tracker.clone();
+        let data_timeout = args.data_timeout;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+            let mut was_available = false;
+            loop {
+                interval.tick().await;
+                let available = tracker_clone.lock().unwrap().data_available(data_timeout, std::time::Instant::now());
+                if was_available && !available {
+                    log("WARN", "No location update within data timeout, marking data unavailable", &[
+                        ("data_timeout", data_timeout.to_string()),
+                    ]);
+                    metrics::gauge!("geoclue_data_available").set(0.0);
+                }
+                was_available = available;
+            }
+        });
+    }
+
+    // Shared variables for shutdown handling
+    let shutdown_flag = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    // Wakes the reconnect backoff; notify_one keeps the permit if nobody is waiting yet
+    let shutdown_notify = Arc::new(tokio::sync::Notify::new());
+    let shutdown_notify_clone = shutdown_notify.clone();
+
+    // Handle graceful shutdown; the task yields the signal that triggered it
+    let shutdown_signal = tokio::spawn(async move {
+        let signal_name = match wait_for_shutdown_signal().await {
+            Ok(signal_name) => signal_name,
+            Err(e) => {
+                log("ERROR", "Failed to listen for shutdown signals", &[("error", format!("{}", e))]);
+                return None;
+            }
+        };
+        
+        log("INFO", "Shutdown signal received", &[("signal", signal_name.to_string())]);
+        shutdown_flag_clone.store(true, std::sync::atomic::Ordering::Relaxed);
+        shutdown_notify_clone.notify_one();
+        Some(signal_name)
+    });
+
+    // Main reconnection loop
+    let mut retry_count = 0;
+    let mut backoff_rng = rand::rng();
+    let mut has_connected_before = false;
+    // Whether the missing-daemon WARN has been logged since GeoClue2 was last reachable
+    let mut daemon_missing = false;
+    // Set when the run ended after --max-updates updates rather than on a signal
+    let mut capture_complete = false;
+    
+    loop {
+        // Check if shutdown was requested
+        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            log("INFO", "Shutdown requested, exiting", &[]);
+            break;
+        }
+
+        // Attempt to connect to GeoClue2
+        match setup_geoclue_connection(&args).await {
+            Ok(geoclue_conn) => {
+                log("INFO", "Successfully connected to GeoClue2", &[]);
+                metrics::gauge!(self_metric("geoclue_daemon_present")).set(1.0);
+                daemon_missing = false;
+                if has_connected_before {
+                    metrics::counter!(self_metric("geoclue_reconnects_total")).increment(1);
+                }
+                retry_count = 0; // Reset retry count on successful connection
+                has_connected_before = true; // Mark that we've connected successfully
+                tracker.lock().unwrap().start_session();
+                
+                // Set up shutdown handler for this connection
+                let shutdown_connection = Arc::new(connect_system_bus(&args).await?);
+                let shutdown_clients = geoclue_conn.clients.clone();
+                let shutdown_names = geoclue_conn.names.clone();
+                let shutdown_flag_monitor = shutdown_flag.clone();
+                let shutdown_client_stopped = geoclue_conn.client_stopped.clone();
+                
+                let shutdown_handle = tokio::spawn(async move {
+                    // Wait for shutdown signal
+                    while !shutdown_flag_monitor.load(std::sync::atomic::Ordering::Relaxed) {
+                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                    }
+                    
+                    log("INFO", "Stopping GeoClue2 client for shutdown", &[]);
+                    shutdown_client_stopped.store(true, Ordering::Relaxed);
+                    
+                    for shutdown_client_entry in &shutdown_clients {
+                        // Create a new client proxy specifically for shutdown
+                        match zbus::Proxy::new(
+                            &shutdown_connection,
+                            shutdown_names.service.as_str(),
+                            &shutdown_client_entry.path,
+                            shutdown_names.client_interface()
+                        ).await {
+                            Ok(shutdown_client) => {
+                                // Call Stop on the client for clean shutdown
+                                if let Err(e) = dbus_call("Stop", shutdown_client.call::<_, _, ()>("Stop", &())).await {
+                                    log("ERROR", "Failed to stop GeoClue2 client", &[("error", format!("{}", e))]);
+                                } else {
+                                    log("INFO", "GeoClue2 client stopped successfully", &[
+                                        ("client", shutdown_client_entry.label.clone()),
+                                    ]);
+                                }
+                            },
+                            Err(e) => {
+                                log("ERROR", "Failed to create shutdown client proxy", &[("error", format!("{}", e))]);
+                            }
+                        }
+                    }
+                    
+                    // Set the "up" metric to 0 to indicate the exporter is shutting down
+                    set_connection_up(false);
+                    metrics::gauge!("up").set(0.0);
+                });
+
+                // Monitor location updates
+                let monitoring_result = monitor_location_updates(&args, &geoclue_conn, tracker.clone(), &sinks).await;
+
+                // Monitoring only completes once --max-updates is reached; shut down as on a signal
+                if monitoring_result.is_ok() {
+                    capture_complete = true;
+                    shutdown_flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                
+                // Cancel shutdown handler if we're not shutting down
+                if !shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
+                    shutdown_handle.abort();
+                }
+                
+                // Handle monitoring result
+                match monitoring_result {
+                    Ok(_) => {
+                        log("INFO", "Location monitoring completed normally", &[]);
+                        // Wait for the shutdown handler to stop the clients
+                        let _ = shutdown_handle.await;
+                        break;
+                    },
+                    Err(e) => {
+                        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
+                            log("INFO", "Location monitoring stopped due to shutdown", &[]);
+                            // Wait for shutdown handler to complete
+                            let _ = shutdown_handle.await;
+                            break;
+                        } else if is_disconnection_error(&e, has_connected_before) {
+                            set_connection_up(false);
+                            tracker.lock().unwrap().end_session();
+                            log("WARN", "GeoClue2 connection lost, will attempt to reconnect", &[
+                                ("error", format!("{:#}", e)),
+                                ("retry_count", retry_count.to_string()),
+                            ]);
+                            // Continue to retry logic
+                        } else {
+                            log("ERROR", "Non-recoverable error in location monitoring", &[
+                                ("error", format!("{:#}", e)),
+                            ]);
+                            return Err(e);
+                        }
+                    }
+                }
+            },
+            Err(e) if is_daemon_missing_error(&e) => {
+                // Keep serving metrics and look for the daemon again after the backoff,
+                // warning only once until it shows up
+                set_connection_up(false);
+                metrics::gauge!(self_metric("geoclue_daemon_present")).set(0.0);
+                let level = if daemon_missing { "DEBUG" } else { "WARN" };
+                log(level, "GeoClue2 is not available on the system bus, will keep retrying", &[
+                    ("error", format!("{:#}", e)),
+                    ("retry_count", retry_count.to_string()),
+                ]);
+                daemon_missing = true;
+            },
+            Err(e) => {
+                set_connection_up(false);
+                log("WARN", "Failed to connect to GeoClue2", &[
+                    ("error", format!("{:#}", e)),
+                    ("retry_count", retry_count.to_string()),
+                ]);
+                
+                if is_disconnection_error(&e, has_connected_before) {
+                    log("INFO", "Error identified as disconnection, will retry", &[
+                        ("error", format!("{:#}", e)),
+                    ]);
+                } else {
+                    log("ERROR", "Non-recoverable error connecting to GeoClue2", &[
+                        ("error", format!("{:#}", e)),
+                    ]);
+                    return Err(e);
+                }
+            }
+        }
+
+        // Check if shutdown was requested before sleeping
+        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            break;
+        }
+
+        // Calculate exponential backoff delay
+        retry_count += 1;
+        let delay = jittered_backoff(retry_count, args.max_backoff, &mut backoff_rng);
+        
+        log(if daemon_missing { "DEBUG" } else { "INFO" }, "Waiting before reconnection attempt", &[
+            ("delay_seconds", format!("{:.3}", delay.as_secs_f64())),
+            ("retry_count", retry_count.to_string()),
+        ]);
+        log("DEBUG", "Reconnect backoff", &[
+            ("backoff_seconds", backoff_cap(retry_count, args.max_backoff).to_string()),
+            ("max_backoff", args.max_backoff.to_string()),
+        ]);
+        
+        // Waiting for GeoClue2 can last indefinitely, so a signal must cut the backoff short
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = shutdown_notify.notified() => {},
+        }
+    }
+
+    log("INFO", "Exporter shutting down", &[]);
+
+    // Send the final values before exiting
+    #[cfg(feature = "otlp")]
+    if let Some(provider) = otlp_provider {
+        if let Err(e) = provider.shutdown() {
+            log("WARN", "Failed to flush OTLP metrics", &[("error", format!("{}", e))]);
+        }
+    }
+
+    if let (Ok(Some(path)), false) = (unix_socket_path(&args.bind_address), SOCKET_ACTIVATED.load(Ordering::Relaxed)) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            log("WARN", "Failed to remove metrics socket", &[
+                ("socket", path.display().to_string()),
+                ("error", format!("{}", e)),
+            ]);
+        }
+    }
+
+    if let Some(path) = &args.state_dump_file {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log("WARN", "Failed to remove state file", &[("error", format!("{}", e))]);
+            }
+        }
+    }
+
+    if let Some(url) = &args.shutdown_webhook {
+        // Only a signal-triggered shutdown has finished the task; otherwise report why monitoring ended
+        let reason = if capture_complete {
+            "max_updates"
+        } else if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
+            shutdown_signal.await.ok().flatten().unwrap_or("signal")
+        } else {
+            "monitoring_completed"
+        };
+        let payload = shutdown_webhook_payload(reason, started_at.elapsed(), tracker.lock().unwrap().last_reading.as_ref());
+        send_shutdown_webhook(url, payload, SHUTDOWN_WEBHOOK_TIMEOUT).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    
+    // Test the log level logic functions
+    #[test]
+    fn test_should_log() {
+        // Test Debug level
+        set_log_level(LogLevel::Debug);
+        assert!(should_log(LogLevel::Debug));
+        assert!(should_log(LogLevel::Info));
+        assert!(should_log(LogLevel::Warn));
+        assert!(should_log(LogLevel::Error));
+        
+        // Test Info level
+        set_log_level(LogLevel::Info);
+        assert!(!should_log(LogLevel::Debug));
+        assert!(should_log(LogLevel::Info));
+        assert!(should_log(LogLevel::Warn));
+        assert!(should_log(LogLevel::Error));
+        
+        // Test Warn level
+        set_log_level(LogLevel::Warn);
+        assert!(!should_log(LogLevel::Debug));
+        assert!(!should_log(LogLevel::Info));
+        assert!(should_log(LogLevel::Warn));
+        assert!(should_log(LogLevel::Error));
+        
+        // Test Error level
+        set_log_level(LogLevel::Error);
+        assert!(!should_log(LogLevel::Debug));
+        assert!(!should_log(LogLevel::Info));
+        assert!(!should_log(LogLevel::Warn));
+        assert!(should_log(LogLevel::Error));
+        assert!(!should_log(LogLevel::Trace));
+
+        // Test Trace level
+        set_log_level(LogLevel::Trace);
+        assert!(should_log(LogLevel::Trace));
+        assert!(should_log(LogLevel::Debug));
+        assert!(should_log(LogLevel::Info));
+        assert!(should_log(LogLevel::Warn));
+        assert!(should_log(LogLevel::Error));
+
+        // Trace is suppressed at Debug and above
+        set_log_level(LogLevel::Debug);
+        assert!(!should_log(LogLevel::Trace));
+        set_log_level(LogLevel::Info);
+        assert!(!should_log(LogLevel::Trace));
+        set_log_level(LogLevel::Warn);
+        assert!(!should_log(LogLevel::Trace));
+
+        // The trace level is accepted on the command line
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--log-level", "trace"]).unwrap();
+        assert_eq!(args.log_level, LogLevel::Trace);
+    }
+    
+    // Test which log level a config reload applies
+    #[test]
+    fn test_reloaded_log_level() {
+        let path = std::env::temp_dir().join(format!("{}-reload-{}.toml", PKG_NAME, std::process::id()));
+
+        std::fs::write(&path, "log_level = \"debug\"\ndistance_threshold = 50\n").unwrap();
+        assert_eq!(reloaded_log_level(&path, false).unwrap(), Some(LogLevel::Debug));
+
+        // --log-level on the command line keeps precedence over the file
+        assert_eq!(reloaded_log_level(&path, true).unwrap(), None);
+
+        std::fs::write(&path, "distance_threshold = 50\n").unwrap();
+        assert_eq!(reloaded_log_level(&path, false).unwrap(), None);
+
+        std::fs::write(&path, "log_level = \"loud\"\n").unwrap();
+        assert!(reloaded_log_level(&path, false).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded_log_level(&path, false).unwrap(), None);
+    }
+
+    // Test text and JSON log line formatting
+    #[test]
+    fn test_format_log_line() {
+        let fields = [("path", "/org/freedesktop/GeoClue2/Client/1".to_string())];
+
+        let text = format_log_line(LogFormat::Text, "2024-03-01 07:15:00.000", "INFO", "Got client path", &fields);
+        assert_eq!(text, "timestamp=\"2024-03-01 07:15:00.000\" level=INFO message=\"Got client path\" path=/org/freedesktop/GeoClue2/Client/1");
+
+        // Quotes, backslashes and newlines must survive a JSON round trip
+        let message = "quote \" backslash \\ newline \n done";
+        let json = format_log_line(LogFormat::Json, "2024-03-01 07:15:00.000", "WARN", message, &fields);
+        assert!(!json.contains('\n'));
+
+        let entry: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(entry["timestamp"], "2024-03-01 07:15:00.000");
+        assert_eq!(entry["level"], "WARN");
+        assert_eq!(entry["message"], message);
+        assert_eq!(entry["path"], "/org/freedesktop/GeoClue2/Client/1");
+    }
+
+    // Test that every TRACE line of one update cycle carries the same correlation ID
+    #[test]
+    fn test_update_cycle_correlation_id() {
+        let cycle = UpdateCycle::next();
+        let calls = [
+            ("Raw LocationUpdated signal", vec![("client", "street".to_string())]),
+            ("Fetched location property", vec![("property", "Latitude".to_string())]),
+            ("Fetched location property", vec![("property", "Longitude".to_string())]),
+            ("Set location gauges", vec![("metrics", "latitude,longitude".to_string())]),
+        ];
+
+        let expected = format!(" cycle={} ", cycle.id);
+        for (message, fields) in &calls {
+            let line = format_log_line(LogFormat::Text, "2024-03-01 07:15:00.000", "TRACE", message, &cycle.fields(fields));
+            assert!(line.contains(&expected), "{}", line);
+        }
+
+        // The next cycle gets a fresh ID
+        assert_ne!(UpdateCycle::next().id, cycle.id);
+    }
+
+    // Test the set_gauge_if_valid function
+    #[test]
+    fn test_set_gauge_if_valid() {
+        // Test with valid values
+        assert_eq!(set_gauge_if_valid("latitude", 35.123, 35.123, &ValidationConfig::default()), Ok(()));
+        assert_eq!(set_gauge_if_valid("longitude", 135.456, 135.456, &ValidationConfig::default()), Ok(()));
+        assert_eq!(set_gauge_if_valid("accuracy", 10.5, 10.5, &ValidationConfig::default()), Ok(()));
+        assert_eq!(set_gauge_if_valid("altitude", 123.4, 123.4, &ValidationConfig::default()), Ok(()));
+        assert_eq!(set_gauge_if_valid("speed", 5.2, 5.2, &ValidationConfig::default()), Ok(()));
+        assert_eq!(set_gauge_if_valid("heading", 270.0, 270.0, &ValidationConfig::default()), Ok(()));
+        
+        // Test with invalid values (should return the reason)
+        assert_eq!(set_gauge_if_valid("latitude", -1.0, -1.0, &ValidationConfig::default()), Err(InvalidMetric::Unavailable));
+        assert!(matches!(set_gauge_if_valid("longitude", -1.7e308, -1.7e308, &ValidationConfig::default()), Err(InvalidMetric::OutOfRange { .. })));
+        
+        // Test with unknown metric name
+        assert_eq!(set_gauge_if_valid("unknown_metric", 123.0, 123.0, &ValidationConfig::default()), Err(InvalidMetric::UnknownMetric));
+    }
+
+    // Test the values mirrored for the OTLP export follow the last value written per metric
+    #[test]
+    fn test_exported_values() {
+        let values = ExportedValues::new();
+        assert_eq!(values.get("latitude"), None);
+
+        values.record("latitude", 52.52);
+        values.record("heading", 90.0);
+        values.record("latitude", 52.53);
+        values.record("unknown", 1.0);
+        assert_eq!(values.get("latitude"), Some(52.53));
+        assert_eq!(values.get("heading"), Some(90.0));
+        assert_eq!(values.get("speed"), None);
+        assert_eq!(values.get("unknown"), None);
+
+        // Without the feature the endpoint is refused instead of silently ignored
+        let result = args_from_matches(&Args::command().get_matches_from(["geoclue-prometheus-exporter", "--otlp-endpoint", "http://localhost:4318/v1/metrics"]));
+        assert_eq!(result.is_ok(), cfg!(feature = "otlp"));
+    }
+
+    // Test per-client gauges apply the same validation as the primary gauges
+    #[test]
+    fn test_client_gauges_validation() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--valid-range", "accuracy=0:1000"]).unwrap();
+        let validation = ValidationConfig::from_args(&args);
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            set_client_gauges("wifi", &LocationReading {
+                latitude: f64::NAN,
+                longitude: 13.405,
+                accuracy: 5000.0,
+                altitude: 34.0,
+                speed: -2.0,
+                heading: -5.0,
+                satellites: None,
+            }, &CoordinateCoarsening::default(), &validation);
+        });
+
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_client_longitude{client=\"wifi\"} 13.405"), "{}", rendered);
+        assert!(rendered.contains("geoclue_client_altitude{client=\"wifi\"} 34"), "{}", rendered);
+        for rejected in ["latitude", "accuracy", "speed", "heading"] {
+            assert!(!rendered.contains(&format!("geoclue_client_{}{{", rejected)), "{}", rendered);
+        }
+    }
+
+    // Test --valid-range parsing and that configured bounds reject values outside them
+    #[test]
+    fn test_validation_config() {
+        let args = Args::try_parse_from([
+            "geoclue-prometheus-exporter",
+            "--valid-range", "heading=0:359.999",
+            "--valid-range", "latitude=-90:90",
+            "--valid-range", "latitude=-60:60",
+        ]).unwrap();
+        let validation = ValidationConfig::from_args(&args);
+        assert_eq!(validation.range("heading"), Some((0.0, 359.999)));
+        assert_eq!(validation.range("latitude"), Some((-60.0, 60.0)));
+        assert_eq!(validation.range("longitude"), None);
+
+        assert_eq!(set_gauge_if_valid("heading", 359.999, 359.999, &validation), Ok(()));
+        assert_eq!(set_gauge_if_valid("heading", 360.0, 360.0, &validation), Err(InvalidMetric::OutOfRange { min: 0.0, max: 359.999 }));
+        assert_eq!(set_gauge_if_valid("latitude", -61.0, -61.0, &validation), Err(InvalidMetric::OutOfRange { min: -60.0, max: 60.0 }));
+        assert_eq!(set_gauge_if_valid("longitude", 500.0, 500.0, &validation), Ok(()));
+
+        // Sentinels are still reported as such inside a configured range
+        assert_eq!(set_gauge_if_valid("latitude", -1.0, -1.0, &validation), Err(InvalidMetric::Unavailable));
+
+        // The default accepts what it always did
+        assert_eq!(set_gauge_if_valid("heading", 360.0, 360.0, &ValidationConfig::default()), Ok(()));
+
+        for invalid in ["heading", "heading=0", "colour=0:1", "heading=10:0", "heading=0:inf"] {
+            assert!(parse_value_range(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    // Test the availability gauges of optional values, and that unknown values keep the last one
+    #[test]
+    fn test_optional_value_availability() {
+        // Below sea level is a real altitude, a negative speed or heading is not
+        assert_eq!(invalid_metric_value("altitude", -28.0, &ValidationConfig::default()), None);
+        assert!(matches!(invalid_metric_value("altitude", -f64::MAX, &ValidationConfig::default()), Some(InvalidMetric::OutOfRange { .. })));
+        assert_eq!(invalid_metric_value("speed", -0.5, &ValidationConfig::default()), Some(InvalidMetric::Unavailable));
+        assert_eq!(invalid_metric_value("heading", -1.0, &ValidationConfig::default()), Some(InvalidMetric::Unavailable));
+        assert_eq!(invalid_metric_value("heading", 0.0, &ValidationConfig::default()), None);
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let update = |alt: f64| metrics::with_local_recorder(&recorder, || {
+            set_availability_gauge("altitude", invalid_metric_value("altitude", alt, &ValidationConfig::default()).is_none());
+            set_location_gauge("altitude", alt, alt, &ValidationConfig::default());
+        });
+
+        update(34.0);
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_altitude_available 1"), "{}", rendered);
+        assert!(rendered.contains("geoclue_altitude 34"), "{}", rendered);
+
+        update(-f64::MAX);
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_altitude_available 0"), "{}", rendered);
+        assert!(rendered.contains("geoclue_altitude 34"), "{}", rendered);
+    }
+
+    // Test NaN coordinates are rejected and counted without setting the gauges
+    #[test]
+    fn test_nan_coordinates() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            assert!(!set_location_gauge("latitude", f64::NAN, f64::NAN, &ValidationConfig::default()));
+            assert!(!set_location_gauge("longitude", f64::NAN, f64::NAN, &ValidationConfig::default()));
+            // NaN in other properties is rejected but is not a coordinate bug
+            assert!(!set_location_gauge("speed", f64::NAN, f64::NAN, &ValidationConfig::default()));
+            assert!(set_location_gauge("accuracy", 25.0, 25.0, &ValidationConfig::default()));
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_nan_coordinates_total 2"), "{}", rendered);
+        assert!(rendered.contains("geoclue_rejected_values_total{metric=\"speed\",reason=\"nan\"} 1"), "{}", rendered);
+        assert!(!rendered.contains("geoclue_latitude "), "{}", rendered);
+        assert!(!rendered.contains("geoclue_longitude "), "{}", rendered);
+        assert!(NAN_COORDINATES_WARNED.load(Ordering::Relaxed));
+    }
+    
+    // Test the get_version_string function
+    #[test]
+    fn test_version_string_format() {
+        let version_str = get_version_string();
+        
+        // Verify it contains the expected components
+        assert!(version_str.contains(PKG_NAME));
+        assert!(version_str.contains(PKG_VERSION));
+        assert!(version_str.contains("Build:"));
+    }
+    
+    // Test AccuracyLevel conversion
+    #[test]
+    fn test_accuracy_level_conversion() {
+        // Test all conversions
+        assert_eq!(AccuracyLevel::None as u32, 0);
+        assert_eq!(AccuracyLevel::Country as u32, 1);
+        assert_eq!(AccuracyLevel::City as u32, 4);
+        assert_eq!(AccuracyLevel::Neighborhood as u32, 5);
+        assert_eq!(AccuracyLevel::Street as u32, 6);
+        assert_eq!(AccuracyLevel::Exact as u32, 8);
+        
+        // Test from conversions
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::None), AccuracyLevel::None));
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Country), AccuracyLevel::Country));
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::City), AccuracyLevel::City));
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Neighborhood), AccuracyLevel::Neighborhood));
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Street), AccuracyLevel::Street));
+        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Exact), AccuracyLevel::Exact));
+
+        // Names and GeoClue2 numeric values select the same level
+        let level = |value: &str| Args::try_parse_from(["geoclue-prometheus-exporter", "--accuracy-level", value])
+            .map(|args| args.accuracy_level);
+        assert_eq!(level("street").unwrap(), level("6").unwrap());
+        assert_eq!(level("8").unwrap(), AccuracyLevelArg::Exact);
+        assert_eq!(level("0").unwrap(), AccuracyLevelArg::None);
+
+        // Non-standard values round down, out-of-range values are rejected
+        assert_eq!(level("7").unwrap(), AccuracyLevelArg::Street);
+        assert_eq!(level("3").unwrap(), AccuracyLevelArg::Country);
+        assert_eq!(rounded_accuracy_level("7"), Some(7));
+        assert_eq!(rounded_accuracy_level("6"), None);
+        assert_eq!(rounded_accuracy_level("street"), None);
+        assert!(level("9").is_err());
+        assert!(level("precise").is_err());
+    }
+    
+    // Test the state snapshot survives a write/read round trip
+    #[test]
+    fn test_state_snapshot_round_trip() {
+        let mut tracker = UpdateTracker::new();
+        tracker.received_updates = 12;
+        tracker.last_update = Some(std::time::Instant::now());
+        tracker.last_reading = Some(LocationReading {
+            latitude: 35.5,
+            longitude: 139.7,
+            accuracy: 12.0,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites: None,
+        });
+        let snapshot = StateSnapshot::capture(&tracker, std::time::Duration::from_secs(600));
+        assert_eq!(snapshot.uptime_seconds, 600);
+        assert_eq!(snapshot.last_latitude, Some(35.5));
+
+        let path = std::env::temp_dir().join(format!("{}-state-{}.json", PKG_NAME, std::process::id()));
+        snapshot.write(&path).unwrap();
+        assert_eq!(StateSnapshot::read(&path).unwrap(), snapshot);
+
+        // Later snapshots replace the file
+        let empty = StateSnapshot::capture(&UpdateTracker::new(), std::time::Duration::from_secs(5));
+        empty.write(&path).unwrap();
+        assert_eq!(StateSnapshot::read(&path).unwrap().last_latitude, None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Test UpdateTracker functionality
+    #[test]
+    fn test_update_tracker() {
+        let tracker = Arc::new(Mutex::new(UpdateTracker::new()));
+        
+        // Simulate receiving updates
+        {
+            let mut tracker_guard = tracker.lock().unwrap();
+            tracker_guard.received_updates += 1;
+            assert_eq!(tracker_guard.received_updates, 1);
+        }
+        
+        // Simulate another update
+        {
+            let mut tracker_guard = tracker.lock().unwrap();
+            tracker_guard.received_updates += 1;
+            assert_eq!(tracker_guard.received_updates, 2);
+        }
+
+        // Data is unavailable before the first update and once older than the timeout
+        {
+            let mut tracker_guard = tracker.lock().unwrap();
+            let now = std::time::Instant::now();
+            assert!(!tracker_guard.data_available(0, now));
+
+            tracker_guard.last_update = Some(now);
+            assert!(tracker_guard.data_available(30, now + std::time::Duration::from_secs(30)));
+            assert!(!tracker_guard.data_available(30, now + std::time::Duration::from_secs(31)));
+            assert!(tracker_guard.data_available(0, now + std::time::Duration::from_secs(3600)));
+            tracker_guard.last_update = None;
+        }
+
+        // The update age is -1 until the first processed update, then counts up from zero
+        {
+            let mut tracker_guard = tracker.lock().unwrap();
+            assert_eq!(tracker_guard.update_age_seconds(), -1.0);
+
+            tracker_guard.last_update = Some(std::time::Instant::now() - std::time::Duration::from_secs(42));
+            let age = tracker_guard.update_age_seconds();
+            assert!((42.0..43.0).contains(&age), "unexpected age {}", age);
+        }
+    }
+    
+    // Test that session accuracy aggregates and the connection uptime reset on reconnect
+    #[test]
+    fn test_session_accuracy_stats() {
+        let mut tracker = UpdateTracker::new();
+        assert_eq!(tracker.session_accuracy.average(), None);
+
+        for accuracy in [20.0, 5.0, 35.0] {
+            record_accuracy_sample(&mut tracker, AccuracyLevel::Street, accuracy);
+        }
+        assert_eq!(tracker.session_accuracy.count, 3);
+        assert_eq!(tracker.session_accuracy.min, 5.0);
+        assert_eq!(tracker.session_accuracy.max, 35.0);
+        assert_eq!(tracker.session_accuracy.average(), Some(20.0));
+
+        // A simulated reconnect starts a fresh session
+        tracker.start_session();
+        assert_eq!(tracker.session_accuracy, AccuracyStats::default());
+        let later = std::time::Instant::now() + std::time::Duration::from_secs(120);
+        assert!(tracker.connection_uptime_seconds(later) >= 120.0);
+        tracker.end_session();
+        assert_eq!(tracker.connection_uptime_seconds(later), 0.0);
+        tracker.start_session();
+        assert!(tracker.connection_uptime_seconds(std::time::Instant::now()) < 1.0);
+
+        record_accuracy_sample(&mut tracker, AccuracyLevel::Street, 1200.0);
+        assert_eq!(tracker.session_accuracy.min, 1200.0);
+        assert_eq!(tracker.session_accuracy.max, 1200.0);
+        assert_eq!(tracker.session_accuracy.average(), Some(1200.0));
+    }
+
+    // Test hour-of-day bucketing of update timestamps
+    #[test]
+    fn test_update_hour() {
+        let morning: DateTime<Utc> = "2024-03-01T07:15:00Z".parse().unwrap();
+        let afternoon: DateTime<Utc> = "2024-03-01T14:59:59Z".parse().unwrap();
+        let midnight: DateTime<Utc> = "2024-03-02T00:00:00Z".parse().unwrap();
+
+        assert_eq!(update_hour(morning, LogTimezone::Utc), 7);
+        assert_eq!(update_hour(afternoon, LogTimezone::Utc), 14);
+        assert_eq!(update_hour(midnight, LogTimezone::Utc), 0);
+
+        // Local time buckets follow the system timezone
+        assert_eq!(update_hour(afternoon, LogTimezone::Local), afternoon.with_timezone(&Local).hour());
+    }
+
+    // Test that a reading is pushed to Graphite as a plaintext line
+    #[tokio::test]
+    async fn test_graphite_sink_delivers_lines() {
+        use tokio::io::AsyncBufReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let sink = GraphiteSink::spawn(address, "geoclue".to_string());
+        sink.send(&[("latitude", 35.5), ("longitude", -120.25)], 1700000000);
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut reader = tokio::io::BufReader::new(socket);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "geoclue.latitude 35.5 1700000000\n");
+
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "geoclue.longitude -120.25 1700000000\n");
+
+        // An empty prefix leaves the bare metric name
+        assert_eq!(graphite_line("", "speed", 1.5, 42), "speed 1.5 42\n");
+    }
+
+    // HTTP state serving /metrics with every optional feature off; tests override fields
+    fn test_http_state(handle: PrometheusHandle) -> HttpState {
+        HttpState {
+            handle,
+            metrics_path: "/metrics".to_string(),
+            events: None,
+            scrapes: Arc::new(ScrapeTracker::new()),
+            validate_output: false,
+            header_timeout: None,
+            request_timeout: None,
+            allowed_networks: Vec::new(),
+            hmac_key: None,
+        }
+    }
+
+    // Send a GET request to the test server and return the raw response
+    async fn http_get(address: SocketAddr, path: &str) -> String {
+        http_get_with_headers(address, path, &[]).await
+    }
+
+    // Send a GET request with extra headers and return the raw response
+    async fn http_get_with_headers(address: SocketAddr, path: &str, headers: &[(&str, &str)]) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", path, headers);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    // Test that the time since the last scrape grows until a scrape resets it
+    #[tokio::test]
+    async fn test_seconds_since_last_scrape() {
+        let scrapes = Arc::new(ScrapeTracker::new());
+        let later = std::time::Instant::now() + std::time::Duration::from_secs(90);
+        assert!(scrapes.seconds_since_last_scrape(later) >= 90.0);
+
+        // A scrape of the metrics path resets the age
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            scrapes: scrapes.clone(),
+            ..test_http_state(recorder.handle())
+        })));
+
+        // Other endpoints do not count as scrapes
+        http_get(address, "/health").await;
+        assert!(scrapes.seconds_since_last_scrape(later) >= 90.0);
+
+        http_get(address, "/metrics").await;
+        assert!(scrapes.seconds_since_last_scrape(std::time::Instant::now()) < 1.0);
+        assert!(scrapes.seconds_since_last_scrape(later) < 90.0);
+    }
+
+    // Test a client that never finishes its request headers is disconnected and counted
+    #[tokio::test]
+    async fn test_http_header_timeout() {
+        use tokio::io::AsyncReadExt;
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            header_timeout: Some(std::time::Duration::from_millis(200)),
+            request_timeout: Some(std::time::Duration::from_secs(5)),
+            ..test_http_state(recorder.handle())
+        })));
+
+        // Complete requests are still answered
+        assert!(http_get(address, "/health").await.starts_with("HTTP/1.1 200"));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: local").await.unwrap();
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read_to_end(&mut response)).await;
+        assert!(read.is_ok(), "slow connection was not closed");
+        assert!(!String::from_utf8_lossy(&response).contains("200 OK"));
+
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_http_timeouts_total{kind=\"header\"} 1"), "{}", rendered);
+    }
+
+    // Test a signed metrics response verifies against its body with the configured key
+    #[tokio::test]
+    async fn test_metrics_signature() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || metrics::gauge!("geoclue_latitude").set(52.52));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            hmac_key: Some(b"scrape-secret".to_vec()),
+            ..test_http_state(recorder.handle())
+        })));
+
+        let response = http_get(address, "/metrics").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(body.contains("geoclue_latitude 52.52"), "{}", body);
+        let signature = head.lines()
+            .find_map(|line| line.strip_prefix("x-metrics-signature: "))
+            .expect("signature header");
+        let hex = signature.strip_prefix("sha256=").unwrap();
+        let digest: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+
+        // Verify the way a scraper would, in constant time
+        let verify = |key: &[u8], body: &str| {
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).unwrap();
+            mac.update(body.as_bytes());
+            mac.verify_slice(&digest).is_ok()
+        };
+        assert!(verify(b"scrape-secret", body));
+        assert!(!verify(b"other-secret", body));
+        assert!(!verify(b"scrape-secret", &body.replace("52.52", "52.53")));
+
+        // Reference value for the documented scheme
+        assert_eq!(metrics_signature(b"key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--hmac-key", ""]).is_err());
+    }
+
+    // Test --allow-cidr parsing and that clients outside the allowed networks get 403
+    #[tokio::test]
+    async fn test_allow_cidr() {
+        let ip = |address: &str| address.parse::<std::net::IpAddr>().unwrap();
+        let networks = vec![parse_cidr("10.0.0.0/8").unwrap(), parse_cidr("fd00::/8").unwrap(), parse_cidr("192.168.1.5").unwrap()];
+        assert!(is_peer_allowed(&networks, ip("10.1.2.3")));
+        assert!(is_peer_allowed(&networks, ip("::ffff:10.1.2.3")));
+        assert!(is_peer_allowed(&networks, ip("fd12::1")));
+        assert!(is_peer_allowed(&networks, ip("192.168.1.5")));
+        assert!(!is_peer_allowed(&networks, ip("192.168.1.6")));
+        assert!(!is_peer_allowed(&networks, ip("127.0.0.1")));
+        assert!(is_peer_allowed(&[], ip("203.0.113.7")));
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("localhost").is_err());
+
+        let serve = |allowed_networks: Vec<ipnet::IpNet>| async move {
+            let recorder = PrometheusBuilder::new().build_recorder();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+                allowed_networks,
+                ..test_http_state(recorder.handle())
+            })));
+            address
+        };
+
+        let address = serve(vec![parse_cidr("10.0.0.0/8").unwrap()]).await;
+        let response = http_get(address, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(http_get(address, "/health").await.starts_with("HTTP/1.1 403"));
+
+        let address = serve(vec![parse_cidr("127.0.0.0/8").unwrap()]).await;
+        let response = http_get(address, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    // Test conditional scrapes get 304 until a location update is applied
+    #[tokio::test]
+    async fn test_conditional_scrape() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || metrics::gauge!("geoclue_latitude").set(52.52));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let scrapes = Arc::new(ScrapeTracker::new());
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            scrapes: scrapes.clone(),
+            ..test_http_state(recorder.handle())
+        })));
+
+        let header = |response: &str, name: &str| response.lines()
+            .find_map(|line| line.strip_prefix(&format!("{}: ", name)).map(str::to_string))
+            .unwrap_or_else(|| panic!("no {} header in {}", name, response));
+
+        let response = http_get(address, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let last_modified = header(&response, "last-modified");
+        let etag = header(&response, "etag");
+
+        // Nothing changed since the first scrape
+        let response = http_get_with_headers(address, "/metrics", &[("If-Modified-Since", &last_modified)]).await;
+        assert!(response.starts_with("HTTP/1.1 304"), "{}", response);
+        assert!(!response.contains("geoclue_latitude"), "{}", response);
+        let response = http_get_with_headers(address, "/metrics", &[("If-None-Match", &etag)]).await;
+        assert!(response.starts_with("HTTP/1.1 304"), "{}", response);
+
+        // Upkeep rewriting the age and process metrics is not a new version
+        metrics::with_local_recorder(&recorder, || metrics::gauge!("geoclue_update_age_seconds").set(15.0));
+        let response = http_get_with_headers(address, "/metrics", &[("If-None-Match", &etag)]).await;
+        assert!(response.starts_with("HTTP/1.1 304"), "{}", response);
+
+        // A location update, even within the same second, produces a new version
+        metrics::with_local_recorder(&recorder, || metrics::gauge!("geoclue_latitude").set(52.53));
+        scrapes.record_location_update(Utc::now());
+        let response = http_get_with_headers(address, "/metrics", &[("If-Modified-Since", &last_modified)]).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("geoclue_latitude 52.53"), "{}", response);
+        assert_ne!(header(&response, "etag"), etag);
+        let response = http_get_with_headers(address, "/metrics", &[("If-None-Match", &etag)]).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // Last-Modified is the time of the update, not of the scrape that first served it
+        let updated_at = DateTime::from_timestamp(2_000_000_000, 0).unwrap();
+        scrapes.record_location_update(updated_at);
+        let response = http_get(address, "/metrics").await;
+        assert_eq!(header(&response, "last-modified"), "Wed, 18 May 2033 03:33:20 GMT");
+    }
+
+    // Test --on-update-command receives the update in its environment and is capped
+    #[tokio::test]
+    async fn test_update_command() {
+        let path = std::env::temp_dir().join(format!("{}-update-command-{}", PKG_NAME, std::process::id()));
+        let command = UpdateCommand::new(
+            format!("echo \"$GEOCLUE_LAT $GEOCLUE_LON $GEOCLUE_ACCURACY $GEOCLUE_SOURCE $GEOCLUE_TIMESTAMP ${{GEOCLUE_SPEED:-none}}\" > {}", path.display()),
+            1
+        );
+
+        let readings = [("latitude", 52.52), ("longitude", 13.405), ("accuracy", 25.0)];
+        command.run(&readings, "street", 1_700_000_000).unwrap().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "52.52 13.405 25 street 1700000000 none\n");
+        std::fs::remove_file(&path).unwrap();
+
+        // A second update while the only slot is busy is dropped and counted
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        let slow = UpdateCommand::new("sleep 1".to_string(), 1);
+        let running = slow.run(&readings, "street", 1_700_000_000).unwrap();
+        assert!(slow.run(&readings, "street", 1_700_000_001).is_none());
+        assert!(recorder.handle().render().contains("geoclue_update_command_dropped_total 1"));
+        running.abort();
+    }
+
+    // Test --default-position is validated and exported until a real fix overwrites it
+    #[test]
+    fn test_default_position() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--default-position", "-33.87, 151.21"]).unwrap();
+        assert_eq!(args.default_position, Some((-33.87, 151.21)));
+        for invalid in ["52.5", "52.5,north", "91,0", "0,-180.5", "NaN,0"] {
+            assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--default-position", invalid]).is_err(), "{}", invalid);
+        }
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || seed_default_position(args.default_position));
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_latitude -33.87"), "{}", rendered);
+        assert!(rendered.contains("geoclue_longitude 151.21"), "{}", rendered);
+        assert!(rendered.contains("geoclue_using_default_position 1"), "{}", rendered);
+
+        // The first live fix replaces the coordinates and clears the flag
+        let readings = [("latitude", 52.52), ("longitude", 13.405)];
+        metrics::with_local_recorder(&recorder, || {
+            if readings.iter().all(|(name, value)| set_location_gauge(name, *value, *value, &ValidationConfig::default())) {
+                clear_default_position();
+            }
+        });
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_latitude 52.52"), "{}", rendered);
+        assert!(rendered.contains("geoclue_using_default_position 0"), "{}", rendered);
+
+        // Without a default the coordinates are left for the first fix
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || seed_default_position(None));
+        let rendered = recorder.handle().render();
+        assert!(!rendered.contains("geoclue_latitude"), "{}", rendered);
+        assert!(rendered.contains("geoclue_using_default_position 0"), "{}", rendered);
+    }
+
+    // Test --disable-metrics removes a location metric from the output and rejects unknown names
+    #[test]
+    fn test_disable_metrics() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--disable-metrics", "altitude"]).unwrap();
+        assert_eq!(args.disable_metrics, vec!["altitude".to_string()]);
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--disable-metrics", "altitude,colour"]).is_err());
+
+        disable_location_metrics(&args.disable_metrics);
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            describe_metrics();
+            assert!(set_location_gauge("latitude", 52.52, 52.52, &ValidationConfig::default()));
+            assert!(set_location_gauge("altitude", 34.0, 34.0, &ValidationConfig::default()));
+            // A sentinel is still rejected, so it never counts as a valid reading
+            assert!(matches!(set_gauge_if_valid("altitude", -1.7e308, -1.7e308, &ValidationConfig::default()), Err(InvalidMetric::OutOfRange { .. })));
+            assert!(!set_location_gauge("altitude", f64::NAN, f64::NAN, &ValidationConfig::default()));
+            set_client_gauges("street", &LocationReading {
+                latitude: 52.52,
+                longitude: 13.405,
+                accuracy: 20.0,
+                altitude: 34.0,
+                speed: -1.0,
+                heading: -1.0,
+                satellites: None,
+            }, &CoordinateCoarsening::default(), &ValidationConfig::default());
+        });
+        disable_location_metrics(&[]);
+
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_latitude 52.52"), "{}", rendered);
+        assert!(rendered.contains("geoclue_client_latitude{client=\"street\"} 52.52"), "{}", rendered);
+        assert!(!rendered.contains("geoclue_altitude"), "{}", rendered);
+        assert!(!rendered.contains("geoclue_client_altitude"), "{}", rendered);
+    }
+
+    // Test every geoclue_* metric in the rendered output comes with # HELP and # TYPE lines
+    #[test]
+    fn test_rendered_metrics_are_documented() {
+        let args = Args::try_parse_from([
+            "geoclue-prometheus-exporter",
+            "--validate-output",
+            "--on-update-command", "true",
+            "--push-gateway", "http://127.0.0.1:9091",
+        ]).unwrap();
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            initialize_metrics(&args);
+            for (name, value) in [("latitude", 52.52), ("longitude", 13.405), ("accuracy", 20.0), ("altitude", 34.0), ("speed", 1.5), ("heading", 90.0)] {
+                assert!(set_location_gauge(name, value, value, &ValidationConfig::default()));
+            }
+            assert!(!set_location_gauge("heading", f64::NAN, f64::NAN, &ValidationConfig::default()));
+            metrics::histogram!("geoclue_accuracy_meters").record(20.0);
+        });
+
+        let rendered = render_metrics(&recorder.handle());
+        let documented = |directive: &str| -> std::collections::HashSet<String> {
+            rendered.lines()
+                .filter_map(|line| line.strip_prefix(directive))
+                .filter_map(|rest| rest.split_whitespace().next())
+                .map(str::to_string)
+                .collect()
+        };
+        let (help, types) = (documented("# HELP "), documented("# TYPE "));
+
+        let mut samples = 0;
+        for line in rendered.lines().filter(|line| line.starts_with("geoclue_")) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = ["_bucket", "_sum", "_count"].iter()
+                .filter_map(|suffix| name.strip_suffix(suffix))
+                .find(|family| types.contains(*family))
+                .unwrap_or(name);
+            assert!(help.contains(family), "no # HELP for {}", family);
+            assert!(types.contains(family), "no # TYPE for {}", family);
+            samples += 1;
+        }
+        assert!(samples > 20, "{}", rendered);
+    }
+
+    // Test --validate-output accepts rendered pages and counts a malformed injection
+    #[test]
+    fn test_validate_output() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            describe_metrics();
+            metrics::gauge!("geoclue_latitude").set(52.5);
+            metrics::counter!("geoclue_dbus_calls_total", "method" => "Start", "result" => "error \"quoted\"\n").increment(1);
+            metrics::histogram!("geoclue_accuracy_meters").record(12.0);
+        });
+        let rendered = recorder.handle().render();
+        assert_eq!(validate_exposition(&rendered), Ok(()));
+        assert_eq!(validate_exposition(FALLBACK_METRICS), Ok(()));
+
+        assert!(validate_exposition("geoclue latitude 1\n").is_err());
+        assert!(validate_exposition("geoclue_latitude{source=\"gps} 1\n").is_err());
+        assert!(validate_exposition("geoclue_latitude{1source=\"gps\"} 1\n").is_err());
+        assert!(validate_exposition("# TYPE geoclue_latitude meter\n").is_err());
+        assert_eq!(validate_exposition("geoclue_latitude{source=\"gps\",} NaN 1700000000\n"), Ok(()));
+
+        let malformed = format!("{}geoclue_latitude{{source=\"gps\"}} north\n", rendered);
+        let error = validate_exposition(&malformed).unwrap_err();
+        assert!(error.contains("invalid sample value"), "{}", error);
+
+        let counting = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&counting, || {
+            assert!(check_rendered_output(&rendered));
+            assert!(!check_rendered_output(&malformed));
+        });
+        assert!(counting.handle().render().contains("geoclue_invalid_output_total 1"));
+    }
+
+    // Test socket activation is detected per sd_listen_fds and inherited sockets are taken over
+    #[tokio::test]
+    async fn test_socket_activation() {
+        assert_eq!(listen_fds_count(Some("4242"), Some("1"), 4242), Some(1));
+        assert_eq!(listen_fds_count(Some("4242"), Some("2"), 4242), Some(2));
+        // Variables meant for another process, or malformed ones, are not ours
+        assert_eq!(listen_fds_count(Some("1"), Some("1"), 4242), None);
+        assert_eq!(listen_fds_count(None, Some("1"), 4242), None);
+        assert_eq!(listen_fds_count(Some("4242"), None, 4242), None);
+        assert_eq!(listen_fds_count(Some("4242"), Some("0"), 4242), None);
+        assert_eq!(listen_fds_count(Some("pid"), Some("1"), 4242), None);
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        match listener_from_fd(tcp.into()).unwrap() {
+            HttpListener::Tcp(listener) => assert_eq!(listener.local_addr().unwrap(), address),
+            HttpListener::Unix(_) => panic!("TCP socket taken over as a Unix socket"),
+        }
+
+        let path = std::env::temp_dir().join(format!("{}-activated-{}.sock", PKG_NAME, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(matches!(listener_from_fd(unix.into()).unwrap(), HttpListener::Unix(_)));
+        std::fs::remove_file(&path).unwrap();
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert!(listener_from_fd(file.into()).is_err());
+    }
+
+    // Test metrics are served over a Unix domain socket and a stale socket is replaced
+    #[tokio::test]
+    async fn test_serve_http_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        assert_eq!(unix_socket_path("unix:/run/exporter.sock").unwrap(), Some(PathBuf::from("/run/exporter.sock")));
+        assert_eq!(unix_socket_path("127.0.0.1").unwrap(), None);
+        assert!(unix_socket_path("unix:").is_err());
+
+        let path = std::env::temp_dir().join(format!("{}-http-{}.sock", PKG_NAME, std::process::id()));
+        drop(bind_unix_listener(&path).unwrap());
+        assert!(path.exists());
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || metrics::gauge!("geoclue_latitude").set(52.5));
+        tokio::spawn(serve_http(HttpListener::Unix(bind_unix_listener(&path).unwrap()), Arc::new(test_http_state(recorder.handle()))));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("geoclue_latitude 52.5"));
+
+        // Anything other than a socket at the path is left alone
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(bind_unix_listener(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Test the /health and /ready endpoints follow the connection and update state
+    #[tokio::test]
+    async fn test_health_and_ready_endpoints() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(test_http_state(recorder.handle()))));
+
+        let response = http_get(address, "/health").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with(&health_json(false)), "unexpected response: {}", response);
+
+        let response = http_get(address, "/ready").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "unexpected response: {}", response);
+
+        metrics::with_local_recorder(&recorder, || set_connection_up(true));
+        LOCATION_RECEIVED.store(true, Ordering::Relaxed);
+
+        let response = http_get(address, "/health").await;
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["geoclue_connected"], true);
+
+        let response = http_get(address, "/ready").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    // Test that each update appends one NDJSON line and the file rotates at its size cap
+    #[test]
+    fn test_events_file() {
+        let path = std::env::temp_dir().join(format!("{}-events-{}.ndjson", PKG_NAME, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut events_file = EventsFile::open(path.clone(), 0).unwrap();
+        let rotated_path = events_file.rotated_path();
+
+        events_file.append(&events_file_line(&[("latitude", 35.5), ("longitude", 139.7)], "street", 1_700_000_000)).unwrap();
+        events_file.append(&events_file_line(&[("latitude", 35.6), ("accuracy", 8.0)], "exact", 1_700_000_030)).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<serde_json::Value> = contents.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["latitude"], 35.5);
+        assert_eq!(events[0]["source"], "street");
+        assert!(events[0]["heading"].is_null());
+        assert_eq!(events[1]["timestamp"], 1_700_000_030);
+        assert_eq!(events[1]["accuracy"], 8.0);
+
+        // With a cap smaller than two lines, the next write rotates the file
+        events_file.max_bytes = contents.len() as u64;
+        events_file.append(&events_file_line(&[], "street", 1_700_000_060)).unwrap();
+        assert_eq!(std::fs::read_to_string(&rotated_path).unwrap(), contents);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated_path).unwrap();
+    }
+
+    // Test that metrics are served on a custom path and other paths return 404
+    #[tokio::test]
+    async fn test_custom_metrics_path() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            metrics::gauge!("geoclue_latitude").set(35.5);
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            metrics_path: "/geoclue/metrics".to_string(),
+            ..test_http_state(handle)
+        })));
+
+        let response = http_get(address, "/geoclue/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("geoclue_latitude 35.5"));
+
+        let response = http_get(address, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+
+        // SSE is disabled, so /events is not routed either
+        let response = http_get(address, "/events").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+
+        assert!(parse_metrics_path("metrics").is_err());
+    }
+
+    // Test that the heading is suppressed below the speed threshold only when enabled
+    #[test]
+    fn test_heading_is_meaningful() {
+        assert!(!heading_is_meaningful(true, 0.5, 0.2));
+        assert!(heading_is_meaningful(true, 0.5, 0.5));
+        assert!(heading_is_meaningful(true, 0.5, 12.0));
+
+        // Unknown speed and a disabled option never suppress
+        assert!(heading_is_meaningful(true, 0.5, -1.0));
+        assert!(heading_is_meaningful(false, 0.5, 0.2));
+    }
+
+    // Test the WGS84 to ECEF conversion against known reference points
+    #[test]
+    fn test_wgs84_to_ecef() {
+        let close = |actual: (f64, f64, f64), expected: (f64, f64, f64)| {
+            (actual.0 - expected.0).abs() < 1e-3
+                && (actual.1 - expected.1).abs() < 1e-3
+                && (actual.2 - expected.2).abs() < 1e-3
+        };
+
+        // Equator/prime meridian, equator/90E and the north pole (semi-minor axis)
+        assert!(close(wgs84_to_ecef(0.0, 0.0, 0.0), (6_378_137.0, 0.0, 0.0)));
+        assert!(close(wgs84_to_ecef(0.0, 90.0, 0.0), (0.0, 6_378_137.0, 0.0)));
+        assert!(close(wgs84_to_ecef(90.0, 0.0, 0.0), (0.0, 0.0, 6_356_752.314_245)));
+
+        // Height is added along the ellipsoid normal
+        assert!(close(wgs84_to_ecef(0.0, 0.0, 100.0), (6_378_237.0, 0.0, 0.0)));
+
+        // Greenwich observatory (51.4778N, 0.0015W, 45.8m)
+        let (x, y, z) = wgs84_to_ecef(51.4778, -0.0015, 45.8);
+        assert!((x - 3_980_608.0).abs() < 50.0, "x = {}", x);
+        assert!((y - -104.2).abs() < 5.0, "y = {}", y);
+        assert!((z - 4_966_861.0).abs() < 50.0, "z = {}", z);
+    }
+
+    // Test that only the latest Location object path of a client is rendered
+    #[test]
+    fn test_set_location_info() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let client = "/org/freedesktop/GeoClue2/Client/1";
+
+        set_location_info(client, "/org/freedesktop/GeoClue2/Location/1");
+        set_location_info(client, "/org/freedesktop/GeoClue2/Location/2");
+
+        let rendered = render_metrics(&recorder.handle());
+        let series = |location: &str| format!(
+            "geoclue_location_info{{location_path=\"{}\",client_path=\"{}\"}} 1", location, client
+        );
+        assert!(!rendered.contains("/org/freedesktop/GeoClue2/Location/1\""), "{}", rendered);
+        assert!(rendered.contains(&series("/org/freedesktop/GeoClue2/Location/2")), "{}", rendered);
+        assert!(rendered.contains("# TYPE geoclue_location_info gauge"), "{}", rendered);
+        assert_eq!(validate_exposition(&rendered), Ok(()));
+    }
+
+    // Test that DBus call outcomes are counted and results pass through unchanged
+    #[tokio::test]
+    async fn test_dbus_call() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let ok: zbus::Result<u32> = dbus_call("GetClient", async { Ok(7) }).await;
+        assert_eq!(ok.unwrap(), 7);
+
+        let failed: zbus::Result<()> = dbus_call(format!("Get.{}", "Latitude"), async {
+            Err(zbus::Error::Failure("gone".to_string()))
+        }).await;
+        assert!(matches!(failed, Err(zbus::Error::Failure(message)) if message == "gone"));
+
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_dbus_calls_total{method=\"GetClient\",result=\"success\"} 1"), "{}", rendered);
+        assert!(rendered.contains("geoclue_dbus_calls_total{method=\"Get.Latitude\",result=\"error\"} 1"), "{}", rendered);
+    }
+
+    // Test the --trace-dbus log lines for a successful and a failed mocked call
+    #[tokio::test]
+    async fn test_dbus_trace_fields() {
+        let timestamp = "2024-03-01 07:15:00.000";
+        let fields = dbus_trace_fields("Set.DistanceThreshold", "50", Ok("()".to_string()), std::time::Duration::from_micros(420));
+        assert_eq!(
+            format_log_line(LogFormat::Text, timestamp, "TRACE", "DBus call", &fields),
+            "timestamp=\"2024-03-01 07:15:00.000\" level=TRACE message=\"DBus call\" dbus=true method=Set.DistanceThreshold args=50 result=success value=() elapsed_us=420"
+        );
+
+        let fields = dbus_trace_fields("Get.Latitude", "", Err("gone".to_string()), std::time::Duration::from_micros(7));
+        let json: serde_json::Value = serde_json::from_str(&format_log_line(LogFormat::Json, timestamp, "TRACE", "DBus call", &fields)).unwrap();
+        assert_eq!(json["dbus"], "true");
+        assert_eq!(json["method"], "Get.Latitude");
+        assert_eq!(json["result"], "error");
+        assert_eq!(json["error"], "gone");
+        assert_eq!(json["elapsed_us"], "7");
+
+        // Tracing leaves the call's result unchanged
+        TRACE_DBUS.store(true, Ordering::Relaxed);
+        let result: zbus::Result<u32> = dbus_call_with_args("GetClient", String::new(), async { Ok(7) }).await;
+        TRACE_DBUS.store(false, Ordering::Relaxed);
+        assert_eq!(result.unwrap(), 7);
+    }
+
+    // Test signals are processed while the client runs and ignored and counted after Stop
+    #[test]
+    fn test_late_signal() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let client_stopped = AtomicBool::new(false);
+
+        metrics::with_local_recorder(&recorder, || {
+            assert!(!is_late_signal(&client_stopped));
+            client_stopped.store(true, Ordering::Relaxed);
+            assert!(is_late_signal(&client_stopped));
+            assert!(is_late_signal(&client_stopped));
+        });
+        assert!(handle.render().contains("geoclue_late_signals_ignored_total 2"), "{}", handle.render());
+    }
+
+    // Test a restart drops the signals queued up to the Stop reply and keeps those sent after Start
+    #[test]
+    fn test_restart_drops_late_signals() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let client_stopped = AtomicBool::new(false);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut signals = futures_util::stream::poll_fn(|cx| receiver.poll_recv(cx));
+
+        sender.send("old level, before Stop").unwrap();
+        let stop = async {
+            sender.send("old level, before the Stop reply").unwrap();
+            Ok(())
+        };
+        let start = async {
+            sender.send("new level").unwrap();
+            Ok(())
+        };
+        metrics::with_local_recorder(&recorder, || {
+            restart_dropping_late_signals(&client_stopped, &mut signals, stop, start)
+                .now_or_never().unwrap().unwrap();
+        });
+
+        assert!(!client_stopped.load(Ordering::Relaxed));
+        assert_eq!(signals.next().now_or_never(), Some(Some("new level")));
+        assert!(handle.render().contains("geoclue_late_signals_ignored_total 2"), "{}", handle.render());
+    }
+
+    // Test publishing the requested and available accuracy levels, and the missing-property sentinel
+    #[test]
+    fn test_record_available_accuracy_level() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || record_requested_accuracy_level(AccuracyLevelArg::Street.into()));
+        assert!(recorder.handle().render().contains("geoclue_requested_accuracy_level 6"));
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || record_available_accuracy_level(Ok(4), AccuracyLevel::Exact));
+        assert!(handle.render().contains("geoclue_available_accuracy_level 4"), "{}", handle.render());
+
+        let missing = Err(zbus::Error::Failure("No such property".to_string()));
+        metrics::with_local_recorder(&recorder, || record_available_accuracy_level(missing, AccuracyLevel::Exact));
+        assert!(handle.render().contains("geoclue_available_accuracy_level -1"), "{}", handle.render());
+    }
+
+    // Test a PropertiesChanged signal for AvailableAccuracyLevel updates the gauges live
+    #[test]
+    fn test_available_accuracy_change() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let levels = Mutex::new(AccuracyLevels { requested: AccuracyLevel::Exact, available: Some(8) });
+
+        let mut changed = std::collections::HashMap::new();
+        changed.insert("InUse", zvariant::Value::from(true));
+        assert_eq!(changed_available_accuracy_level(&changed), None);
+        changed.insert("AvailableAccuracyLevel", zvariant::Value::from(4u32));
+
+        metrics::with_local_recorder(&recorder, || {
+            let available = changed_available_accuracy_level(&changed).unwrap();
+            handle_available_accuracy_change(&levels, available);
+            // A repeated signal with the same value is not a change
+            handle_available_accuracy_change(&levels, available);
+        });
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_available_accuracy_changes_total 1"), "{}", rendered);
+        assert!(rendered.contains("geoclue_available_accuracy_level 4"));
+        assert!(rendered.contains("geoclue_effective_accuracy_level 4"));
+        assert_eq!(levels.lock().unwrap().available, Some(4));
+
+        // Location services turned back on
+        metrics::with_local_recorder(&recorder, || handle_available_accuracy_change(&levels, 8));
+        assert!(handle.render().contains("geoclue_available_accuracy_changes_total 2"));
+        assert!(handle.render().contains("geoclue_effective_accuracy_level 8"));
+    }
+
+    // Test the effective accuracy level follows fallback and is capped by the available level
+    #[test]
+    fn test_effective_accuracy_level() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let fallback = [AccuracyLevelArg::Exact, AccuracyLevelArg::Street, AccuracyLevelArg::City];
+
+        metrics::with_local_recorder(&recorder, || {
+            let available = record_available_accuracy_level(Ok(6), AccuracyLevel::Exact);
+            set_accuracy_level_gauges(AccuracyLevel::Exact, available);
+        });
+        assert!(handle.render().contains("geoclue_active_accuracy_level 8"));
+        assert!(handle.render().contains("geoclue_effective_accuracy_level 6"));
+
+        // Simulate two fallback steps without an update
+        let mut active = AccuracyLevelArg::Exact;
+        while let Some(next) = next_fallback_level(&fallback, active) {
+            active = next;
+            metrics::with_local_recorder(&recorder, || set_accuracy_level_gauges(active.into(), Some(6)));
+        }
+        assert!(handle.render().contains("geoclue_active_accuracy_level 4"));
+        assert!(handle.render().contains("geoclue_effective_accuracy_level 4"));
+
+        // Without an available level the requested level is taken as is
+        metrics::with_local_recorder(&recorder, || set_accuracy_level_gauges(AccuracyLevel::Street, None));
+        assert!(handle.render().contains("geoclue_effective_accuracy_level 6"));
+    }
+
+    // Test the applied thresholds read back from GeoClue2 are published as reported
+    #[test]
+    fn test_record_applied_threshold() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            record_applied_threshold("geoclue_distance_threshold_meters", "DistanceThreshold", 10, Ok(10));
+            // Clamped by the daemon: the gauge shows what is in effect
+            record_applied_threshold("geoclue_time_threshold_seconds", "TimeThreshold", 5, Ok(30));
+        });
+        let rendered = handle.render();
+        assert!(rendered.contains("geoclue_distance_threshold_meters 10"), "{}", rendered);
+        assert!(rendered.contains("geoclue_time_threshold_seconds 30"), "{}", rendered);
+
+        // A failed readback leaves the last published value alone
+        metrics::with_local_recorder(&recorder, || {
+            record_applied_threshold("geoclue_time_threshold_seconds", "TimeThreshold", 5, Err(anyhow::anyhow!("gone")));
+        });
+        assert!(handle.render().contains("geoclue_time_threshold_seconds 30"));
+    }
+
+    // Test stepping down the accuracy fallback list
+    #[test]
+    fn test_next_fallback_level() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--accuracy-fallback", "exact,street,city"]).unwrap();
+        assert_eq!(args.accuracy_fallback, vec![AccuracyLevelArg::Exact, AccuracyLevelArg::Street, AccuracyLevelArg::City]);
+
+        let fallback = &args.accuracy_fallback;
+        assert_eq!(next_fallback_level(fallback, AccuracyLevelArg::Exact), Some(AccuracyLevelArg::Street));
+        assert_eq!(next_fallback_level(fallback, AccuracyLevelArg::Street), Some(AccuracyLevelArg::City));
+        assert_eq!(next_fallback_level(fallback, AccuracyLevelArg::City), None);
+
+        // A level outside the list starts at its first entry
+        assert_eq!(next_fallback_level(fallback, AccuracyLevelArg::Neighborhood), Some(AccuracyLevelArg::Exact));
+        assert_eq!(next_fallback_level(&[], AccuracyLevelArg::Street), None);
+
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--accuracy-fallback", "exact,precise"]).is_err());
+    }
+
+    // Test that only operational metrics move to the self-metrics prefix
+    #[test]
+    fn test_self_metric_name() {
+        let prefix = Some("exporter_");
+        assert_eq!(self_metric_name("geoclue_reconnects_total", prefix), "exporter_reconnects_total");
+        assert_eq!(self_metric_name("geoclue_render_failures_total", prefix), "exporter_render_failures_total");
+        assert_eq!(self_metric_name("geoclue_connection_up", prefix), "exporter_connection_up");
+        assert_eq!(self_metric_name("geoclue_latitude", prefix), "geoclue_latitude");
+        assert_eq!(self_metric_name("geoclue_accuracy_meters", prefix), "geoclue_accuracy_meters");
+
+        // Without a self-metrics prefix every metric keeps its registered name
+        assert_eq!(self_metric_name("geoclue_reconnects_total", None), "geoclue_reconnects_total");
+
+        for name in SELF_METRICS {
+            assert!(metric_description(name).is_some(), "unregistered metric {}", name);
+        }
+    }
+
+    // Test --list-metrics prints every registered metric with its type and description
+    #[test]
+    fn test_metric_listing() {
+        let listing = metric_listing();
+        assert!(listing.lines().any(|line| line == "geoclue_latitude\tgauge\tLatitude in degrees"));
+        assert!(listing.contains("geoclue_reconnects_total\tcounter\t"));
+        assert!(listing.contains("geoclue_accuracy_meters\thistogram\t"));
+        assert_eq!(listing.lines().count(), METRIC_DESCRIPTIONS.len());
+    }
+
+    // Test that the generated alerting rules are valid YAML built on registered metrics
+    #[test]
+    fn test_alert_rules_yaml() {
+        let rules: serde_yaml::Value = serde_yaml::from_str(&alert_rules_yaml()).unwrap();
+        let alerts: Vec<&str> = rules["groups"][0]["rules"].as_sequence().unwrap().iter()
+            .map(|rule| rule["alert"].as_str().unwrap())
+            .collect();
+        assert_eq!(alerts, [
+            "GeoClueExporterDown",
+            "GeoClueDisconnected",
+            "GeoClueLocationStale",
+            "GeoClueHighReconnectRate",
+            "GeoClueLowAccuracy",
+        ]);
+        assert_eq!(rules["groups"][0]["rules"][0]["expr"], "up{job=\"geoclue\"} == 0");
+
+        // Every rule must reference a registered metric, or it would silently be dropped
+        for rule in ALERT_RULES {
+            assert!(metric_description(rule.metric).is_some(), "unregistered metric {}", rule.metric);
+            assert!(rule.expr.contains(rule.metric));
+        }
+    }
+
+    // Test the client info-metric and its label sanitizing
+    #[test]
+    fn test_set_client_info() {
+        assert_eq!(sanitize_label_value("/org/freedesktop/GeoClue2/Client/1"), "/org/freedesktop/GeoClue2/Client/1");
+        assert_eq!(sanitize_label_value("bad\npath\t"), "bad_path_");
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            set_client_info("/org/freedesktop/GeoClue2/Client/3", PKG_NAME);
+        });
+
+        let expected = format!(
+            "geoclue_client_info{{client_path=\"/org/freedesktop/GeoClue2/Client/3\",desktop_id=\"{}\"}} 1",
+            PKG_NAME
+        );
+        assert!(handle.render().contains(&expected), "{}", handle.render());
+    }
+
+    // Test hostname bind addresses resolve and failures are reported distinctly
+    #[tokio::test]
+    async fn test_resolve_bind_address() {
+        assert!(is_hostname("localhost"));
+        assert!(is_hostname("metrics.example.org."));
+        assert!(!is_hostname("not-an-address%"));
+        assert!(!is_hostname("-bad.example"));
+        assert!(!is_hostname("::1"));
+
+        let parse = |address: &str| Args::try_parse_from(["geoclue-prometheus-exporter", "--bind-address", address]).unwrap();
+
+        let socket_addr = resolve_bind_address(&parse("127.0.0.1")).await.unwrap();
+        assert_eq!(socket_addr, "127.0.0.1:9090".parse().unwrap());
+
+        let socket_addr = resolve_bind_address(&parse("localhost")).await.unwrap();
+        assert!(socket_addr.ip().is_loopback());
+        assert_eq!(socket_addr.port(), 9090);
+
+        let error = resolve_bind_address(&parse("no-such-host.invalid")).await.unwrap_err();
+        assert!(error.to_string().starts_with("Failed to resolve bind address no-such-host.invalid"), "{}", error);
+
+        let error = resolve_bind_address(&parse("not-an-address%")).await.unwrap_err();
+        assert!(error.to_string().starts_with("Failed to parse bind address"), "{}", error);
+    }
+
+    // Test IPv6 bind addresses with and without brackets and zone identifiers
+    #[test]
+    fn test_parse_socket_addr() {
+        let loopback: SocketAddr = "[::1]:9090".parse().unwrap();
+        assert_eq!(parse_socket_addr("::1", 9090).unwrap(), loopback);
+        assert_eq!(parse_socket_addr("[::1]", 9090).unwrap(), loopback);
+        assert_eq!(parse_socket_addr("0.0.0.0", 9090).unwrap(), "0.0.0.0:9090".parse().unwrap());
+
+        let SocketAddr::V6(link_local) = parse_socket_addr("fe80::1%3", 9090).unwrap() else {
+            panic!("expected an IPv6 address");
+        };
+        assert_eq!(link_local.ip(), &"fe80::1".parse::<std::net::Ipv6Addr>().unwrap());
+        assert_eq!(link_local.scope_id(), 3);
+
+        // Interface names are resolved to their index; lo exists on every Linux host
+        let SocketAddr::V6(link_local) = parse_socket_addr("[fe80::1%lo]", 9090).unwrap() else {
+            panic!("expected an IPv6 address");
+        };
+        assert_ne!(link_local.scope_id(), 0);
+
+        let eth0 = parse_socket_addr("fe80::1%eth0", 9090);
+        if std::path::Path::new("/sys/class/net/eth0").exists() {
+            assert!(eth0.is_ok());
+        } else {
+            assert!(eth0.unwrap_err().to_string().contains("unknown network interface eth0"));
+        }
+
+        assert!(parse_socket_addr("::1::2", 9090).is_err());
+        assert!(parse_socket_addr("[::1", 9090).is_err());
+    }
+
+    // Test that a property read failing once is recorded after the retry
+    #[tokio::test]
+    async fn test_read_with_retries() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let delay = tokio::time::Duration::from_millis(1);
+
+        let attempts = std::cell::Cell::new(0);
+        let flaky_read = || {
+            attempts.set(attempts.get() + 1);
+            let result = if attempts.get() == 1 {
+                Err(zbus::Error::Failure("temporarily unavailable".to_string()))
+            } else {
+                Ok(35.5)
+            };
+            std::future::ready(result)
+        };
+
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        assert_eq!(read_with_retries("Latitude", 1, delay, flaky_read).await.unwrap(), 35.5);
+        assert_eq!(attempts.get(), 2);
+        assert!(handle.render().contains("geoclue_property_read_retries_total{property=\"Latitude\"} 1"), "{}", handle.render());
+
+        // Without retries the first failure is final
+        attempts.set(0);
+        assert!(read_with_retries("Latitude", 0, delay, flaky_read).await.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    // Test the meters-to-degrees conversion and snapping positions to grid cells
+    #[test]
+    fn test_snap_to_grid() {
+        // 1km is ~0.009 degrees of latitude everywhere, and twice that in longitude at 60N
+        let (lat_step, lon_step) = meters_to_degrees(1000.0, 0.0);
+        assert!((lat_step - 0.008993).abs() < 1e-6, "{}", lat_step);
+        assert!((lon_step - lat_step).abs() < 1e-12);
+        let (lat_step, lon_step) = meters_to_degrees(1000.0, 60.0);
+        assert!((lon_step - 2.0 * lat_step).abs() < 1e-9);
+        assert_eq!(meters_to_degrees(1000.0, 90.0).1, 360.0);
+
+        // Berlin on a 1km grid: the centre of cell (5839, 907) in each direction
+        let (lat, lon) = snap_to_grid(52.520008, 13.404954, 1000.0);
+        let (lat_step, _) = meters_to_degrees(1000.0, 52.520008);
+        assert!((lat - 5839.5 * lat_step).abs() < 1e-9, "{}", lat);
+        let (_, lon_step) = meters_to_degrees(1000.0, lat);
+        assert!((lon - 907.5 * lon_step).abs() < 1e-9, "{}", lon);
+        assert!(haversine(52.520008, 13.404954, lat, lon) < 1000.0);
+
+        // Nearby positions in the same cell export the same coordinates
+        assert_eq!(snap_to_grid(52.5201, 13.4050, 1000.0), (lat, lon));
+        assert_ne!(snap_to_grid(52.5301, 13.4050, 1000.0), (lat, lon));
+
+        // Every output of a position gets the snapped coordinates, not just the primary gauges
+        let coarsening = CoordinateCoarsening { precision: None, grid_meters: Some(1000.0) };
+        assert_eq!(coarsening.apply(52.520008, 13.404954), (lat, lon));
+        assert_eq!(coarsening.apply(-1.0, 13.404954), (-1.0, 13.404954));
+        let reading = LocationReading {
+            latitude: 52.520008,
+            longitude: 13.404954,
+            accuracy: 25.0,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites: None,
+        };
+        let json: serde_json::Value = serde_json::from_str(&once_location_json(&reading, &coarsening, 1700000000)).unwrap();
+        assert!((json["latitude"].as_f64().unwrap() - lat).abs() < 1e-9, "{}", json);
+        assert!((json["longitude"].as_f64().unwrap() - lon).abs() < 1e-9, "{}", json);
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || set_client_gauges("street", &reading, &coarsening, &ValidationConfig::default()));
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains(&format!("geoclue_client_latitude{{client=\"street\"}} {}", lat)), "{}", rendered);
+        assert!(rendered.contains(&format!("geoclue_client_longitude{{client=\"street\"}} {}", lon)), "{}", rendered);
+
+        assert!(parse_grid_meters("250").is_ok());
+        assert!(parse_grid_meters("0").is_err());
+        assert!(parse_grid_meters("-5").is_err());
+    }
+
+    // Test the --once output, with unknown properties as null
+    #[test]
+    fn test_once_location_json() {
+        let reading = LocationReading {
+            latitude: 52.52,
+            longitude: 13.405,
+            accuracy: 25.0,
+            altitude: -1.0,
+            speed: 0.0,
+            heading: -1.0,
+            satellites: None,
+        };
+        let json: serde_json::Value = serde_json::from_str(&once_location_json(&reading, &CoordinateCoarsening::default(), 1700000000)).unwrap();
+        assert_eq!(json["latitude"], 52.52);
+        assert_eq!(json["longitude"], 13.405);
+        assert_eq!(json["accuracy"], 25.0);
+        assert_eq!(json["speed"], 0.0);
+        assert!(json["altitude"].is_null());
+        assert!(json["heading"].is_null());
+        assert_eq!(json["timestamp"], 1700000000);
+    }
+
+    // Test coordinate rounding for --coordinate-precision
+    #[test]
+    fn test_round_coordinate() {
+        assert_eq!(round_coordinate(52.520008, None), 52.520008);
+        assert_eq!(round_coordinate(52.520008, Some(0)), 53.0);
+        assert_eq!(round_coordinate(-13.404954, Some(0)), -13.0);
+        assert_eq!(round_coordinate(52.520008, Some(2)), 52.52);
+        assert_eq!(round_coordinate(-13.404954, Some(2)), -13.4);
+        assert_eq!(round_coordinate(52.520008, Some(5)), 52.52001);
+        assert_eq!(round_coordinate(-13.404954, Some(5)), -13.40495);
+
+        // The unknown sentinel is unchanged at any precision
+        assert_eq!(round_coordinate(-1.0, Some(0)), -1.0);
+        assert_eq!(round_coordinate(-1.0, Some(5)), -1.0);
+    }
+
+    // Test a real coordinate that rounds onto the -1 sentinel is still exported
+    #[test]
+    fn test_coordinate_rounded_to_sentinel() {
+        let rounded = round_coordinate(-0.7, Some(0));
+        assert_eq!(rounded, -1.0);
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            assert!(set_location_gauge("latitude", -0.7, rounded, &ValidationConfig::default()));
+            assert!(!set_location_gauge("longitude", -1.0, -1.0, &ValidationConfig::default()));
+            set_client_gauges("street", &LocationReading {
+                latitude: -0.7,
+                longitude: -1.0,
+                accuracy: 20.0,
+                altitude: -1.0,
+                speed: -1.0,
+                heading: -1.0,
+                satellites: None,
+            }, &CoordinateCoarsening { precision: Some(0), grid_meters: None }, &ValidationConfig::default());
+        });
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_latitude -1"), "{}", rendered);
+        assert!(!rendered.contains("geoclue_longitude "), "{}", rendered);
+        assert!(rendered.contains("geoclue_client_latitude{client=\"street\"} -1"), "{}", rendered);
+        assert!(!rendered.contains("geoclue_client_longitude"), "{}", rendered);
+
+        let reading = LocationReading {
+            latitude: -0.7,
+            longitude: -1.0,
+            accuracy: 20.0,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites: None,
+        };
+        let json: serde_json::Value = serde_json::from_str(&once_location_json(&reading, &CoordinateCoarsening { precision: Some(0), grid_meters: None }, 1700000000)).unwrap();
+        assert_eq!(json["latitude"], -1.0);
+        assert!(json["longitude"].is_null());
+    }
+
+    // Test haversine distances between known city pairs
+    #[test]
+    fn test_haversine_city_pairs() {
+        // London - Paris, ~343.6km
+        assert!((haversine(51.5074, -0.1278, 48.8566, 2.3522) - 343_557.0).abs() < 100.0);
+        // New York - Los Angeles, ~3936km
+        assert!((haversine(40.7128, -74.0060, 34.0522, -118.2437) - 3_935_752.0).abs() < 100.0);
+        // Sydney - Melbourne, ~713km, and the distance is symmetric
+        let sydney_melbourne = haversine(-33.8688, 151.2093, -37.8136, 144.9631);
+        assert!((sydney_melbourne - 713_428.0).abs() < 100.0);
+        assert!((haversine(-37.8136, 144.9631, -33.8688, 151.2093) - sydney_melbourne).abs() < 1e-6);
+    }
+
+    // Test the accuracy-weighted average, including unusable weights and the antimeridian
+    #[test]
+    fn test_weighted_position() {
+        assert_eq!(weighted_position(&[]), None);
+        assert_eq!(weighted_position(&[(52.0, 13.0, 20.0)]), Some((52.0, 13.0)));
+
+        // A 10m fix weighs 100 times as much as a 100m fix
+        let (lat, lon) = weighted_position(&[(0.0, 0.0, 10.0), (1.01, 1.01, 100.0)]).unwrap();
+        assert!((lat - 0.01).abs() < 1e-9);
+        assert!((lon - 0.01).abs() < 1e-9);
+
+        // Fixes with zero or unknown accuracy are left out while others have a weight
+        assert_eq!(weighted_position(&[(10.0, 20.0, 0.0), (30.0, 40.0, 5.0), (50.0, 60.0, -1.0)]), Some((30.0, 40.0)));
+
+        // Without any usable weight every fix counts the same
+        assert_eq!(weighted_position(&[(10.0, 20.0, 0.0), (30.0, 40.0, -1.0)]), Some((20.0, 30.0)));
+
+        // Averaging across the antimeridian stays near +-180
+        let (_, lon) = weighted_position(&[(0.0, 179.0, 10.0), (0.0, -179.0, 10.0)]).unwrap();
+        assert!((lon.abs() - 180.0).abs() < 1e-9);
+        let (_, lon) = weighted_position(&[(0.0, 179.5, 10.0), (0.0, -179.5, 10.0), (0.0, -179.5, 10.0)]).unwrap();
+        assert!((lon - -179.8333333333).abs() < 1e-6);
+    }
+
+    // Test the smoothing window keeps only the most recent fixes
+    #[test]
+    fn test_record_smoothing_sample() {
+        let mut tracker = UpdateTracker::new();
+        let mut smoothed = |lat, lon| {
+            let (smoothed_lat, smoothed_lon) = tracker.record_smoothing_sample(lat, lon, 10.0, 2).unwrap();
+            ((smoothed_lat * 1e6).round() / 1e6, (smoothed_lon * 1e6).round() / 1e6)
+        };
+        assert_eq!(smoothed(10.0, 10.0), (10.0, 10.0));
+        assert_eq!(smoothed(20.0, 20.0), (15.0, 15.0));
+        assert_eq!(smoothed(40.0, 40.0), (30.0, 30.0));
+        assert_eq!(tracker.recent_positions.len(), 2);
+    }
+
+    // Test log-scaled accuracy buckets double monotonically across the configured range
+    #[test]
+    fn test_log_buckets() {
+        let buckets = log_buckets(1.0, 50_000.0);
+        assert_eq!(buckets.first(), Some(&1.0));
+        assert_eq!(buckets.last(), Some(&65_536.0));
+        assert!(buckets.windows(2).all(|pair| pair[1] == pair[0] * 2.0));
+        assert_eq!(buckets.len(), 17);
+
+        // A range that is already a power of two ends exactly on max
+        assert_eq!(log_buckets(2.0, 16.0), vec![2.0, 4.0, 8.0, 16.0]);
+        assert_eq!(log_buckets(5.0, 5.0), vec![5.0]);
+
+        assert_eq!(accuracy_buckets(BucketScale::Linear), ACCURACY_BUCKETS);
+        assert_eq!(accuracy_buckets(BucketScale::Log), buckets);
+    }
+
+    // Test system bus connection retries until success, and gives up once the timeout has passed
+    #[tokio::test]
+    async fn test_connect_with_retry() {
+        let delay = std::time::Duration::from_millis(10);
+        let timeout = std::time::Duration::from_secs(5);
+
+        // Fails twice, then the first successful connection is kept
+        let mut attempts = 0;
+        let result: Result<u32, String> = connect_with_retry(timeout, delay, || {
+            attempts += 1;
+            let outcome = if attempts < 3 { Err(format!("not ready {}", attempts)) } else { Ok(attempts) };
+            async move { outcome }
+        }).await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(attempts, 3);
+
+        // A zero timeout tries exactly once
+        let mut attempts = 0;
+        let result: Result<(), String> = connect_with_retry(std::time::Duration::ZERO, delay, || {
+            attempts += 1;
+            async { Err("no bus".to_string()) }
+        }).await;
+        assert_eq!(result, Err("no bus".to_string()));
+        assert_eq!(attempts, 1);
+
+        // Otherwise it keeps trying until the timeout and returns the last error
+        let started = std::time::Instant::now();
+        let mut attempts = 0;
+        let result: Result<(), String> = connect_with_retry(std::time::Duration::from_millis(100), delay, || {
+            attempts += 1;
+            let error = format!("no bus {}", attempts);
+            async move { Err(error) }
+        }).await;
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100));
+        assert!(attempts > 1);
+        assert_eq!(result, Err(format!("no bus {}", attempts)));
+    }
+
+    // Test the reconnect backoff grows until the cap and jitter stays within it
+    #[test]
+    fn test_jittered_backoff() {
+        use rand::SeedableRng;
+
+        let caps: Vec<u64> = (1..=8).map(|retry_count| backoff_cap(retry_count, 60)).collect();
+        assert_eq!(caps, vec![2, 4, 8, 16, 32, 60, 60, 60]);
+        assert_eq!(backoff_cap(200, 60), 60);
+        assert_eq!(backoff_cap(3, 5), 5);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let delays: Vec<std::time::Duration> = (1..=200)
+            .map(|retry_count| jittered_backoff(retry_count, 60, &mut rng))
+            .collect();
+        for (retry_count, delay) in (1..).zip(&delays) {
+            assert!(*delay <= std::time::Duration::from_secs(backoff_cap(retry_count, 60)), "retry {}: {:?}", retry_count, delay);
+        }
+        // The delays are spread out rather than all at the cap
+        let capped = &delays[10..];
+        assert!(capped.iter().any(|delay| *delay < std::time::Duration::from_secs(30)));
+        assert!(capped.iter().any(|delay| *delay > std::time::Duration::from_secs(30)));
+
+        // The same seed gives the same sequence
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        assert_eq!(jittered_backoff(1, 60, &mut rng), delays[0]);
+    }
+
+    // Test signals and applied updates are counted separately, with the deprecated alias following signals
+    #[test]
+    fn test_signal_and_applied_counts() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let mut tracker = UpdateTracker::new();
+        metrics::with_local_recorder(&recorder, || {
+            for applied in [true, false, true] {
+                tracker.record_signal_received();
+                if applied {
+                    tracker.record_update_applied();
+                }
+            }
+        });
+        assert_eq!((tracker.received_updates, tracker.applied_updates), (3, 2));
+
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_signals_received_total 3"), "{}", rendered);
+        assert!(rendered.contains("geoclue_updates_applied_total 2"), "{}", rendered);
+        assert!(rendered.contains("geoclue_location_updates_received 3"), "{}", rendered);
+    }
+
+    // Test speed conversion and the unit-specific gauge names
+    #[test]
+    fn test_speed_unit() {
+        assert_eq!(SpeedUnit::Mps.convert(12.5), 12.5);
+        assert!((SpeedUnit::Kmh.convert(10.0) - 36.0).abs() < 1e-9);
+        assert!((SpeedUnit::Mph.convert(METERS_PER_MILE / 3600.0) - 1.0).abs() < 1e-9);
+        assert!((SpeedUnit::Mph.convert(10.0) - 22.369362920544).abs() < 1e-9);
+        assert_eq!(SpeedUnit::Kmh.convert(0.0), 0.0);
+
+        assert_eq!(SpeedUnit::Mps.metric_name(), "geoclue_speed");
+        assert_eq!(SpeedUnit::Kmh.metric_name(), "geoclue_speed_kmh");
+        assert_eq!(SpeedUnit::Mph.client_metric_name(), "geoclue_client_speed_mph");
+        for unit in [SpeedUnit::Mps, SpeedUnit::Kmh, SpeedUnit::Mph] {
+            assert!(metric_description(unit.metric_name()).is_some());
+            assert!(metric_description(unit.client_metric_name()).is_some());
+        }
+
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter"]).unwrap();
+        assert_eq!(args.speed_unit, SpeedUnit::Mps);
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--speed-unit", "knots"]).is_err());
+    }
+
+    // Test only an accepted coordinate makes an update applied
+    #[test]
+    fn test_has_accepted_coordinate() {
+        assert!(has_accepted_coordinate(&[("latitude", 52.52), ("accuracy", 20.0)]));
+        assert!(has_accepted_coordinate(&[("longitude", 13.405)]));
+        assert!(!has_accepted_coordinate(&[("accuracy", 20.0), ("altitude", 34.0), ("speed", 1.5)]));
+        assert!(!has_accepted_coordinate(&[]));
+    }
+
+    // Test a bounded capture completes after N applied updates, not counting rejected ones
+    #[test]
+    fn test_capture_complete() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let mut tracker = UpdateTracker::new();
+        let updates = [true, false, true, false, false, true, true];
+        let completed_at = metrics::with_local_recorder(&recorder, || {
+            updates.iter().position(|valid| {
+                tracker.record_signal_received();
+                if *valid {
+                    tracker.record_update_applied();
+                }
+                tracker.capture_complete(Some(3))
+            })
+        });
+        assert_eq!(completed_at, Some(5));
+        assert_eq!((tracker.received_updates, tracker.applied_updates), (6, 3));
+
+        // Without --max-updates the run never completes
+        assert!(!tracker.capture_complete(None));
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--max-updates", "0"]).is_err());
+    }
+
+    // Test --max-consecutive-invalid triggers a reconnect after N fully rejected updates in a row
+    #[test]
+    fn test_max_consecutive_invalid() {
+        let mut tracker = UpdateTracker::new();
+        assert!(!tracker.record_update_validity(false, Some(3)));
+        assert!(!tracker.record_update_validity(false, Some(3)));
+        // A valid update resets the run
+        assert!(!tracker.record_update_validity(true, Some(3)));
+        assert!(!tracker.record_update_validity(false, Some(3)));
+        assert!(!tracker.record_update_validity(false, Some(3)));
+        assert!(tracker.record_update_validity(false, Some(3)));
+        // The run starts over after triggering
+        assert!(!tracker.record_update_validity(false, Some(3)));
+
+        // Without the option invalid updates never trigger
+        let mut tracker = UpdateTracker::new();
+        assert!((0..100).all(|_| !tracker.record_update_validity(false, None)));
+
+        // The resulting error makes the connection loop reconnect rather than exit
+        assert!(is_disconnection_error(&consecutive_invalid_error(3), true));
+        assert!(is_disconnection_error(&consecutive_invalid_error(3), false));
+    }
+
+    // Test distance accumulation skips the first fix and optionally filters jitter
+    #[test]
+    fn test_record_position() {
+        let mut tracker = UpdateTracker::new();
+        assert_eq!(tracker.record_position(51.5074, -0.1278, 0.0, None), None);
+        let distance = tracker.record_position(48.8566, 2.3522, 0.0, None).unwrap();
+        assert!((distance - 343_557.0).abs() < 100.0);
+
+        // ~5.6m steps north with a 10m threshold: the first is jitter, the second counts
+        let mut tracker = UpdateTracker::new();
+        tracker.record_position(35.0, 139.0, 10.0, None);
+        assert_eq!(tracker.record_position(35.00005, 139.0, 10.0, None), None);
+        let distance = tracker.record_position(35.0001, 139.0, 10.0, None).unwrap();
+        assert!((distance - 11.1).abs() < 0.1, "{}", distance);
+        assert_eq!(tracker.distance_traveled, distance);
+    }
+
+    // Test movement between scrapes is reported only for scrapes that saw the position change
+    #[test]
+    fn test_moved_since_last_scrape() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let scrapes = ScrapeTracker::tracking_movement();
+        let scrape = |position| {
+            metrics::with_local_recorder(&recorder, || scrapes.record_movement(position));
+            handle.render().contains("geoclue_moved_since_last_scrape 1")
+        };
+
+        // No fix yet, then the first fix, the same fix again, a move and an idle scrape
+        assert!(!scrape(None));
+        assert!(scrape(Some((52.52, 13.405))));
+        assert!(!scrape(Some((52.52, 13.405))));
+        assert!(scrape(Some((52.521, 13.405))));
+        assert!(!scrape(Some((52.521, 13.405))));
+
+        // Without the option the gauge is never exported
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || ScrapeTracker::new().record_movement(Some((52.52, 13.405))));
+        assert!(!recorder.handle().render().contains("geoclue_moved_since_last_scrape"));
+    }
+
+    // Test moves accumulate into the total while a jump over --max-jump-meters is left out
+    #[test]
+    fn test_distance_traveled_filters_jumps() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let mut tracker = UpdateTracker::new();
+        let max_jump = Some(1000.0);
+
+        // Three ~111m steps north, a spurious jump ~111km away and back, then one more step
+        let fixes = [(35.0, 139.0), (35.001, 139.0), (35.002, 139.0), (35.003, 139.0), (36.003, 139.0), (35.003, 139.0), (35.004, 139.0)];
+        let counted: Vec<bool> = metrics::with_local_recorder(&recorder, || {
+            fixes.iter().map(|(lat, lon)| tracker.record_position(*lat, *lon, 0.0, max_jump).is_some()).collect()
+        });
+        assert_eq!(counted, vec![false, true, true, true, false, false, true]);
+        assert!((tracker.distance_traveled - 4.0 * 111.195).abs() < 0.5, "{}", tracker.distance_traveled);
+        assert!(recorder.handle().render().contains("geoclue_distance_jumps_filtered_total 2"));
+
+        // Without a limit the same jump is counted
+        let mut tracker = UpdateTracker::new();
+        tracker.record_position(35.003, 139.0, 0.0, None);
+        assert!(tracker.record_position(36.003, 139.0, 0.0, None).is_some());
+
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--max-jump-meters", "0"]).is_err());
+    }
+
+    // Test the reported move between the previous and the new Location object
+    #[tokio::test]
+    async fn test_reported_move_meters() {
+        // One degree of latitude is ~111.2km on the mean sphere
+        assert!((haversine(0.0, 0.0, 1.0, 0.0) - 111_195.0).abs() < 1.0);
+        assert_eq!(haversine(35.5, 139.7, 35.5, 139.7), 0.0);
+
+        // Mocked client: the previous Location object lies 0.001 degrees further north
+        let locations = std::collections::HashMap::from([
+            ("/org/freedesktop/GeoClue2/Client/1/Location/1".to_string(), (35.681, 139.767)),
+        ]);
+        let read_location = |path: zvariant::ObjectPath<'static>| {
+            let location = locations.get(path.as_str()).copied();
+            async move { location.ok_or_else(|| anyhow::anyhow!("Unknown object")) }
+        };
+
+        let meters = reported_move_meters("/org/freedesktop/GeoClue2/Client/1/Location/1", (35.680, 139.767), read_location).await;
+        assert!((meters.unwrap() - 111.2).abs() < 0.1, "{:?}", meters);
+
+        // First update (root old path) and vanished objects yield no distance
+        assert_eq!(reported_move_meters("/", (35.680, 139.767), read_location).await, None);
+        assert_eq!(reported_move_meters("/org/freedesktop/GeoClue2/Client/1/Location/9", (35.680, 139.767), read_location).await, None);
+    }
+
+    // Test Location path validation
+    #[test]
+    fn test_is_valid_location_path() {
+        assert!(is_valid_location_path("/org/freedesktop/GeoClue2/Client/1/Location/4"));
+
+        assert!(!is_valid_location_path(""));
+        assert!(!is_valid_location_path("/"));
+        assert!(!is_valid_location_path("org/freedesktop/GeoClue2"));
+        assert!(!is_valid_location_path("/org/freedesktop//GeoClue2"));
+        assert!(!is_valid_location_path("/org/free-desktop/GeoClue2"));
+        assert!(!is_valid_location_path(&format!("/{}", "a".repeat(MAX_LOCATION_PATH_LEN))));
+    }
+
+    // Test that partial property reads are kept and counted
+    #[test]
+    fn test_reading_from_properties() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        let reading = metrics::with_local_recorder(&recorder, || {
+            reading_from_properties(vec![
+                Ok(35.5),
+                Ok(139.7),
+                Ok(12.0),
+                Err(zbus::Error::Failure("Altitude unavailable".to_string())),
+                Ok(0.0),
+                Err(zbus::Error::Failure("Heading unavailable".to_string())),
+            ], None, "/org/freedesktop/GeoClue2/Client/1/Location/1")
+        }).unwrap();
+
+        assert!(handle.render().contains("geoclue_properties_read_last_update 4"), "{}", handle.render());
+        assert_eq!(reading.latitude, 35.5);
+        assert_eq!(reading.altitude, -1.0);
+        assert_eq!(reading.heading, -1.0);
+
+        // An update with no readable property is an error
+        let failures = (0..6).map(|_| Err(zbus::Error::Failure("gone".to_string()))).collect();
+        let result = metrics::with_local_recorder(&recorder, || reading_from_properties(failures, None, "/org/freedesktop/GeoClue2/Client/1/Location/2"));
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "fetching Latitude from /org/freedesktop/GeoClue2/Client/1/Location/2");
+        assert!(format!("{:#}", error).ends_with(": gone"), "{:#}", error);
+        assert!(handle.render().contains("geoclue_properties_read_last_update 0"));
+    }
+
+    // Test the shutdown webhook payload and that an unresponsive webhook cannot block exit
+    #[tokio::test]
+    async fn test_shutdown_webhook() {
+        let reading = LocationReading {
+            latitude: 35.5,
+            longitude: 139.7,
+            accuracy: 12.0,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites: None,
+        };
+
+        let payload: serde_json::Value = serde_json::from_str(&shutdown_webhook_payload(
+            "SIGTERM", std::time::Duration::from_secs(3600), Some(&reading)
+        )).unwrap();
+        assert_eq!(payload["reason"], "SIGTERM");
+        assert_eq!(payload["uptime_seconds"], 3600);
+        assert_eq!(payload["last_position"]["latitude"], 35.5);
+        assert_eq!(payload["last_position"]["accuracy"], 12.0);
+
+        let payload: serde_json::Value = serde_json::from_str(&shutdown_webhook_payload(
+            "SIGINT", std::time::Duration::from_secs(1), None
+        )).unwrap();
+        assert!(payload["last_position"].is_null());
+
+        // Accept the connection but never answer
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _connection = listener.accept().await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+        });
+
+        let url = format!("http://{}/shutdown", address);
+        let delivery = send_shutdown_webhook(&url, "{}".to_string(), std::time::Duration::from_millis(200));
+        assert!(tokio::time::timeout(std::time::Duration::from_secs(5), delivery).await.is_ok());
+    }
+
+    // Test pushing the rendered metrics to a Pushgateway, and that a failed push is an error
+    #[tokio::test]
+    async fn test_push_metrics() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        assert_eq!(push_gateway_url("http://pushgateway:9091/"), "http://pushgateway:9091/metrics/job/geoclue_exporter");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).ends_with("geoclue_latitude 52.5\n") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                assert!(read > 0, "connection closed early");
+                request.extend_from_slice(&buffer[..read]);
+            }
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let client = reqwest::Client::new();
+        let url = push_gateway_url(&format!("http://{}", address));
+        push_metrics(&client, &url, "# TYPE geoclue_latitude gauge\ngeoclue_latitude 52.5\n".to_string()).await.unwrap();
+
+        let request = received.await.unwrap();
+        assert!(request.starts_with("POST /metrics/job/geoclue_exporter HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains("content-type: text/plain; version=0.0.4\r\n"), "{}", request);
+
+        // Nothing listening: the push fails instead of panicking
+        assert!(push_metrics(&client, "http://127.0.0.1:9/metrics/job/geoclue_exporter", String::new()).await.is_err());
+    }
+
+    // Test the IPv4 fallback decision after an IPv6 bind failure
+    #[test]
+    fn test_ipv6_fallback_address() {
+        use std::io::{Error, ErrorKind};
+
+        let unavailable = Error::from_raw_os_error(libc::EAFNOSUPPORT);
+        let not_available = Error::from(ErrorKind::AddrNotAvailable);
+        let in_use = Error::from(ErrorKind::AddrInUse);
+
+        let any: SocketAddr = "[::]:9090".parse().unwrap();
+        let loopback: SocketAddr = "[::1]:9090".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:192.168.1.10]:9090".parse().unwrap();
+        let global: SocketAddr = "[2001:db8::1]:9090".parse().unwrap();
+        let ipv4: SocketAddr = "127.0.0.1:9090".parse().unwrap();
+
+        assert_eq!(ipv6_fallback_address(any, &unavailable, true), Some("0.0.0.0:9090".parse().unwrap()));
+        assert_eq!(ipv6_fallback_address(loopback, &not_available, true), Some("127.0.0.1:9090".parse().unwrap()));
+        assert_eq!(ipv6_fallback_address(mapped, &unavailable, true), Some("192.168.1.10:9090".parse().unwrap()));
+
+        // No IPv4 equivalent, fallback disabled, unrelated errors or IPv4 input: no fallback
+        assert_eq!(ipv6_fallback_address(global, &unavailable, true), None);
+        assert_eq!(ipv6_fallback_address(loopback, &unavailable, false), None);
+        assert_eq!(ipv6_fallback_address(loopback, &in_use, true), None);
+        assert_eq!(ipv6_fallback_address(ipv4, &unavailable, true), None);
+    }
+
+    // Test that an SSE subscriber receives an event after a simulated update
+    #[tokio::test]
+    async fn test_sse_subscriber_receives_update() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let (events, _) = broadcast::channel(SSE_CHANNEL_CAPACITY);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(HttpListener::Tcp(listener), Arc::new(HttpState {
+            events: Some(events.clone()),
+            ..test_http_state(recorder.handle())
+        })));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut reader = tokio::io::BufReader::new(stream);
+
+        // Wait for the initial keep-alive so we know the subscription is active
+        let mut line = String::new();
+        while line != ": keep-alive\n" {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            assert!(!line.is_empty(), "connection closed before keep-alive");
+        }
+
+        events.send(location_event_json(&[("latitude", 35.5), ("longitude", 139.75)], 1700000000)).unwrap();
 
-        // Update metrics, but only if they are valid values
-        set_gauge_if_valid("latitude", lat);
-        set_gauge_if_valid("longitude", lon);
-        set_gauge_if_valid("accuracy", acc);
-        set_gauge_if_valid("altitude", alt);
-        set_gauge_if_valid("speed", spd);
-        set_gauge_if_valid("heading", head);
+        while !line.starts_with("data: ") {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            assert!(!line.is_empty(), "connection closed before event");
+        }
+
+        let event: serde_json::Value = serde_json::from_str(line.trim_start_matches("data: ").trim()).unwrap();
+        assert_eq!(event["latitude"], 35.5);
+        assert_eq!(event["longitude"], 139.75);
+        assert_eq!(event["timestamp"], 1700000000);
     }
 
-    // This indicates the stream has ended (likely due to disconnection)
-    Err(anyhow::anyhow!("Location update stream ended"))
-}
+    // Test updates are counted under the provider their accuracy or satellites point to
+    #[test]
+    fn test_record_update_source() {
+        let reading = |accuracy: f64, satellites: Option<u32>| LocationReading {
+            latitude: 52.52,
+            longitude: 13.405,
+            accuracy,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites,
+        };
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse command line arguments
-    let args = Args::parse();
-    
-    // If --version-info flag is provided, display detailed version info and exit
-    if args.version_info {
-        println!("{}", get_version_string());
-        std::process::exit(0);
-    }
-    
-    // Set global log level
-    // Safety: This is safe because we only set it once at startup
-    unsafe {
-        LOG_LEVEL = args.log_level;
+        assert_eq!(detect_update_source(&reading(5.0, None)), "gps");
+        assert_eq!(detect_update_source(&reading(800.0, Some(7))), "gps");
+        assert_eq!(detect_update_source(&reading(80.0, None)), "network");
+        assert_eq!(detect_update_source(&reading(80.0, Some(0))), "network");
+        assert_eq!(detect_update_source(&reading(2500.0, None)), "modem");
+        assert_eq!(detect_update_source(&reading(25_000.0, None)), "network");
+        assert_eq!(detect_update_source(&reading(-1.0, None)), "unknown");
+        assert_eq!(detect_update_source(&reading(f64::NAN, None)), "unknown");
+
+        let recorder = PrometheusBuilder::new().build_recorder();
+        metrics::with_local_recorder(&recorder, || {
+            for accuracy in [4.0, 12.0, 150.0, 3000.0, -1.0] {
+                record_update_source(&reading(accuracy, None));
+            }
+        });
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("geoclue_updates_by_source_total{source=\"gps\"} 2"), "{}", rendered);
+        assert!(rendered.contains("geoclue_updates_by_source_total{source=\"network\"} 1"), "{}", rendered);
+        assert!(rendered.contains("geoclue_updates_by_source_total{source=\"modem\"} 1"), "{}", rendered);
+        assert!(rendered.contains("geoclue_updates_by_source_total{source=\"unknown\"} 1"), "{}", rendered);
     }
-    
-    // Set up metrics with the provided bind address and port
-    match setup_metrics(&args.bind_address, args.metrics_port) {
-        Ok(_) => {
-            log("INFO", &format!("{} metrics endpoint started", PKG_NAME), &[
-                ("endpoint", format!("http://{}:{}/metrics", args.bind_address, args.metrics_port)),
-                ("version", PKG_VERSION.to_string()),
-                ("build_hash", GIT_HASH.to_string()),
-                ("log_level", format!("{:?}", args.log_level)),
-            ]);
-        },
-        Err(e) => {
-            log("ERROR", &format!("Failed to start {} metrics endpoint", PKG_NAME), &[
-                ("error", format!("{}", e)),
-                ("bind_address", args.bind_address.clone()),
-                ("port", args.metrics_port.to_string()),
-            ]);
-            return Err(e);
-        }
+
+    // Test the fix-quality gate with and without satellite data
+    #[test]
+    fn test_passes_fix_quality_gate() {
+        // Gate disabled
+        assert!(passes_fix_quality_gate(None, Some(2)));
+        assert!(passes_fix_quality_gate(None, None));
+
+        // Gate enabled and satellite count available
+        assert!(passes_fix_quality_gate(Some(4), Some(4)));
+        assert!(passes_fix_quality_gate(Some(4), Some(9)));
+        assert!(!passes_fix_quality_gate(Some(4), Some(3)));
+
+        // Gate enabled but the backend does not expose a count: no-op
+        assert!(passes_fix_quality_gate(Some(4), None));
     }
 
-    log("DEBUG", "Command line arguments", &[
-        ("bind_address", args.bind_address.to_string()),
-        ("distance_threshold", args.distance_threshold.to_string()),
-        ("time_threshold", args.time_threshold.to_string()),
-        ("accuracy_level", format!("{:?}", args.accuracy_level)),
-        ("metrics_port", args.metrics_port.to_string()),
-    ]);
+    // Test that a failing render yields the fallback exposition
+    #[test]
+    fn test_render_with_fallback() {
+        assert_eq!(render_with_fallback(|| "geoclue_latitude 35.5\n".to_string()), "geoclue_latitude 35.5\n");
 
-    // Initialize update tracker
-    let tracker = Arc::new(Mutex::new(UpdateTracker {
-        received_updates: 0,
-    }));
+        let body = render_with_fallback(|| panic!("simulated render failure"));
+        assert_eq!(body, FALLBACK_METRICS);
+        assert!(body.lines().any(|line| line == "up 1"));
+        assert!(body.starts_with("# "));
+    }
 
-    // Periodically collect process metrics
-    let _metrics_handle = tokio::spawn(async {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
-        loop {
-            interval.tick().await;
-            collect();
-        }
-    });
+    // Test that the best-of selection prefers the most accurate reading
+    #[test]
+    fn test_select_best_reading() {
+        let reading = |accuracy: f64| LocationReading {
+            latitude: 35.0,
+            longitude: 139.0,
+            accuracy,
+            altitude: -1.0,
+            speed: -1.0,
+            heading: -1.0,
+            satellites: None,
+        };
 
-    // Shared variables for shutdown handling
-    let shutdown_flag = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let shutdown_flag_clone = shutdown_flag.clone();
+        assert_eq!(select_best_reading(&[Some(reading(2500.0)), Some(reading(12.0))]), Some(1));
+        assert_eq!(select_best_reading(&[Some(reading(8.0)), Some(reading(12.0))]), Some(0));
 
-    // Handle graceful shutdown
-    tokio::spawn(async move {
-        if let Err(e) = ctrl_c().await {
-            log("ERROR", "Failed to listen for ctrl+c signal", &[("error", format!("{}", e))]);
-            return;
-        }
-        
-        log("INFO", "Shutdown signal received", &[]);
-        shutdown_flag_clone.store(true, std::sync::atomic::Ordering::Relaxed);
-    });
+        // Missing readings and unknown accuracy never win
+        assert_eq!(select_best_reading(&[None, Some(reading(500.0))]), Some(1));
+        assert_eq!(select_best_reading(&[Some(reading(-1.0)), Some(reading(500.0))]), Some(1));
+        assert_eq!(select_best_reading(&[Some(reading(f64::NAN)), None]), None);
+        assert_eq!(select_best_reading(&[]), None);
+    }
 
-    // Main reconnection loop
-    let mut retry_count = 0;
-    let max_retry_delay = 60; // Maximum delay between retries in seconds
-    let mut has_connected_before = false;
-    
-    loop {
-        // Check if shutdown was requested
-        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
-            log("INFO", "Shutdown requested, exiting", &[]);
-            break;
-        }
+    // Test that DBus names default to GeoClue2 and follow the command line
+    #[test]
+    fn test_geoclue_names() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter"]).unwrap();
+        let names = GeoClueNames::from_args(&args);
+        assert_eq!(names.service, "org.freedesktop.GeoClue2");
+        assert_eq!(names.manager_path, "/org/freedesktop/GeoClue2/Manager");
+        assert_eq!(names.manager_interface(), "org.freedesktop.GeoClue2.Manager");
+        assert_eq!(names.client_interface(), "org.freedesktop.GeoClue2.Client");
+        assert_eq!(names.location_interface(), "org.freedesktop.GeoClue2.Location");
 
-        // Attempt to connect to GeoClue2
-        match setup_geoclue_connection(&args).await {
-            Ok(geoclue_conn) => {
-                log("INFO", "Successfully connected to GeoClue2", &[]);
-                retry_count = 0; // Reset retry count on successful connection
-                has_connected_before = true; // Mark that we've connected successfully
-                
-                // Set up shutdown handler for this connection
-                let shutdown_connection = Arc::new(Connection::system().await?);
-                let shutdown_client_path = geoclue_conn.client_path.clone();
-                let shutdown_flag_monitor = shutdown_flag.clone();
-                
-                let shutdown_handle = tokio::spawn(async move {
-                    // Wait for shutdown signal
-                    while !shutdown_flag_monitor.load(std::sync::atomic::Ordering::Relaxed) {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    }
-                    
-                    log("INFO", "Stopping GeoClue2 client for shutdown", &[]);
-                    
-                    // Create a new client proxy specifically for shutdown
-                    match zbus::Proxy::new(
-                        &shutdown_connection,
-                        "org.freedesktop.GeoClue2",
-                        &shutdown_client_path,
-                        "org.freedesktop.GeoClue2.Client"
-                    ).await {
-                        Ok(shutdown_client) => {
-                            // Call Stop on the client for clean shutdown
-                            if let Err(e) = shutdown_client.call::<_, _, ()>("Stop", &()).await {
-                                log("ERROR", "Failed to stop GeoClue2 client", &[("error", format!("{}", e))]);
-                            } else {
-                                log("INFO", "GeoClue2 client stopped successfully", &[]);
-                            }
-                        },
-                        Err(e) => {
-                            log("ERROR", "Failed to create shutdown client proxy", &[("error", format!("{}", e))]);
-                        }
-                    }
-                    
-                    // Set the "up" metric to 0 to indicate the exporter is shutting down
-                    metrics::gauge!("up").set(0.0);
-                });
+        let args = Args::try_parse_from([
+            "geoclue-prometheus-exporter",
+            "--service-name", "org.example.GeoShim",
+            "--manager-path", "/org/example/GeoShim/Manager",
+            "--interface-prefix", "org.example.GeoShim",
+        ]).unwrap();
+        let names = GeoClueNames::from_args(&args);
+        assert_eq!(names.service, "org.example.GeoShim");
+        assert_eq!(names.manager_path, "/org/example/GeoShim/Manager");
+        assert_eq!(names.manager_interface(), "org.example.GeoShim.Manager");
+        assert_eq!(names.client_interface(), "org.example.GeoShim.Client");
+        assert_eq!(names.location_interface(), "org.example.GeoShim.Location");
+    }
 
-                // Monitor location updates
-                let monitoring_result = monitor_location_updates(&geoclue_conn, tracker.clone()).await;
-                
-                // Cancel shutdown handler if we're not shutting down
-                if !shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
-                    shutdown_handle.abort();
-                }
-                
-                // Handle monitoring result
-                match monitoring_result {
-                    Ok(_) => {
-                        // This shouldn't happen normally
-                        log("INFO", "Location monitoring completed normally", &[]);
-                        break;
-                    },
-                    Err(e) => {
-                        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
-                            log("INFO", "Location monitoring stopped due to shutdown", &[]);
-                            // Wait for shutdown handler to complete
-                            let _ = shutdown_handle.await;
-                            break;
-                        } else if is_disconnection_error(&e, has_connected_before) {
-                            log("WARN", "GeoClue2 connection lost, will attempt to reconnect", &[
-                                ("error", format!("{}", e)),
-                                ("retry_count", retry_count.to_string()),
-                            ]);
-                            // Continue to retry logic
-                        } else {
-                            log("ERROR", "Non-recoverable error in location monitoring", &[
-                                ("error", format!("{}", e)),
-                            ]);
-                            return Err(e);
-                        }
-                    }
-                }
-            },
-            Err(e) => {
-                log("WARN", "Failed to connect to GeoClue2", &[
-                    ("error", format!("{}", e)),
-                    ("retry_count", retry_count.to_string()),
-                ]);
-                
-                if is_disconnection_error(&e, has_connected_before) {
-                    log("INFO", "Error identified as disconnection, will retry", &[
-                        ("error", format!("{}", e)),
-                    ]);
-                } else {
-                    log("ERROR", "Non-recoverable error connecting to GeoClue2", &[
-                        ("error", format!("{}", e)),
-                    ]);
-                    return Err(e);
-                }
-            }
-        }
+    // Test the desktop id defaults to the package name and must not be empty
+    #[test]
+    fn test_desktop_id() {
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter"]).unwrap();
+        assert_eq!(args.desktop_id, PKG_NAME);
 
-        // Check if shutdown was requested before sleeping
-        if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
-            break;
-        }
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--desktop-id", "org.example.Exporter"]).unwrap();
+        assert_eq!(args.desktop_id, "org.example.Exporter");
 
-        // Calculate exponential backoff delay
-        retry_count += 1;
-        let delay = std::cmp::min(2_u64.pow(std::cmp::min(retry_count, 6)), max_retry_delay);
-        
-        log("INFO", "Waiting before reconnection attempt", &[
-            ("delay_seconds", delay.to_string()),
-            ("retry_count", retry_count.to_string()),
-        ]);
-        
-        tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--desktop-id", ""]).is_err());
+        assert!(Args::try_parse_from(["geoclue-prometheus-exporter", "--desktop-id", "  "]).is_err());
     }
 
-    log("INFO", "Exporter shutting down", &[]);
-    Ok(())
-}
+    // Test evaluation of observed accuracy against the requested level
+    #[test]
+    fn test_accuracy_expectation_met() {
+        // Not enough samples yet
+        assert_eq!(accuracy_expectation_met(AccuracyLevel::Exact, &[5000.0, 5000.0]), None);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::{Arc, Mutex};
-    
-    // Test the log level logic functions
+        // Consistently coarse readings do not meet an Exact request
+        let coarse = [2500.0, 3000.0, 5000.0, 1500.0, 20000.0];
+        assert_eq!(accuracy_expectation_met(AccuracyLevel::Exact, &coarse), Some(false));
+
+        // A single precise reading shows the hardware can deliver
+        let mixed = [2500.0, 3000.0, 8.0, 1500.0, 20000.0];
+        assert_eq!(accuracy_expectation_met(AccuracyLevel::Exact, &mixed), Some(true));
+
+        // The same coarse readings are fine for City, and Country has no bound
+        assert_eq!(accuracy_expectation_met(AccuracyLevel::City, &coarse), Some(true));
+        assert_eq!(accuracy_expectation_met(AccuracyLevel::Country, &[]), Some(true));
+    }
+
+    // Test WGS84 to Web Mercator projection against reference values
     #[test]
-    fn test_should_log() {
-        unsafe {
-            // Test Debug level
-            LOG_LEVEL = LogLevel::Debug;
-            assert!(should_log(LogLevel::Debug));
-            assert!(should_log(LogLevel::Info));
-            assert!(should_log(LogLevel::Warn));
-            assert!(should_log(LogLevel::Error));
-            
-            // Test Info level
-            LOG_LEVEL = LogLevel::Info;
-            assert!(!should_log(LogLevel::Debug));
-            assert!(should_log(LogLevel::Info));
-            assert!(should_log(LogLevel::Warn));
-            assert!(should_log(LogLevel::Error));
-            
-            // Test Warn level
-            LOG_LEVEL = LogLevel::Warn;
-            assert!(!should_log(LogLevel::Debug));
-            assert!(!should_log(LogLevel::Info));
-            assert!(should_log(LogLevel::Warn));
-            assert!(should_log(LogLevel::Error));
-            
-            // Test Error level
-            LOG_LEVEL = LogLevel::Error;
-            assert!(!should_log(LogLevel::Debug));
-            assert!(!should_log(LogLevel::Info));
-            assert!(!should_log(LogLevel::Warn));
-            assert!(should_log(LogLevel::Error));
+    fn test_project_wgs84() {
+        let cases = [
+            ((0.0, 0.0), (0.0, 0.0)),
+            ((0.0, 180.0), (20037508.342789244, 0.0)),
+            ((51.5074, -0.1278), (-14226.630923380362, 6711542.475587636)),
+            ((-33.8688, 151.2093), (16832542.27920734, -4011198.6473075734)),
+        ];
+
+        for ((lat, lon), (expected_x, expected_y)) in cases {
+            let (x, y) = project_wgs84(3857, lat, lon).unwrap();
+            assert!((x - expected_x).abs() < 1e-3, "x for ({}, {}) was {}", lat, lon, x);
+            assert!((y - expected_y).abs() < 1e-3, "y for ({}, {}) was {}", lat, lon, y);
         }
+
+        // Only supported codes are accepted
+        assert_eq!(parse_epsg("3857"), Ok(3857));
+        assert_eq!(parse_epsg("EPSG:3857"), Ok(3857));
+        assert!(parse_epsg("27700").unwrap_err().contains("unsupported EPSG code"));
+        assert!(parse_epsg("mercator").is_err());
+        assert_eq!(project_wgs84(27700, 0.0, 0.0), None);
     }
-    
-    // Test the set_gauge_if_valid function
+
+    // Test port 0 is rejected unless ephemeral ports are explicitly allowed
     #[test]
-    fn test_set_gauge_if_valid() {
-        // Test with valid values
-        assert!(set_gauge_if_valid("latitude", 35.123));
-        assert!(set_gauge_if_valid("longitude", 135.456));
-        assert!(set_gauge_if_valid("accuracy", 10.5));
-        assert!(set_gauge_if_valid("altitude", 123.4));
-        assert!(set_gauge_if_valid("speed", 5.2));
-        assert!(set_gauge_if_valid("heading", 270.0));
-        
-        // Test with invalid values (should return false)
-        assert!(!set_gauge_if_valid("latitude", -1.0));
-        assert!(!set_gauge_if_valid("longitude", -1.7e308));
-        
-        // Test with unknown metric name (should return false)
-        assert!(!set_gauge_if_valid("unknown_metric", 123.0));
+    fn test_validate_ephemeral_port() {
+        let parse = |flags: &[&str]| {
+            let matches = Args::command().try_get_matches_from(
+                ["geoclue-prometheus-exporter"].iter().chain(flags)
+            ).unwrap();
+            args_from_matches(&matches)
+        };
+
+        let error = parse(&["--metrics-port", "0"]).unwrap_err();
+        assert!(format!("{}", error).contains("--allow-ephemeral-port"), "{}", error);
+        assert!(parse(&["--metrics-port", "0", "--allow-ephemeral-port"]).is_ok());
+        assert!(parse(&["--metrics-port", "0", "--bind-address", "unix:/run/exporter.sock"]).is_ok());
+        assert!(parse(&["--metrics-port", "9100"]).is_ok());
     }
-    
-    // Test the get_version_string function
+
+    // Test an accuracy level can only be given to one client
     #[test]
-    fn test_version_string_format() {
-        let version_str = get_version_string();
-        
-        // Verify it contains the expected components
-        assert!(version_str.contains(PKG_NAME));
-        assert!(version_str.contains(PKG_VERSION));
-        assert!(version_str.contains("Build:"));
+    fn test_validate_duplicate_accuracy_levels() {
+        let parse = |flags: &[&str]| {
+            let matches = Args::command().try_get_matches_from(
+                ["geoclue-prometheus-exporter"].iter().chain(flags)
+            ).unwrap();
+            args_from_matches(&matches)
+        };
+
+        assert!(parse(&["--extra-accuracy-level", "city", "--extra-accuracy-level", "country"]).is_ok());
+        let error = parse(&["--extra-accuracy-level", "city", "--extra-accuracy-level", "city"]).unwrap_err();
+        assert!(format!("{}", error).contains("city"), "{}", error);
+        assert!(parse(&["--accuracy-level", "8", "--extra-accuracy-level", "exact"]).is_err());
     }
-    
-    // Test AccuracyLevel conversion
+
+    // Test that config file values fill in defaults but never override explicit flags
     #[test]
-    fn test_accuracy_level_conversion() {
-        // Test all conversions
-        assert_eq!(AccuracyLevel::None as u32, 0);
-        assert_eq!(AccuracyLevel::Country as u32, 1);
-        assert_eq!(AccuracyLevel::City as u32, 4);
-        assert_eq!(AccuracyLevel::Neighborhood as u32, 5);
-        assert_eq!(AccuracyLevel::Street as u32, 6);
-        assert_eq!(AccuracyLevel::Exact as u32, 8);
-        
-        // Test from conversions
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::None), AccuracyLevel::None));
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Country), AccuracyLevel::Country));
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::City), AccuracyLevel::City));
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Neighborhood), AccuracyLevel::Neighborhood));
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Street), AccuracyLevel::Street));
-        assert!(matches!(AccuracyLevel::from(AccuracyLevelArg::Exact), AccuracyLevel::Exact));
+    fn test_apply_file_config() {
+        let config: FileConfig = toml::from_str(r#"
+            distance_threshold = 50
+            time_threshold = 120
+            accuracy_level = "city"
+            metrics_port = 9100
+            bind_address = "0.0.0.0"
+            log_level = "debug"
+        "#).unwrap();
+
+        let matches = Args::command().try_get_matches_from([
+            "geoclue-prometheus-exporter",
+            "--metrics-port", "9200",
+            "--log-level", "warn",
+        ]).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        apply_file_config(&mut args, config, &matches);
+
+        // Values only present in the file are taken from the file
+        assert_eq!(args.distance_threshold, 50);
+        assert_eq!(args.time_threshold, 120);
+        assert_eq!(args.accuracy_level, AccuracyLevelArg::City);
+        assert_eq!(args.bind_address, "0.0.0.0");
+
+        // Explicit command line flags win over the file
+        assert_eq!(args.metrics_port, 9200);
+        assert_eq!(args.log_level, LogLevel::Warn);
+
+        // Unknown keys and bad values are rejected
+        assert!(toml::from_str::<FileConfig>("metrics_prot = 1").is_err());
+        assert!(toml::from_str::<FileConfig>("accuracy_level = \"precise\"").is_err());
     }
-    
-    // Test UpdateTracker functionality
+
+    // Test that the clock offset is applied to emitted timestamps
     #[test]
-    fn test_update_tracker() {
-        let tracker = Arc::new(Mutex::new(UpdateTracker {
-            received_updates: 0,
-        }));
-        
-        // Simulate receiving updates
-        {
-            let mut tracker_guard = tracker.lock().unwrap();
-            tracker_guard.received_updates += 1;
-            assert_eq!(tracker_guard.received_updates, 1);
-        }
-        
-        // Simulate another update
-        {
-            let mut tracker_guard = tracker.lock().unwrap();
-            tracker_guard.received_updates += 1;
-            assert_eq!(tracker_guard.received_updates, 2);
-        }
+    fn test_corrected_timestamp() {
+        let now: DateTime<Utc> = "2024-03-01T07:15:00Z".parse().unwrap();
+        assert_eq!(corrected_timestamp(now, 0.0), 1709277300.0);
+        assert_eq!(corrected_timestamp(now, 90.5), 1709277390.5);
+        assert_eq!(corrected_timestamp(now, -3600.0), 1709273700.0);
+
+        // Negative offsets are accepted on the command line
+        let args = Args::try_parse_from(["geoclue-prometheus-exporter", "--clock-offset-seconds", "-3600"]).unwrap();
+        assert_eq!(args.clock_offset_seconds, -3600.0);
     }
-    
+
     // Test disconnection error detection
     #[test]
     fn test_is_disconnection_error() {
@@ -798,6 +7505,34 @@ mod tests {
         let error = anyhow::anyhow!("Permission denied");
         assert!(!is_disconnection_error(&error, false), "Should be permanent");
         assert!(!is_disconnection_error(&error, true), "Should be permanent");
+
+        // The root cause is still classified once context has been added on top of it
+        let error = anyhow::anyhow!("Permission denied").context("starting client /org/freedesktop/GeoClue2/Client/1");
+        assert!(!is_disconnection_error(&error, true), "Should be permanent through context");
+
+        let error = anyhow::anyhow!("Service not found: org.freedesktop.GeoClue2").context("calling GetClient on /org/freedesktop/GeoClue2/Manager");
+        assert!(!is_disconnection_error(&error, false), "Should be permanent through context");
+        assert!(is_disconnection_error(&error, true), "Should be retryable through context");
+    }
+
+    // Test a missing GeoClue2 service is recognised by its D-Bus error name, through context
+    #[test]
+    fn test_is_daemon_missing_error() {
+        let reply = zbus::Message::method_call("/org/freedesktop/GeoClue2/Manager", "GetClient").unwrap().build(&()).unwrap();
+        let method_error = |name: &str| zbus::Error::MethodError(name.try_into().unwrap(), None, reply.clone());
+
+        let error = anyhow::Error::new(method_error("org.freedesktop.DBus.Error.ServiceUnknown"))
+            .context("calling GetClient on /org/freedesktop/GeoClue2/Manager");
+        assert!(is_daemon_missing_error(&error));
+        assert!(is_daemon_missing_error(&anyhow::Error::new(method_error("org.freedesktop.DBus.Error.NameHasNoOwner"))));
+        assert!(!is_daemon_missing_error(&anyhow::Error::new(method_error("org.freedesktop.DBus.Error.AccessDenied"))));
+
+        let fdo = zbus::Error::FDO(Box::new(zbus::fdo::Error::ServiceUnknown("org.freedesktop.GeoClue2".to_string())));
+        assert!(is_daemon_missing_error(&anyhow::Error::new(fdo)));
+        assert!(is_daemon_missing_error(&anyhow::Error::new(zbus::fdo::Error::NameHasNoOwner("org.freedesktop.GeoClue2".to_string()))));
+
+        // Failing to reach the bus itself is a different problem
+        assert!(!is_daemon_missing_error(&anyhow::anyhow!("I/O error: No such file or directory")));
     }
 
     // Test permanent error detection
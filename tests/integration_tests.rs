@@ -5,7 +5,10 @@
 
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
-use std::process::Command;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
 #[test]
 fn test_version_flag() -> Result<(), Box<dyn std::error::Error>> {
@@ -32,6 +35,21 @@ fn test_version_info_flag() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_version_json_flag() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("geoclue-prometheus-exporter")?;
+    
+    cmd.arg("--version-json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    
+    let version: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(version["name"], env!("CARGO_PKG_NAME"));
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(version["git_hash"].is_string());
+    
+    Ok(())
+}
+
 #[test]
 fn test_invalid_bind_address() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("geoclue-prometheus-exporter")?;
@@ -61,19 +79,98 @@ fn test_help_flag() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
-fn test_disconnection_error_on_unavailable_service() -> Result<(), Box<dyn std::error::Error>> {
+fn test_exit_without_system_bus() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("geoclue-prometheus-exporter")?;
     
-    // Test that the application exits when GeoClue2 service is not available
-    // This simulates the typical case where the service would need to handle reconnection
+    // Without a system bus there is nothing to wait for once --connect-timeout has passed
     cmd.arg("--log-level").arg("error");
+    cmd.arg("--connect-timeout").arg("0");
+    cmd.env("DBUS_SYSTEM_BUS_ADDRESS", "unix:path=/nonexistent/geoclue-prometheus-exporter-test");
     cmd.assert()
         .failure()
-        .stderr(
-            predicate::str::contains("ServiceUnknown")
-                .or(predicate::str::contains("Service not found"))
-                .or(predicate::str::contains("No such file or directory"))
-        );
+        .stderr(predicate::str::contains("No such file or directory"));
+    
+    Ok(())
+}
+
+// Kills the wrapped process when a test ends, whether or not it passed
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Fetch the metrics page, or None while the server is not up yet
+fn scrape(port: u16) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}
+
+#[test]
+fn test_waits_for_missing_geoclue_service() -> Result<(), Box<dyn std::error::Error>> {
+    // A private bus stands in for a system bus without GeoClue2
+    let mut bus = match Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(bus) => KillOnDrop(bus),
+        Err(e) => {
+            eprintln!("skipping, dbus-daemon is not available: {}", e);
+            return Ok(());
+        }
+    };
+    let mut address = String::new();
+    BufReader::new(bus.0.stdout.take().unwrap()).read_line(&mut address)?;
+
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let _exporter = KillOnDrop(Command::cargo_bin("geoclue-prometheus-exporter")?
+        .args(["--log-level", "error", "--metrics-port", &port.to_string()])
+        .env("DBUS_SYSTEM_BUS_ADDRESS", address.trim())
+        .spawn()?);
+
+    // The exporter keeps retrying and reports the missing service instead of exiting
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let metrics = scrape(port).unwrap_or_default();
+        if metrics.contains("geoclue_daemon_present 0") {
+            assert!(metrics.contains("geoclue_connection_up 0"), "{}", metrics);
+            return Ok(());
+        }
+        assert!(Instant::now() < deadline, "no geoclue_daemon_present 0 in {}", metrics);
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[test]
+fn test_check_valid_configuration() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("geoclue-prometheus-exporter")?;
+    
+    // --check must succeed without a GeoClue2 daemon or DBus system bus
+    cmd.arg("--check").arg("--bind-address").arg("0.0.0.0").arg("--metrics-port").arg("9191");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("bind_address=0.0.0.0"))
+        .stdout(predicate::str::contains("metrics_port=9191"))
+        .stdout(predicate::str::contains("Configuration OK"));
+    
+    Ok(())
+}
+
+#[test]
+fn test_check_invalid_bind_address() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("geoclue-prometheus-exporter")?;
+    
+    cmd.arg("--check").arg("--bind-address").arg("not-an-address%");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse bind address"));
     
     Ok(())
 }